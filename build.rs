@@ -0,0 +1,11 @@
+//!
+//! Build script, generating the gRPC server and client code from the
+//! definitions in the proto directory (see crate::grpc).
+//!
+//! Uses the vendored `protoc`, so no protobuf compiler needs to be installed.
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_build::compile_protos("proto/na.proto")?;
+    Ok(())
+}
@@ -0,0 +1,9 @@
+-- Case-insensitive prefix filters on GET /users (`lower(column) LIKE 'prefix%'`)
+CREATE INDEX IF NOT EXISTS idx_users_email_lower ON users (lower(email) text_pattern_ops);
+CREATE INDEX IF NOT EXISTS idx_users_name_lower ON users (lower(name) text_pattern_ops);
+
+-- Creation datetime range filters on GET /users
+CREATE INDEX IF NOT EXISTS idx_users_created_at ON users (created_at);
+
+-- Role filter on GET /users (the primary key covers lookups by user only)
+CREATE INDEX IF NOT EXISTS idx_user_roles_role ON user_roles (role, user_id);
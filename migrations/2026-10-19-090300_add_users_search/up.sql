@@ -0,0 +1,15 @@
+-- Trigram similarity (fuzzy matching of misspelled names and emails)
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- Full-text search document of GET /users/search. Email is indexed both as a
+-- whole and split into its local part and domain labels, so searching by any
+-- of them matches. The column is not mapped in `src/schema.rs`, so plain
+-- `SELECT users.*` queries (diesel default selection) don't fetch it.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS search_vector tsvector
+    GENERATED ALWAYS AS (
+        to_tsvector('simple', name || ' ' || email || ' ' || translate(email, '@.-_+', '     '))
+    ) STORED;
+
+CREATE INDEX IF NOT EXISTS idx_users_search_vector ON users USING gin (search_vector);
+CREATE INDEX IF NOT EXISTS idx_users_name_trgm ON users USING gin (lower(name) gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_users_email_trgm ON users USING gin (lower(email) gin_trgm_ops);
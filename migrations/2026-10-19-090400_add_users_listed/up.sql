@@ -0,0 +1,2 @@
+-- Whether the user profile is shown to other (non-admin) users in lists and search results
+ALTER TABLE users ADD COLUMN IF NOT EXISTS listed BOOLEAN NOT NULL DEFAULT TRUE;
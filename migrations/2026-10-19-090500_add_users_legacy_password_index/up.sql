@@ -0,0 +1,5 @@
+-- Legacy (non-Argon2) password hashes, counted by the metrics endpoint; the
+-- index shrinks as the hashes are upgraded on login
+CREATE INDEX IF NOT EXISTS idx_users_legacy_password_hash
+  ON users (split_part(hashed_password, '$', 2))
+  WHERE hashed_password NOT LIKE '$argon2%';
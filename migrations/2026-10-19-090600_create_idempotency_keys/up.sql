@@ -0,0 +1,15 @@
+-- Responses of the requests made with the `Idempotency-Key` header, replayed
+-- on retries. The status is NULL while the request is in progress
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+  principal TEXT NOT NULL,
+  idempotency_key TEXT NOT NULL,
+  fingerprint TEXT NOT NULL,
+  status SMALLINT,
+  headers TEXT NOT NULL DEFAULT '[]',
+  body BYTEA NOT NULL DEFAULT '',
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  expires_at TIMESTAMP NOT NULL,
+  PRIMARY KEY (principal, idempotency_key)
+);
+
+CREATE INDEX IF NOT EXISTS idx_idempotency_keys_expires_at ON idempotency_keys (expires_at);
@@ -0,0 +1,6 @@
+-- End of the lease of the request in progress, after which a retry may take
+-- the key over (e.g. after a crash). NULL once the response is stored
+ALTER TABLE idempotency_keys ADD COLUMN IF NOT EXISTS locked_until TIMESTAMP;
+
+-- Requests in progress before the leases were introduced may be taken over
+UPDATE idempotency_keys SET locked_until = CURRENT_TIMESTAMP WHERE status IS NULL;
@@ -24,6 +24,9 @@ pub struct HttpConfig {
     pub listen_host: String,
     /// Port to bind the listener to.
     pub listen_port: u16,
+    /// Whether to serve the interactive API documentation (Swagger UI) at
+    /// /docs. The OpenAPI specification is served at /openapi.json anyway.
+    pub docs_ui: bool,
 }
 
 impl HttpConfig {
@@ -33,6 +36,39 @@ impl HttpConfig {
     }
 }
 
+/// gRPC API configuration (see crate::grpc).
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct GrpcConfig {
+    /// Host to bind the listener to.
+    pub listen_host: String,
+    /// Port to bind the listener to (distinct from the HTTP one).
+    pub listen_port: u16,
+}
+
+impl GrpcConfig {
+    /// Returns a string representing the configured network endpoint.
+    pub fn as_bind_str(&self) -> String {
+        format!("{}:{}", self.listen_host, self.listen_port)
+    }
+}
+
+/// Metrics endpoint configuration (see crate::handlers::metrics).
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct MetricsConfig {
+    /// Host to bind the listener to. The endpoint requires no authorization,
+    /// so the listener must only be reachable from the internal network.
+    pub listen_host: String,
+    /// Port to bind the listener to (distinct from the HTTP and gRPC ones).
+    pub listen_port: u16,
+}
+
+impl MetricsConfig {
+    /// Returns a string representing the configured network endpoint.
+    pub fn as_bind_str(&self) -> String {
+        format!("{}:{}", self.listen_host, self.listen_port)
+    }
+}
+
 /// JWT configuration.
 #[derive(Deserialize, Debug, Clone, Default)]
 pub struct JwtConfig {
@@ -42,6 +78,164 @@ pub struct JwtConfig {
     pub secret: String,
 }
 
+/// OAuth client registered to introspect the tokens (see
+/// crate::handlers::oauth).
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct OAuthClient {
+    /// Client identifier.
+    pub client_id: String,
+    /// Client secret.
+    ///
+    /// Sensitive.
+    pub client_secret: String,
+}
+
+/// OAuth configuration.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct OAuthConfig {
+    /// Clients (resource servers) allowed to introspect the tokens.
+    #[serde(default)]
+    pub clients: Vec<OAuthClient>,
+}
+
+/// Pagination configuration.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct PaginationConfig {
+    /// Shared secret, used to sign the list cursors, so they cannot be forged.
+    ///
+    /// Sensitive.
+    pub cursor_secret: String,
+    /// Page size used when the request does not specify one.
+    pub default_page_size: i32,
+    /// Maximum page size a request may specify.
+    pub max_page_size: i32,
+    /// Table size (estimated number of rows) starting from which unfiltered
+    /// lists report estimated total counts instead of exact ones.
+    pub exact_count_threshold: i64,
+}
+
+/// User accounts configuration.
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub struct AccountsConfig {
+    /// Period (in hours) during which a deleted account may be restored.
+    ///
+    /// Deleted accounts are purged after this period expires.
+    pub deletion_grace_period_hours: u32,
+    /// Interval (in seconds) between deleted accounts purge runs.
+    pub purge_interval_secs: u64,
+}
+
+impl AccountsConfig {
+    /// Returns the deletion grace period as a [chrono::Duration].
+    pub fn deletion_grace_period(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.deletion_grace_period_hours.into())
+    }
+}
+
+/// Password hashing configuration.
+///
+/// New passwords are hashed with Argon2id using these parameters. Hashes made
+/// with other algorithms or parameters are upgraded on successful login (see
+/// [crate::passwords]).
+///
+/// Imported hashes are verified on login with their own parameters, so the
+/// ones exceeding the `max_*` limits are rejected on import: verifying them
+/// would take too much time or memory.
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub struct PasswordsConfig {
+    /// Argon2 memory size, in KiB.
+    pub argon2_memory_kib: u32,
+    /// Argon2 number of iterations.
+    pub argon2_iterations: u32,
+    /// Argon2 degree of parallelism.
+    pub argon2_parallelism: u32,
+    /// Maximum Argon2 memory size (in KiB) of the imported hashes.
+    pub max_argon2_memory_kib: u32,
+    /// Maximum Argon2 number of iterations of the imported hashes.
+    pub max_argon2_iterations: u32,
+    /// Maximum Argon2 degree of parallelism of the imported hashes.
+    pub max_argon2_parallelism: u32,
+    /// Maximum bcrypt cost of the imported hashes.
+    pub max_bcrypt_cost: u32,
+    /// Maximum scrypt CPU/memory cost (as a power of 2) of the imported hashes.
+    pub max_scrypt_log_n: u8,
+    /// Maximum scrypt block size of the imported hashes.
+    pub max_scrypt_r: u32,
+    /// Maximum scrypt parallelization of the imported hashes.
+    pub max_scrypt_p: u32,
+    /// Maximum PBKDF2 number of rounds of the imported hashes.
+    pub max_pbkdf2_rounds: u32,
+}
+
+impl PasswordsConfig {
+    /// Returns the configured Argon2 parameters.
+    pub fn argon2_params(&self) -> Result<argon2::Params, argon2::Error> {
+        argon2::Params::new(
+            self.argon2_memory_kib,
+            self.argon2_iterations,
+            self.argon2_parallelism,
+            None,
+        )
+    }
+}
+
+/// Idempotency keys configuration (see crate::middleware::idempotency).
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct IdempotencyConfig {
+    /// Shared secret, used to key the request fingerprints, so the stored
+    /// ones reveal nothing of the request bodies (passwords included).
+    ///
+    /// Sensitive.
+    pub fingerprint_secret: String,
+    /// Period (in seconds) during which the response is replayed to the
+    /// requests retried with the same idempotency key.
+    pub ttl_secs: u64,
+    /// Period (in seconds) during which a request in progress holds its key.
+    /// Once it expires (e.g. because the server crashed while handling the
+    /// request), a retry takes the key over.
+    pub lock_timeout_secs: u64,
+    /// Interval (in seconds) between expired idempotency keys purge runs.
+    pub purge_interval_secs: u64,
+}
+
+impl IdempotencyConfig {
+    /// Returns the idempotency keys time to live as a [chrono::Duration].
+    pub fn ttl(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.ttl_secs.try_into().unwrap_or(i64::MAX))
+    }
+
+    /// Returns the lease of the requests in progress as a [chrono::Duration].
+    pub fn lock_timeout(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.lock_timeout_secs.try_into().unwrap_or(i64::MAX))
+    }
+}
+
+/// GraphQL endpoint configuration (see crate::graphql).
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub struct GraphqlConfig {
+    /// Maximum depth of the selection sets of a query.
+    pub max_depth: usize,
+    /// Maximum complexity of a query: the number of fields it may resolve,
+    /// the fields of connections being multiplied by the requested page size.
+    pub max_complexity: usize,
+}
+
+/// API versioning configuration.
+///
+/// Requests to the unversioned paths which do not specify the version with
+/// the `Api-Version` header are served by the oldest version, and are
+/// deprecated (see crate::middleware::api_version): once the sunset date
+/// passes, the version must be specified.
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub struct VersioningConfig {
+    /// Datetime since which the requests not specifying the version are
+    /// deprecated.
+    pub unversioned_deprecated_at: chrono::DateTime<chrono::Utc>,
+    /// Datetime after which the requests not specifying the version may stop
+    /// being served (optional).
+    pub unversioned_sunset_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
 ///
 /// Server configuration
 #[derive(Deserialize, Debug, Clone, Default)]
@@ -50,8 +244,27 @@ pub struct ServerConfig {
     pub database: DatabaseConfig,
     /// HTTP configuration.
     pub http: HttpConfig,
+    /// gRPC configuration.
+    pub grpc: GrpcConfig,
+    /// Metrics endpoint configuration.
+    pub metrics: MetricsConfig,
     /// JWT configuration.
     pub jwt: JwtConfig,
+    /// OAuth configuration.
+    #[serde(default)]
+    pub oauth: OAuthConfig,
+    /// User accounts configuration.
+    pub accounts: AccountsConfig,
+    /// Pagination configuration.
+    pub pagination: PaginationConfig,
+    /// Password hashing configuration.
+    pub passwords: PasswordsConfig,
+    /// API versioning configuration.
+    pub versioning: VersioningConfig,
+    /// Idempotency keys configuration.
+    pub idempotency: IdempotencyConfig,
+    /// GraphQL endpoint configuration.
+    pub graphql: GraphqlConfig,
 }
 
 impl ServerConfig {
@@ -80,6 +293,22 @@ impl ServerConfig {
             .build()?
             .try_deserialize::<ServerConfig>()?;
 
+        if cfg.pagination.default_page_size < 1
+            || cfg.pagination.default_page_size > cfg.pagination.max_page_size
+        {
+            return Err(ConfigError::Message(
+                "pagination.default_page_size must be within [1, pagination.max_page_size]"
+                    .to_string(),
+            ));
+        }
+
+        if let Err(e) = cfg.passwords.argon2_params() {
+            return Err(ConfigError::Message(format!(
+                "passwords.argon2_* parameters are invalid: {}",
+                e
+            )));
+        }
+
         // Probably there is a better way to make config global
         Ok(cfg)
     }
@@ -0,0 +1,46 @@
+//!
+//! Module contains database access helpers.
+
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::errors::ApiError;
+
+/// Maximum number of attempts of an operation failing with transient errors.
+const MAX_ATTEMPTS: u32 = 4;
+
+/// Delay before the first retry; doubled for every next one.
+const BASE_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+///
+/// Runs the database operation, retrying it while it fails with transient
+/// errors (serialization failures and deadlocks, see
+/// [ApiError::is_transient]).
+///
+/// Retries are delayed with exponential backoff and jitter, so the competing
+/// transactions are not retried in lockstep. The last error is returned once
+/// the attempts are exhausted.
+///
+/// The operation must be safe to repeat: it is either a single statement or a
+/// whole transaction. Sleeps between the attempts, so it must be wrapped with
+/// actix' `web::block`.
+pub fn with_retries<T>(mut operation: impl FnMut() -> Result<T, ApiError>) -> Result<T, ApiError> {
+    let mut attempt = 1;
+    loop {
+        match operation() {
+            Err(e) if e.is_transient() && attempt < MAX_ATTEMPTS => {
+                let backoff = BASE_RETRY_DELAY * 2u32.pow(attempt - 1);
+                let jitter = rand::thread_rng().gen_range(Duration::ZERO..BASE_RETRY_DELAY);
+                log::warn!(
+                    "Retrying the database operation (attempt {}) due to error: {}",
+                    attempt,
+                    e
+                );
+                std::thread::sleep(backoff + jitter);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
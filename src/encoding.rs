@@ -0,0 +1,216 @@
+//!
+//! Module contains the content negotiation of request and response bodies.
+//!
+//! Bodies may be encoded with JSON (the default), MessagePack or CBOR, the
+//! compact binary encodings preferred by embedded clients. Request bodies are
+//! decoded according to their `Content-Type` (see [Encoded]), and responses
+//! are encoded according to the `Accept` header (see [Encoding] and
+//! [EncodedResponse]). Problem details are negotiated the same way (see
+//! [crate::errors::ProblemDetails::with_request]).
+//!
+//! Bulk export and import endpoints stream their bodies, and keep their own
+//! formats (NDJSON and CSV).
+
+use std::future::{ready, Ready};
+use std::io;
+use std::ops::Deref;
+
+use actix_web::dev::Payload;
+use actix_web::http::header::{self, Header};
+use actix_web::{web, FromRequest, HttpMessage, HttpRequest, HttpResponse, HttpResponseBuilder};
+use futures_util::future::LocalBoxFuture;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::errors::{ErrorCode, ProblemDetails, PROBLEM_JSON};
+
+///
+/// Body encoding.
+///
+/// Used as an extractor, negotiates the response encoding with the `Accept`
+/// header (see [Encoding::negotiate]).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// JSON (`application/json`).
+    #[default]
+    Json,
+    /// MessagePack (`application/msgpack`).
+    MsgPack,
+    /// CBOR, RFC 8949 (`application/cbor`).
+    Cbor,
+}
+
+impl Encoding {
+    /// Picks the most preferred supported encoding of the `Accept` header.
+    ///
+    /// Falls back to JSON if there is no `Accept` header, or none of the
+    /// accepted media types is supported: the response is produced anyway,
+    /// as RFC 9110 permits.
+    pub fn negotiate(req: &HttpRequest) -> Self {
+        let Ok(accept) = header::Accept::parse(req) else {
+            return Self::Json;
+        };
+
+        accept
+            .ranked()
+            .iter()
+            .find_map(|mime| match mime.essence_str() {
+                "application/*" | "*/*" => Some(Self::Json),
+                essence => Self::from_media_type(essence),
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns the encoding of the request body, according to its
+    /// `Content-Type`, if the media type is supported.
+    pub fn of_request(req: &HttpRequest) -> Option<Self> {
+        let mime = req.mime_type().ok()??;
+        Self::from_media_type(mime.essence_str())
+    }
+
+    /// Returns the encoding of the given media type (without parameters).
+    fn from_media_type(essence: &str) -> Option<Self> {
+        match essence {
+            "application/json" => Some(Self::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Self::MsgPack)
+            }
+            "application/cbor" => Some(Self::Cbor),
+            _ => None,
+        }
+    }
+
+    /// Returns the media type of the encoded bodies.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::MsgPack => "application/msgpack",
+            Self::Cbor => "application/cbor",
+        }
+    }
+
+    /// Returns the media type of the encoded problem details (see
+    /// [ProblemDetails]).
+    pub fn problem_content_type(self) -> &'static str {
+        match self {
+            Self::Json => PROBLEM_JSON,
+            Self::MsgPack => "application/problem+msgpack",
+            Self::Cbor => "application/problem+cbor",
+        }
+    }
+
+    /// Returns the file name extension of the encoded bodies.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::MsgPack => "msgpack",
+            Self::Cbor => "cbor",
+        }
+    }
+
+    /// Encodes the value. Structs are encoded as maps in every encoding, so
+    /// the field names are kept.
+    pub fn encode(self, value: &impl Serialize) -> Result<Vec<u8>, io::Error> {
+        match self {
+            Self::Json => serde_json::to_vec(value).map_err(io::Error::from),
+            Self::MsgPack => rmp_serde::to_vec_named(value).map_err(io::Error::other),
+            Self::Cbor => {
+                let mut buffer = vec![];
+                ciborium::into_writer(value, &mut buffer).map_err(io::Error::other)?;
+                Ok(buffer)
+            }
+        }
+    }
+
+    /// Decodes the value.
+    pub fn decode<T: DeserializeOwned>(self, body: &[u8]) -> Result<T, io::Error> {
+        match self {
+            Self::Json => serde_json::from_slice(body).map_err(io::Error::from),
+            Self::MsgPack => rmp_serde::from_slice(body).map_err(io::Error::other),
+            Self::Cbor => ciborium::from_reader(body).map_err(io::Error::other),
+        }
+    }
+}
+
+impl FromRequest for Encoding {
+    type Error = std::convert::Infallible;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(Self::negotiate(req)))
+    }
+}
+
+///
+/// Extension of [HttpResponseBuilder], the counterpart of
+/// [HttpResponseBuilder::json] for the negotiated encodings.
+pub trait EncodedResponse {
+    /// Sets the body, encoded with the given encoding, along with the
+    /// `Content-Type` header.
+    fn encoded(&mut self, encoding: Encoding, value: impl Serialize) -> HttpResponse;
+}
+
+impl EncodedResponse for HttpResponseBuilder {
+    fn encoded(&mut self, encoding: Encoding, value: impl Serialize) -> HttpResponse {
+        match encoding.encode(&value) {
+            Ok(body) => self
+                .insert_header((header::CONTENT_TYPE, encoding.content_type()))
+                .body(body),
+            Err(e) => {
+                log::error!("Failed to encode the response: {}", e);
+                ProblemDetails::new(ErrorCode::InternalError).into_response()
+            }
+        }
+    }
+}
+
+///
+/// Request body extractor, decoding the body according to its `Content-Type`
+/// (the counterpart of [web::Json] for the negotiated encodings).
+///
+/// JSON bodies (and bodies of unsupported media types) are extracted with
+/// [web::Json], so [web::JsonConfig] applies to them. MessagePack and CBOR
+/// bodies are limited with [web::PayloadConfig].
+#[derive(Debug)]
+pub struct Encoded<T>(pub T);
+
+impl<T> Encoded<T> {
+    /// Unwraps into the decoded value.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Encoded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for Encoded<T> {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let encoding = match Encoding::of_request(req) {
+            Some(encoding) if encoding != Encoding::Json => encoding,
+            _ => {
+                let json = web::Json::<T>::from_request(req, payload);
+                return Box::pin(async move { Ok(Self(json.await?.into_inner())) });
+            }
+        };
+
+        let req = req.clone();
+        let body = web::Bytes::from_request(&req, payload);
+        Box::pin(async move {
+            let body = body.await?;
+            encoding.decode(&body).map(Self).map_err(|e| {
+                ProblemDetails::new(ErrorCode::MalformedRequest)
+                    .with_detail(e.to_string())
+                    .with_request(&req)
+                    .into_error()
+            })
+        })
+    }
+}
@@ -1,13 +1,23 @@
 //!
 //! Module contains API errors and ways to convert those errors into API responses.
 
-use actix_web::HttpResponse;
+use actix_web::dev::ServiceResponse;
+use actix_web::error::{
+    InternalError, JsonPayloadError, PathError, QueryPayloadError, UrlencodedError,
+};
+use actix_web::http::{header, StatusCode};
+use actix_web::middleware::ErrorHandlerResponse;
 use actix_web::{error::BlockingError as ActixBlockingError, Responder};
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
 use argon2::password_hash::errors::Error as Argon2Error;
 use diesel::result::{DatabaseErrorKind, Error as DieselError};
 use jsonwebtoken::errors::Error as JwtError;
 use r2d2::Error as R2d2Error;
 use serde::{Deserialize, Serialize};
+use validator::{ValidationErrors, ValidationErrorsKind};
+
+use crate::encoding::{EncodedResponse, Encoding};
+use crate::middleware::request_id::RequestId;
 
 /// Enum representing API errors.
 ///
@@ -20,7 +30,7 @@ pub enum ApiError {
     /// May contain handlable errors (for example, a duplicate for index).
     #[error("Database error: {from}")]
     Diesel {
-        ///
+        /// Source error.
         #[from]
         from: DieselError,
     },
@@ -29,7 +39,7 @@ pub enum ApiError {
     /// Irrecoverable.
     #[error("Hashing error: {from}")]
     Argon2 {
-        ///
+        /// Source error.
         #[from]
         from: Argon2Error,
     },
@@ -38,7 +48,7 @@ pub enum ApiError {
     /// Irrecoverable.
     #[error("Actix blocking operation error: {from}")]
     ActixBlocking {
-        ///
+        /// Source error.
         #[from]
         from: ActixBlockingError,
     },
@@ -47,7 +57,7 @@ pub enum ApiError {
     /// Irrecoverable.
     #[error("Database pool error: {from}")]
     R2d2 {
-        ///
+        /// Source error.
         #[from]
         from: R2d2Error,
     },
@@ -56,7 +66,7 @@ pub enum ApiError {
     /// May contain handlable errors (for example, JWT token validation error).
     #[error("JWT error: {from}")]
     Jwt {
-        ///
+        /// Source error.
         #[from]
         from: JwtError,
     },
@@ -65,48 +75,620 @@ pub enum ApiError {
     /// Specific for JWT token create request.
     #[error("Invalid credentials provided")]
     InvalidCredentials {},
+    /// Invalid client error.
+    ///
+    /// Specific for the OAuth endpoints, authenticating the clients.
+    #[error("Invalid client credentials provided")]
+    InvalidClient {},
+    /// Resource not found error.
+    ///
+    /// Returned when the requested resource does not exist.
+    #[error("Resource not found")]
+    NotFound {},
+    /// Forbidden error.
+    ///
+    /// Returned when the authorized user lacks the permissions required.
+    #[error("Forbidden")]
+    Forbidden {},
+    /// Request validation error representation.
+    ///
+    /// Returned when the request is well-formed, but its values are not acceptable.
+    #[error("Validation error: {from}")]
+    Validation {
+        /// Source error.
+        #[from]
+        from: ValidationErrors,
+    },
+    /// Precondition failed error.
+    ///
+    /// Returned when the `If-Match` header does not match the current resource version.
+    #[error("Precondition failed")]
+    PreconditionFailed {},
+    /// Precondition required error.
+    ///
+    /// Returned when a conditional request is required, but no `If-Match` header is provided.
+    #[error("Precondition required")]
+    PreconditionRequired {},
+    /// I/O error (e.g. when encoding a response body).
+    #[error("I/O error: {from}")]
+    Io {
+        /// Source error.
+        #[from]
+        from: std::io::Error,
+    },
+    /// Not acceptable error.
+    ///
+    /// Returned when none of the media types listed in the `Accept` header can be produced.
+    #[error("Not acceptable")]
+    NotAcceptable {},
+}
+
+/// Number of seconds clients are asked to wait before retrying, when the
+/// database is unavailable (see [ErrorCode::ServiceUnavailable]).
+pub const RETRY_AFTER_SECS: u32 = 5;
+
+/// Request fields (or parameters) guarded by the database constraints, used
+/// to report which field a constraint violation is caused by.
+const CONSTRAINT_FIELDS: [(&str, &str); 3] = [
+    ("idx_email", "email"),
+    ("user_roles_pkey", "role"),
+    ("user_roles_user_id_fkey", "user_id"),
+];
+
+impl ApiError {
+    ///
+    /// Returns the stable error code the error is rendered with.
+    ///
+    /// Database errors are mapped as follows:
+    /// - `NotFound`: 404 not_found
+    /// - unique violation: 409 conflict
+    /// - foreign key violation: 409 constraint_violation
+    /// - check and not null violations: 422 validation_failed
+    /// - serialization failures and deadlocks (once the retries are
+    ///   exhausted, see [crate::db::with_retries]), closed connections and
+    ///   read-only transactions (e.g. during a failover): 503
+    ///   service_unavailable
+    /// - anything else: 500 internal_error
+    ///
+    /// Pool errors (that is, connection acquisition timeouts) are mapped to
+    /// 503 service_unavailable.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::ActixBlocking { .. }
+            | Self::Argon2 { .. }
+            | Self::Jwt { .. }
+            | Self::Io { .. } => ErrorCode::InternalError,
+            Self::R2d2 { .. } => ErrorCode::ServiceUnavailable,
+            Self::InvalidCredentials {} => ErrorCode::InvalidCredentials,
+            Self::InvalidClient {} => ErrorCode::InvalidClient,
+            Self::NotFound {} => ErrorCode::NotFound,
+            Self::Forbidden {} => ErrorCode::Forbidden,
+            Self::Validation { .. } => ErrorCode::ValidationFailed,
+            Self::PreconditionFailed {} => ErrorCode::PreconditionFailed,
+            Self::PreconditionRequired {} => ErrorCode::PreconditionRequired,
+            Self::NotAcceptable {} => ErrorCode::NotAcceptable,
+            Self::Diesel { .. } if self.is_transient() => ErrorCode::ServiceUnavailable,
+            Self::Diesel { from } => match from {
+                DieselError::NotFound => ErrorCode::NotFound,
+                DieselError::DatabaseError(kind, _) => match kind {
+                    DatabaseErrorKind::UniqueViolation => ErrorCode::Conflict,
+                    DatabaseErrorKind::ForeignKeyViolation => ErrorCode::ConstraintViolation,
+                    DatabaseErrorKind::CheckViolation | DatabaseErrorKind::NotNullViolation => {
+                        ErrorCode::ValidationFailed
+                    }
+                    DatabaseErrorKind::ClosedConnection
+                    | DatabaseErrorKind::UnableToSendCommand
+                    | DatabaseErrorKind::ReadOnlyTransaction => ErrorCode::ServiceUnavailable,
+                    _ => ErrorCode::InternalError,
+                },
+                _ => ErrorCode::InternalError,
+            },
+        }
+    }
+
+    /// Returns `true` if the error is caused by a concurrent transaction
+    /// (serialization failure or deadlock), so the operation may succeed if
+    /// retried.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Diesel {
+                from: DieselError::DatabaseError(DatabaseErrorKind::SerializationFailure, _),
+            } => true,
+            // Diesel does not map deadlocks (SQLSTATE 40P01) to a separate kind
+            Self::Diesel {
+                from: DieselError::DatabaseError(DatabaseErrorKind::Unknown, info),
+            } => info.message().starts_with("deadlock detected"),
+            _ => false,
+        }
+    }
+
+    /// Returns the failed validation of the field guarded by the violated
+    /// database constraint, if the field is known.
+    fn constraint_violation(&self) -> Option<FieldErrorPayload> {
+        let (kind, info) = match self {
+            Self::Diesel {
+                from: DieselError::DatabaseError(kind, info),
+            } => (kind, info),
+            _ => return None,
+        };
+        let code = match kind {
+            DatabaseErrorKind::UniqueViolation => "unique",
+            DatabaseErrorKind::ForeignKeyViolation => "reference",
+            DatabaseErrorKind::CheckViolation => "check",
+            DatabaseErrorKind::NotNullViolation => "required",
+            _ => return None,
+        };
+        let field = match info.constraint_name() {
+            Some(constraint) => CONSTRAINT_FIELDS
+                .iter()
+                .find(|(name, _)| *name == constraint)
+                .map(|(_, field)| *field)?,
+            None => info.column_name()?,
+        };
+        Some(FieldErrorPayload {
+            field: field.to_string(),
+            code: code.to_string(),
+            params: serde_json::Map::new(),
+        })
+    }
 }
 
 impl Responder for ApiError {
     type Body = actix_web::body::BoxBody;
     fn respond_to(self, req: &actix_web::HttpRequest) -> HttpResponse<Self::Body> {
+        let code = self.code();
+        if code == ErrorCode::InternalError || code == ErrorCode::ServiceUnavailable {
+            // Probably not the best place to put logs into?..
+            log::error!(
+                "Responding an error to '{} {}' request due to error: {}",
+                req.method(),
+                req.uri(),
+                &self
+            );
+        }
+
+        ProblemDetails::from(self).with_request(req).into_response()
+    }
+}
+
+impl From<ApiError> for ProblemDetails {
+    fn from(e: ApiError) -> Self {
+        let mut problem = ProblemDetails::new(e.code());
+        if let Some(violation) = e.constraint_violation() {
+            problem.detail = Some(format!("Constraint violated by `{}`", violation.field));
+            problem.errors.push(violation);
+        }
+        if let ApiError::Validation { from } = e {
+            problem.errors = FieldErrorPayload::collect(&from);
+        }
+        problem
+    }
+}
+
+///
+/// Stable machine-readable error code, rendered as the `code` member of
+/// [ProblemDetails].
+///
+/// Clients may switch on the code: unlike titles and details, codes are never
+/// changed once released (new codes may be added, though).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request is malformed (for example, the JSON body cannot be parsed).
+    MalformedRequest,
+    /// The credentials provided to create a token are invalid.
+    InvalidCredentials,
+    /// The authorization token is missing, invalid or expired.
+    Unauthorized,
+    /// The OAuth client authentication failed (see the `WWW-Authenticate`
+    /// response header).
+    InvalidClient,
+    /// The authorized user lacks the permissions required.
+    Forbidden,
+    /// The requested resource does not exist.
+    NotFound,
+    /// The resource does not support the request method (see the `Allow`
+    /// response header).
+    MethodNotAllowed,
+    /// None of the media types listed in the `Accept` header can be produced.
+    NotAcceptable,
+    /// The resource already exists (see [ProblemDetails::errors] for the
+    /// conflicting field).
+    Conflict,
+    /// The request conflicts with the current state of a related resource
+    /// (for example, the referenced resource does not exist).
+    ConstraintViolation,
+    /// Another request with the same `Idempotency-Key` is still in progress.
+    IdempotencyKeyInUse,
+    /// The `Idempotency-Key` was already used with a different request.
+    IdempotencyKeyReused,
+    /// The `If-Match` header does not match the current resource version.
+    PreconditionFailed,
+    /// The request body is too large.
+    PayloadTooLarge,
+    /// The request body media type is not supported.
+    UnsupportedMediaType,
+    /// The request is well-formed, but its values are not acceptable (see
+    /// [ProblemDetails::errors]).
+    ValidationFailed,
+    /// A conditional request is required, but no `If-Match` header is provided.
+    PreconditionRequired,
+    /// Unexpected server-side failure.
+    InternalError,
+    /// The service is temporarily unable to handle the request (see the
+    /// `Retry-After` response header).
+    ServiceUnavailable,
+}
+
+impl ErrorCode {
+    /// Returns the HTTP status the error is responded with.
+    pub fn status(self) -> StatusCode {
         match self {
-            Self::ActixBlocking { .. }
-            | Self::Argon2 { .. }
-            | Self::R2d2 { .. }
-            | Self::Jwt { .. } => {
-                // Probably not the best place to put logs into?..
-                log::error!(
-                    "Responding an error to '{} {}' request due to error: {}",
-                    req.method(),
-                    req.uri(),
-                    &self
-                );
-                generic_ise()
-            }
-            Self::InvalidCredentials {} => HttpResponse::BadRequest().json(ErrorPayload {
-                reason: "Invalid credentials",
-            }),
-            Self::Diesel { from } => {
-                if let DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) = from {
-                    return HttpResponse::Conflict().json(ErrorPayload {
-                        reason: "Resource already exists",
-                    });
-                }
-                generic_ise()
+            Self::MalformedRequest | Self::InvalidCredentials => StatusCode::BAD_REQUEST,
+            Self::Unauthorized | Self::InvalidClient => StatusCode::UNAUTHORIZED,
+            Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            Self::NotAcceptable => StatusCode::NOT_ACCEPTABLE,
+            Self::Conflict | Self::ConstraintViolation | Self::IdempotencyKeyInUse => {
+                StatusCode::CONFLICT
             }
+            Self::IdempotencyKeyReused => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            Self::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::ValidationFailed => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::PreconditionRequired => StatusCode::PRECONDITION_REQUIRED,
+            Self::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    /// Returns the short human-readable summary of the error.
+    pub fn title(self) -> &'static str {
+        match self {
+            Self::MalformedRequest => "Malformed request",
+            Self::InvalidCredentials => "Invalid credentials",
+            Self::Unauthorized => "Unauthorized",
+            Self::InvalidClient => "Client authentication failed",
+            Self::Forbidden => "Forbidden",
+            Self::NotFound => "Resource not found",
+            Self::MethodNotAllowed => "Method not allowed",
+            Self::NotAcceptable => "Requested media type is not supported",
+            Self::Conflict => "Resource already exists",
+            Self::ConstraintViolation => "Request conflicts with a related resource",
+            Self::IdempotencyKeyInUse => "Request with the same idempotency key is in progress",
+            Self::IdempotencyKeyReused => "Idempotency key was used with a different request",
+            Self::PreconditionFailed => "Resource was modified",
+            Self::PayloadTooLarge => "Request body is too large",
+            Self::UnsupportedMediaType => "Request body media type is not supported",
+            Self::ValidationFailed => "Validation failed",
+            Self::PreconditionRequired => "If-Match header is required",
+            Self::InternalError => "Internal server error",
+            Self::ServiceUnavailable => "Service temporarily unavailable",
+        }
+    }
+
+    /// Returns the code matching the HTTP status, falling back to
+    /// [Self::MalformedRequest] for other client errors, and to
+    /// [Self::InternalError] for other server errors.
+    pub fn from_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED => Self::Unauthorized,
+            StatusCode::FORBIDDEN => Self::Forbidden,
+            StatusCode::NOT_FOUND => Self::NotFound,
+            StatusCode::METHOD_NOT_ALLOWED => Self::MethodNotAllowed,
+            StatusCode::NOT_ACCEPTABLE => Self::NotAcceptable,
+            StatusCode::CONFLICT => Self::Conflict,
+            StatusCode::PRECONDITION_FAILED => Self::PreconditionFailed,
+            StatusCode::PAYLOAD_TOO_LARGE => Self::PayloadTooLarge,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => Self::UnsupportedMediaType,
+            StatusCode::UNPROCESSABLE_ENTITY => Self::ValidationFailed,
+            StatusCode::PRECONDITION_REQUIRED => Self::PreconditionRequired,
+            StatusCode::SERVICE_UNAVAILABLE => Self::ServiceUnavailable,
+            status if status.is_client_error() => Self::MalformedRequest,
+            _ => Self::InternalError,
+        }
+    }
+
+    /// Returns the code as it is serialized (for example, `not_found`).
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::MalformedRequest => "malformed_request",
+            Self::InvalidCredentials => "invalid_credentials",
+            Self::Unauthorized => "unauthorized",
+            Self::InvalidClient => "invalid_client",
+            Self::Forbidden => "forbidden",
+            Self::NotFound => "not_found",
+            Self::MethodNotAllowed => "method_not_allowed",
+            Self::NotAcceptable => "not_acceptable",
+            Self::Conflict => "conflict",
+            Self::ConstraintViolation => "constraint_violation",
+            Self::IdempotencyKeyInUse => "idempotency_key_in_use",
+            Self::IdempotencyKeyReused => "idempotency_key_reused",
+            Self::PreconditionFailed => "precondition_failed",
+            Self::PayloadTooLarge => "payload_too_large",
+            Self::UnsupportedMediaType => "unsupported_media_type",
+            Self::ValidationFailed => "validation_failed",
+            Self::PreconditionRequired => "precondition_required",
+            Self::InternalError => "internal_error",
+            Self::ServiceUnavailable => "service_unavailable",
+        }
+    }
+}
+
+/// Media type of the error responses encoded with JSON (see
+/// [Encoding::problem_content_type]).
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+///
+/// Error response body, as defined by RFC 7807 (Problem Details for HTTP APIs).
+///
+/// https://datatracker.ietf.org/doc/html/rfc7807
+///
+/// Example:
+/// {
+///   "type": "urn:na:error:validation_failed",
+///   "title": "Validation failed",
+///   "status": 422,
+///   "instance": "/user",
+///   "code": "validation_failed",
+///   "request_id": "4bf92f3577b34da6a3ce929d0e0e4736",
+///   "errors": [{"field": "email", "code": "email", "params": {}}]
+/// }
+#[derive(Debug, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct ProblemDetails {
+    /// Problem type URI, derived from the code (`urn:na:error:<code>`).
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    /// Short human-readable summary of the problem type.
+    pub title: String,
+    /// HTTP status code.
+    pub status: u16,
+    /// Human-readable explanation specific to this occurrence, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// Path of the request the problem occurred with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    /// Stable machine-readable error code (see [ErrorCode]).
+    pub code: ErrorCode,
+    /// Id of the request the problem occurred with, to be quoted when
+    /// reporting the problem (see crate::middleware::request_id).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Failed validations, for [ErrorCode::ValidationFailed] problems.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldErrorPayload>,
+    /// Encoding of the response, negotiated with the request (see
+    /// [ProblemDetails::with_request]).
+    #[serde(skip)]
+    encoding: Encoding,
+}
+
+impl ProblemDetails {
+    /// Creates the problem details of the given code.
+    pub fn new(code: ErrorCode) -> Self {
+        Self {
+            problem_type: format!("urn:na:error:{}", code.as_str()),
+            title: code.title().to_string(),
+            status: code.status().as_u16(),
+            detail: None,
+            instance: None,
+            code,
+            request_id: None,
+            errors: Vec::new(),
+            encoding: Encoding::Json,
+        }
+    }
+
+    /// Creates the problem details of the given HTTP status (see
+    /// [ErrorCode::from_status]). The status is kept as is, even if the code
+    /// is a fallback one.
+    pub fn from_status(status: StatusCode) -> Self {
+        let mut problem = Self::new(ErrorCode::from_status(status));
+        if problem.status != status.as_u16() {
+            problem.status = status.as_u16();
+            problem.title = status
+                .canonical_reason()
+                .unwrap_or(problem.code.title())
+                .to_string();
         }
+        problem
     }
+
+    /// Sets the occurrence-specific explanation.
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    /// Sets the path of the request the problem occurred with.
+    pub fn with_instance(mut self, path: impl Into<String>) -> Self {
+        self.instance = Some(path.into());
+        self
+    }
+
+    /// Sets the path and the id of the request the problem occurred with, and
+    /// negotiates the encoding of the response with the request (see
+    /// [Encoding::negotiate]).
+    pub fn with_request(mut self, req: &HttpRequest) -> Self {
+        self.instance = Some(req.path().to_string());
+        self.request_id = req.extensions().get::<RequestId>().map(|id| id.0.clone());
+        self.encoding = Encoding::negotiate(req);
+        self
+    }
+
+    /// Renders the problem as an `application/problem+json` response (or the
+    /// `application/problem+msgpack` or `application/problem+cbor` one, if
+    /// negotiated).
+    pub fn into_response(self) -> HttpResponse {
+        let status = StatusCode::from_u16(self.status).unwrap_or(self.code.status());
+        let retry = self.code == ErrorCode::ServiceUnavailable;
+        let content_type = self.encoding.problem_content_type();
+        let mut resp = HttpResponse::build(status).encoded(self.encoding, &self);
+        let _ = resp.headers_mut().insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static(content_type),
+        );
+        if retry {
+            let _ = resp.headers_mut().insert(
+                header::RETRY_AFTER,
+                header::HeaderValue::from(RETRY_AFTER_SECS),
+            );
+        }
+        resp
+    }
+
+    /// Wraps the problem into an actix error, for the places where an error
+    /// is expected instead of a response (middlewares, extractor error
+    /// handlers).
+    pub fn into_error(self) -> actix_web::Error {
+        let title = self.title.clone();
+        InternalError::from_response(title, self.into_response()).into()
+    }
+}
+
+/// JSON body extractor error handler (see [actix_web::web::JsonConfig]).
+pub fn json_error_handler(err: JsonPayloadError, req: &HttpRequest) -> actix_web::Error {
+    let code = match &err {
+        JsonPayloadError::OverflowKnownLength { .. } | JsonPayloadError::Overflow { .. } => {
+            ErrorCode::PayloadTooLarge
+        }
+        JsonPayloadError::ContentType => ErrorCode::UnsupportedMediaType,
+        _ => ErrorCode::MalformedRequest,
+    };
+    ProblemDetails::new(code)
+        .with_detail(err.to_string())
+        .with_request(req)
+        .into_error()
 }
 
-fn generic_ise() -> HttpResponse<<ApiError as Responder>::Body> {
-    HttpResponse::InternalServerError().json(ErrorPayload {
-        reason: "Internal server error",
-    })
+/// URL-encoded form body extractor error handler (see
+/// [actix_web::web::FormConfig]).
+pub fn form_error_handler(err: UrlencodedError, req: &HttpRequest) -> actix_web::Error {
+    let code = match &err {
+        UrlencodedError::Overflow { .. } => ErrorCode::PayloadTooLarge,
+        UrlencodedError::ContentType => ErrorCode::UnsupportedMediaType,
+        _ => ErrorCode::MalformedRequest,
+    };
+    ProblemDetails::new(code)
+        .with_detail(err.to_string())
+        .with_request(req)
+        .into_error()
 }
 
-#[allow(missing_docs)]
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ErrorPayload<'a> {
-    pub reason: &'a str,
+/// Query string extractor error handler (see [actix_web::web::QueryConfig]).
+pub fn query_error_handler(err: QueryPayloadError, req: &HttpRequest) -> actix_web::Error {
+    ProblemDetails::new(ErrorCode::MalformedRequest)
+        .with_detail(err.to_string())
+        .with_request(req)
+        .into_error()
+}
+
+/// Path parameters extractor error handler (see [actix_web::web::PathConfig]).
+///
+/// A path which cannot be parsed does not identify any resource, so it is
+/// reported as not found (as actix does by default).
+pub fn path_error_handler(err: PathError, req: &HttpRequest) -> actix_web::Error {
+    ProblemDetails::new(ErrorCode::NotFound)
+        .with_detail(err.to_string())
+        .with_request(req)
+        .into_error()
+}
+
+/// Default service, responding to requests not matching any route.
+pub async fn not_found(req: HttpRequest) -> HttpResponse {
+    ProblemDetails::new(ErrorCode::NotFound)
+        .with_request(&req)
+        .into_response()
+}
+
+///
+/// Error responses handler (see [actix_web::middleware::ErrorHandlers]).
+///
+/// Renders the error responses built by actix itself (for example, the empty
+/// 405 responses of resources, or extractor errors without a configured
+/// handler) as problem details. The original headers (such as `Allow`) are
+/// kept. Responses which are problem details already (in any encoding) are
+/// left untouched.
+pub fn render_problem<B>(res: ServiceResponse<B>) -> actix_web::Result<ErrorHandlerResponse<B>> {
+    let is_problem = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/problem+"));
+    if is_problem {
+        return Ok(ErrorHandlerResponse::Response(res.map_into_left_body()));
+    }
+
+    let (req, res) = res.into_parts();
+    let mut problem = ProblemDetails::from_status(res.status()).with_request(&req);
+    if let Some(err) = res.error() {
+        if problem.code != ErrorCode::InternalError {
+            problem.detail = Some(err.to_string());
+        }
+    }
+    let mut rendered = problem.into_response();
+    for (name, value) in res.headers() {
+        if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH {
+            rendered.headers_mut().append(name.clone(), value.clone());
+        }
+    }
+    Ok(ErrorHandlerResponse::Response(
+        ServiceResponse::new(req, rendered).map_into_right_body(),
+    ))
+}
+
+///
+/// Single failed validation of a request field.
+#[derive(Debug, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct FieldErrorPayload {
+    /// Field name; nested fields are joined with dots (`parent.child`).
+    pub field: String,
+    /// Machine-readable error code (`email`, `length`, `range`, `blank`, ...).
+    pub code: String,
+    /// Validation rule parameters (for example, `min` and `max` for `length`).
+    pub params: serde_json::Map<String, serde_json::Value>,
+}
+
+impl FieldErrorPayload {
+    /// Flattens validation errors into a list, sorted by field name.
+    pub fn collect(errors: &ValidationErrors) -> Vec<Self> {
+        let mut collected = Vec::new();
+        Self::collect_into(errors, "", &mut collected);
+        collected.sort_by(|a, b| a.field.cmp(&b.field));
+        collected
+    }
+
+    fn collect_into(errors: &ValidationErrors, prefix: &str, collected: &mut Vec<Self>) {
+        for (field, kind) in errors.errors() {
+            let field = format!("{prefix}{field}");
+            match kind {
+                ValidationErrorsKind::Field(field_errors) => {
+                    collected.extend(field_errors.iter().map(|error| {
+                        Self {
+                            field: field.clone(),
+                            code: error.code.to_string(),
+                            params: error
+                                .params
+                                .iter()
+                                // Never echo the rejected value back: it may be a password
+                                .filter(|(name, _)| *name != "value")
+                                .map(|(name, value)| (name.to_string(), value.clone()))
+                                .collect(),
+                        }
+                    }))
+                }
+                ValidationErrorsKind::Struct(nested) => {
+                    Self::collect_into(nested, &format!("{field}."), collected)
+                }
+                ValidationErrorsKind::List(items) => {
+                    for (index, nested) in items {
+                        Self::collect_into(nested, &format!("{field}.{index}."), collected)
+                    }
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,180 @@
+//!
+//! Module contains personal data exporters, used to build GDPR data export
+//! archives (see GET /user/me/export).
+//!
+//! Every table referencing `users.id` (or holding records of the user
+//! otherwise, such as the idempotency keys) must register an exporter in
+//! [EXPORTERS], so the archive covers everything we hold about the user.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use serde::Serialize;
+
+use crate::errors::ApiError;
+use crate::models::{IdempotencyKey, User};
+use crate::schema::{idempotency_keys, user_roles, users};
+
+///
+/// Exports a single section of the user personal data.
+pub trait UserDataExporter: Sync {
+    /// Section name, used as a key in the export archive.
+    fn section(&self) -> &'static str;
+
+    /// Collects the section data of the given user.
+    fn export(&self, user_id: i32, conn: &mut PgConnection) -> Result<serde_json::Value, ApiError>;
+}
+
+/// All registered exporters, in the order of sections in the archive.
+pub static EXPORTERS: &[&dyn UserDataExporter] =
+    &[&ProfileExporter, &RolesExporter, &IdempotencyKeysExporter];
+
+///
+/// User personal data export archive.
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct UserDataExport {
+    /// User id the data belongs to.
+    pub user_id: i32,
+    /// Archive generation datetime.
+    pub generated_at: chrono::NaiveDateTime,
+    /// Exported sections, keyed by [UserDataExporter::section].
+    pub data: serde_json::Map<String, serde_json::Value>,
+}
+
+impl UserDataExport {
+    /// Collect the archive, running all registered exporters within a single
+    /// read-only transaction, so the sections are consistent with each other.
+    pub fn collect(user_id: i32, conn: &mut PgConnection) -> Result<Self, ApiError> {
+        let data = conn.build_transaction().read_only().repeatable_read().run(
+            |conn| -> Result<_, ApiError> {
+                let mut data = serde_json::Map::new();
+                for exporter in EXPORTERS {
+                    let _ = data.insert(
+                        exporter.section().to_string(),
+                        exporter.export(user_id, conn)?,
+                    );
+                }
+                Ok(data)
+            },
+        )?;
+
+        Ok(Self {
+            user_id,
+            generated_at: chrono::Utc::now().naive_utc(),
+            data,
+        })
+    }
+}
+
+///
+/// Exports the `users` table record.
+///
+/// The password hash is omitted: it is not personal data in a usable form,
+/// and it must never leave the system.
+#[derive(Clone, Copy, Debug)]
+pub struct ProfileExporter;
+
+#[derive(Serialize)]
+struct ExportedProfile {
+    id: i32,
+    email: String,
+    name: String,
+    created_at: chrono::NaiveDateTime,
+    updated_at: chrono::NaiveDateTime,
+    deleted_at: Option<chrono::NaiveDateTime>,
+    listed: bool,
+}
+
+impl UserDataExporter for ProfileExporter {
+    fn section(&self) -> &'static str {
+        "profile"
+    }
+
+    fn export(&self, user_id: i32, conn: &mut PgConnection) -> Result<serde_json::Value, ApiError> {
+        let user = users::table.find(user_id).first::<User>(conn)?;
+
+        Ok(serde_json::json!(ExportedProfile {
+            id: user.id,
+            email: user.email,
+            name: user.name,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+            deleted_at: user.deleted_at,
+            listed: user.listed,
+        }))
+    }
+}
+
+///
+/// Exports the `user_roles` table records.
+#[derive(Clone, Copy, Debug)]
+pub struct RolesExporter;
+
+#[derive(Serialize, Queryable)]
+struct ExportedRole {
+    role: String,
+    created_at: chrono::NaiveDateTime,
+}
+
+impl UserDataExporter for RolesExporter {
+    fn section(&self) -> &'static str {
+        "roles"
+    }
+
+    fn export(&self, user_id: i32, conn: &mut PgConnection) -> Result<serde_json::Value, ApiError> {
+        let roles = user_roles::table
+            .filter(user_roles::user_id.eq(user_id))
+            .select((user_roles::role, user_roles::created_at))
+            .order_by(user_roles::role)
+            .load::<ExportedRole>(conn)?;
+
+        Ok(serde_json::json!(roles))
+    }
+}
+
+///
+/// Exports the `idempotency_keys` table records of the user, along with the
+/// stored responses (see crate::middleware::idempotency).
+///
+/// Request fingerprints are omitted: they are keyed hashes, meaningless
+/// outside of the system.
+#[derive(Clone, Copy, Debug)]
+pub struct IdempotencyKeysExporter;
+
+#[derive(Serialize)]
+struct ExportedIdempotencyKey {
+    idempotency_key: String,
+    status: Option<i16>,
+    headers: serde_json::Value,
+    /// Base64-encoded, as it may be encoded with MessagePack or CBOR.
+    body: String,
+    created_at: chrono::NaiveDateTime,
+    expires_at: chrono::NaiveDateTime,
+}
+
+impl UserDataExporter for IdempotencyKeysExporter {
+    fn section(&self) -> &'static str {
+        "idempotency_keys"
+    }
+
+    fn export(&self, user_id: i32, conn: &mut PgConnection) -> Result<serde_json::Value, ApiError> {
+        let keys = idempotency_keys::table
+            .filter(idempotency_keys::principal.eq(IdempotencyKey::user_principal(user_id)))
+            .order_by(idempotency_keys::created_at)
+            .load::<IdempotencyKey>(conn)?;
+
+        let exported: Vec<_> = keys
+            .into_iter()
+            .map(|key| ExportedIdempotencyKey {
+                idempotency_key: key.idempotency_key,
+                status: key.status,
+                headers: serde_json::from_str(&key.headers).unwrap_or_default(),
+                body: STANDARD.encode(&key.body),
+                created_at: key.created_at,
+                expires_at: key.expires_at,
+            })
+            .collect();
+        Ok(serde_json::json!(exported))
+    }
+}
@@ -0,0 +1,375 @@
+//!
+//! Module contains the GraphQL schema over the user domain.
+//!
+//! Served at POST /graphql (see [crate::handlers::graphql]) alongside the
+//! REST API, so clients may fetch users (admins: along with their roles) in
+//! a single request. Resolvers share the REST handlers logic and the Diesel
+//! models, so both APIs apply the same validation, visibility and pagination
+//! rules.
+//!
+//! The caller [Identity] comes from the JWT middleware, which lets anonymous
+//! requests through here: only the `register` mutation is available to them.
+//!
+//! Errors are listed in the `errors` member of the response, carrying the
+//! [ErrorCode] (along with the HTTP status, the failed validations and the
+//! request id) in their `extensions`, as problem details do.
+//!
+//! Queries are limited in depth and complexity (see [GraphqlConfig]). The
+//! page size of connections (`first` or `last`) is required, and multiplies
+//! the complexity of the selected fields.
+
+use actix_web::{http::header::IfMatch, web};
+use async_graphql::connection::{Connection, Edge};
+use async_graphql::{
+    ComplexObject, Context, EmptySubscription, Error, ErrorExtensions, InputObject, Object, Schema,
+    SimpleObject,
+};
+
+use crate::config::{GraphqlConfig, ServerConfig};
+use crate::errors::{ApiError, ErrorCode, ProblemDetails};
+use crate::handlers::user::{register_single_user, update_user_profile, InputUser, UserPatch};
+use crate::handlers::users::{
+    list_users, ListRequest, ListTotal, ListedUser, SortOrder, UserStatus,
+};
+use crate::handlers::OutputUser;
+use crate::middleware::{
+    jwt::Identity,
+    request_id::{self, RequestId},
+};
+use crate::models::User;
+use crate::validation::field_error;
+use crate::DbPool;
+
+/// GraphQL schema of the user domain.
+pub type UsersSchema = Schema<Query, Mutation, EmptySubscription>;
+
+///
+/// Builds the schema, limited according to the configuration.
+///
+/// Resolvers expect the request data: the database pool
+/// (`web::Data<DbPool>`), the server configuration (`&'static ServerConfig`)
+/// and the caller [Identity], if authorized.
+pub fn schema(cfg: &GraphqlConfig) -> UsersSchema {
+    Schema::build(Query, Mutation, EmptySubscription)
+        .limit_depth(cfg.max_depth)
+        .limit_complexity(cfg.max_complexity)
+        .finish()
+}
+
+///
+/// User, rendered for the caller (see [OutputUser]).
+#[derive(Debug, SimpleObject)]
+#[graphql(name = "User", complex)]
+pub struct UserNode {
+    /// User id
+    pub id: i32,
+    /// User email (masked for other users, e.g. `j***@example.org`)
+    pub email: String,
+    /// User name
+    pub name: String,
+    /// User creation datetime
+    pub created_at: chrono::NaiveDateTime,
+    /// Whether the user is shown to other users in lists and search results
+    /// (only rendered for the user itself and admins)
+    pub listed: Option<bool>,
+    /// Names of the roles granted to the user, if already loaded along with
+    /// the user.
+    #[graphql(skip)]
+    pub roles: Option<Vec<String>>,
+}
+
+#[ComplexObject]
+impl UserNode {
+    /// Names of the roles granted to the user (only rendered for the user
+    /// itself and admins)
+    async fn roles(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<String>> {
+        if let Some(roles) = &self.roles {
+            return Ok(roles.clone());
+        }
+        let caller = identity(ctx)?;
+        if caller.user_id != self.id && !caller.is_admin() {
+            return Err(api_error(ApiError::Forbidden {}));
+        }
+
+        let db = db(ctx);
+        let user_id = self.id;
+        let roles = request_id::block(move || User::roles(user_id, db))
+            .await
+            .map_err(ApiError::from)
+            .and_then(|roles| roles);
+        roles.map_err(api_error)
+    }
+}
+
+impl From<OutputUser> for UserNode {
+    fn from(user: OutputUser) -> Self {
+        Self {
+            id: user.id,
+            email: user.email,
+            name: user.name,
+            created_at: user.created_at,
+            listed: user.listed,
+            roles: None,
+        }
+    }
+}
+
+impl From<ListedUser> for UserNode {
+    /// Converts the listed user, fetched with all the fields (see
+    /// [ListRequest::fields]).
+    fn from(user: ListedUser) -> Self {
+        Self {
+            id: user.id,
+            email: user.email.unwrap_or_default(),
+            name: user.name.unwrap_or_default(),
+            created_at: user.created_at.unwrap_or_default(),
+            listed: user.listed,
+            roles: user.roles,
+        }
+    }
+}
+
+///
+/// Users connection fields, in addition to the edges and the page info.
+#[derive(Clone, Copy, Debug, SimpleObject)]
+pub struct UsersConnectionFields {
+    /// Total number of matching users (only loaded if selected)
+    pub total: Option<ListTotal>,
+}
+
+/// Users connection (Relay style), the cursors being the ones of the REST
+/// users list.
+pub type UsersConnection = Connection<String, UserNode, UsersConnectionFields>;
+
+///
+/// Users list filters, combined with AND (see [ListRequest]).
+#[derive(Debug, Default, InputObject)]
+pub struct UsersFilter {
+    /// Case-insensitive email prefix (admins only)
+    pub email_prefix: Option<String>,
+    /// Case-insensitive name prefix
+    pub name_prefix: Option<String>,
+    /// Lower bound of the user creation datetime (not inclusive)
+    pub created_after: Option<chrono::NaiveDateTime>,
+    /// Upper bound of the user creation datetime (not inclusive)
+    pub created_before: Option<chrono::NaiveDateTime>,
+    /// Role granted to the user (admins only)
+    pub role: Option<String>,
+    /// User status (default: active; deleted users are listed to admins only)
+    pub status: Option<UserStatus>,
+}
+
+///
+/// Profile update of the authorized user (see [UserPatch]).
+///
+/// Absent fields are left untouched.
+#[derive(Debug, InputObject)]
+pub struct ProfileInput {
+    /// User name
+    pub name: Option<String>,
+    /// Whether the user is shown to other users in lists and search results
+    pub listed: Option<bool>,
+}
+
+/// Query root.
+#[derive(Clone, Copy, Debug)]
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// The authorized user
+    async fn me(&self, ctx: &Context<'_>) -> async_graphql::Result<UserNode> {
+        let user_id = identity(ctx)?.user_id;
+        let db = db(ctx);
+        let user = request_id::block(move || User::find_by_id(user_id, db))
+            .await
+            .map_err(ApiError::from)
+            .and_then(|user| user)
+            .map_err(api_error)?;
+
+        Ok(OutputUser::for_owner(user).into())
+    }
+
+    /// User by id, rendered for the caller (null if there is no such user)
+    async fn user(&self, ctx: &Context<'_>, id: i32) -> async_graphql::Result<Option<UserNode>> {
+        let caller = identity(ctx)?;
+        let db = db(ctx);
+        let user = request_id::block(move || User::find_by_id(id, db))
+            .await
+            .map_err(ApiError::from)
+            .and_then(|user| user);
+
+        match user {
+            Ok(user) => Ok(Some(OutputUser::for_caller(user, caller).into())),
+            Err(ApiError::NotFound {}) => Ok(None),
+            Err(e) => Err(api_error(e)),
+        }
+    }
+
+    /// Registered users, paginated with cursors (see GET /users).
+    ///
+    /// Either `first` (along with `after`, if any) or `last` along with
+    /// `before` must be set.
+    #[graphql(complexity = "page_size(first, last) * child_complexity")]
+    #[allow(clippy::too_many_arguments)]
+    async fn users(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<String>,
+        last: Option<i32>,
+        before: Option<String>,
+        filter: Option<UsersFilter>,
+        sort: Option<SortOrder>,
+    ) -> async_graphql::Result<UsersConnection> {
+        let caller = identity(ctx)?;
+        let cfg = cfg(ctx);
+        let limit = match (first, last) {
+            (Some(_), Some(_)) => Err(field_error("last", "conflicts_with_first")),
+            (None, None) => Err(field_error("first", "required")),
+            (Some(_), None) if before.is_some() => {
+                Err(field_error("before", "conflicts_with_first"))
+            }
+            (None, Some(_)) if after.is_some() => Err(field_error("after", "conflicts_with_last")),
+            (None, Some(_)) if before.is_none() => Err(field_error("before", "required")),
+            (Some(limit), None) | (None, Some(limit)) => Ok(limit),
+        };
+        let limit = limit.map_err(|e| api_error(e.into()))?;
+
+        let look_ahead = ctx.look_ahead();
+        let node = look_ahead.field("edges").field("node");
+        let nodes = look_ahead.field("nodes");
+        // Roles of other users are listed to admins only
+        let expand_roles =
+            caller.is_admin() && (node.field("roles").exists() || nodes.field("roles").exists());
+        let filter = filter.unwrap_or_default();
+        let query = ListRequest {
+            limit: Some(limit),
+            include_total: Some(look_ahead.field("total").exists()),
+            after,
+            before,
+            sort,
+            email_prefix: filter.email_prefix,
+            name_prefix: filter.name_prefix,
+            created_after: filter.created_after,
+            created_before: filter.created_before,
+            role: filter.role,
+            status: filter.status,
+            fields: None,
+            expand: expand_roles.then(|| "roles".to_string()),
+        };
+
+        let listing = list_users(db(ctx), cfg, caller, query)
+            .await
+            .map_err(api_error)?;
+        let mut connection = UsersConnection::with_additional_fields(
+            listing.response.prev_cursor.is_some(),
+            listing.response.next_cursor.is_some(),
+            UsersConnectionFields {
+                total: listing.response.total,
+            },
+        );
+        connection.edges = listing
+            .cursors
+            .into_iter()
+            .zip(listing.response.users)
+            .map(|(cursor, user)| Edge::new(cursor, user.into()))
+            .collect();
+        Ok(connection)
+    }
+}
+
+/// Mutation root.
+#[derive(Clone, Copy, Debug)]
+pub struct Mutation;
+
+#[Object]
+impl Mutation {
+    /// Registers a new user (see POST /user). Available to anonymous clients.
+    async fn register(
+        &self,
+        ctx: &Context<'_>,
+        input: InputUser,
+    ) -> async_graphql::Result<UserNode> {
+        let user = register_single_user(db(ctx), &cfg(ctx).passwords, input)
+            .await
+            .map_err(api_error)?;
+
+        // New users are granted no roles
+        Ok(UserNode {
+            roles: Some(vec![]),
+            ..OutputUser::for_owner(user).into()
+        })
+    }
+
+    /// Updates the authorized user profile (see PATCH /user/me).
+    ///
+    /// The update is unconditional: use the REST endpoint to update the
+    /// profile only if it was not modified concurrently.
+    async fn update_profile(
+        &self,
+        ctx: &Context<'_>,
+        input: ProfileInput,
+    ) -> async_graphql::Result<UserNode> {
+        let user_id = identity(ctx)?.user_id;
+        let patch = UserPatch {
+            name: input.name.map(Some),
+            listed: input.listed.map(Some),
+        };
+        let user = update_user_profile(db(ctx), user_id, IfMatch::Any, patch)
+            .await
+            .map_err(api_error)?;
+
+        Ok(OutputUser::for_owner(user).into())
+    }
+}
+
+/// Returns the page size of the connection (for the complexity calculation).
+fn page_size(first: Option<i32>, last: Option<i32>) -> usize {
+    first.or(last).unwrap_or_default().max(1) as usize
+}
+
+/// Returns the database pool of the request.
+fn db(ctx: &Context<'_>) -> web::Data<DbPool> {
+    ctx.data_unchecked::<web::Data<DbPool>>().clone()
+}
+
+/// Returns the server configuration.
+fn cfg(ctx: &Context<'_>) -> &'static ServerConfig {
+    ctx.data_unchecked::<&'static ServerConfig>()
+}
+
+/// Returns the caller identity, failing if the request is anonymous.
+fn identity<'a>(ctx: &Context<'a>) -> async_graphql::Result<&'a Identity> {
+    ctx.data_opt::<Identity>()
+        .ok_or_else(|| problem_error(ProblemDetails::new(ErrorCode::Unauthorized)))
+}
+
+/// Converts the API error into the GraphQL one (see [problem_error]).
+fn api_error(e: ApiError) -> Error {
+    let code = e.code();
+    if code == ErrorCode::InternalError || code == ErrorCode::ServiceUnavailable {
+        log::error!("Responding a GraphQL error due to error: {}", e);
+    }
+    problem_error(ProblemDetails::from(e))
+}
+
+/// Converts the problem details into the GraphQL error: the detail (or the
+/// title) becomes the message, and the rest goes to the extensions.
+fn problem_error(mut problem: ProblemDetails) -> Error {
+    problem.request_id = RequestId::current().map(|id| id.0);
+    let message = problem.detail.clone().unwrap_or(problem.title.clone());
+
+    Error::new(message).extend_with(|_, extensions| {
+        extensions.set("code", problem.code.as_str());
+        extensions.set("status", problem.status);
+        if let Some(request_id) = problem.request_id {
+            extensions.set("request_id", request_id);
+        }
+        if !problem.errors.is_empty() {
+            if let Ok(errors) = async_graphql::to_value(&problem.errors) {
+                extensions.set("errors", errors);
+            }
+        }
+    })
+}
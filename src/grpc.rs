@@ -0,0 +1,423 @@
+//!
+//! Module contains the gRPC API, served alongside the REST one on its own
+//! port (see [GrpcConfig]), for internal services.
+//!
+//! Services are defined in `proto/na.proto` (see [proto]): token issuance and
+//! validation ([AuthService]), and user registration, lookup and listing
+//! ([UsersService]). They share the REST handlers logic and [DbPool], so both
+//! APIs apply the same validation, visibility and pagination rules.
+//!
+//! Calls are authorized with the same bearer tokens as the REST API, passed
+//! with the `authorization` metadata. Token validation is reserved to the
+//! clients listed in `oauth.clients`, authenticated the same way as for the
+//! token introspection (see [ClientInterceptor]). Failures are mapped to the
+//! gRPC status codes, with the [ErrorCode] passed as the `error-code`
+//! metadata.
+//!
+//! [GrpcConfig]: crate::config::GrpcConfig
+
+use std::str::FromStr;
+
+use actix_web::web;
+use tonic::metadata::MetadataValue;
+use tonic::service::Interceptor;
+use tonic::{Code, Request, Response, Status};
+
+use crate::config::ServerConfig;
+use crate::errors::{ApiError, ErrorCode, ProblemDetails};
+use crate::handlers::auth::{authenticate_user, generate_jwt_token, TokenCreateRequest};
+use crate::handlers::oauth::{authenticate_client, parse_basic_credentials};
+use crate::handlers::user::{register_single_user, InputUser};
+use crate::handlers::users::{list_users, ListRequest, ListedUser, SortOrder, UserStatus};
+use crate::handlers::OutputUser;
+use crate::middleware::jwt::{verify_token, Identity};
+use crate::middleware::request_id;
+use crate::models::User;
+use crate::DbPool;
+
+/// Code generated from `proto/na.proto` (package `na.v1`): messages, along
+/// with the servers and the clients of the services.
+#[allow(missing_docs, unused_results, missing_copy_implementations)]
+pub mod proto {
+    tonic::include_proto!("na.v1");
+}
+
+use proto::auth_server::{Auth, AuthServer};
+use proto::users_server::{Users, UsersServer};
+
+/// Name of the metadata carrying the [ErrorCode] of the failed calls.
+pub const ERROR_CODE: &str = "error-code";
+
+///
+/// Serves the gRPC services on the given listener, until it fails.
+///
+/// Connections are handled on the current runtime, while the database calls
+/// run on the blocking threads pool (as the REST handlers' do).
+pub async fn serve(
+    listener: tokio::net::TcpListener,
+    db: web::Data<DbPool>,
+    cfg: &'static ServerConfig,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let incoming = tonic::transport::server::TcpIncoming::from_listener(listener, true, None)?;
+
+    let auth = AuthService {
+        db: db.clone(),
+        cfg,
+    };
+    tonic::transport::Server::builder()
+        .add_service(AuthServer::with_interceptor(
+            auth,
+            ClientInterceptor { cfg },
+        ))
+        .add_service(UsersServer::new(UsersService { db, cfg }))
+        .serve_with_incoming(incoming)
+        .await?;
+    Ok(())
+}
+
+///
+/// Authenticates the clients calling with the credentials of a client listed
+/// in `oauth.clients`, passed with the `authorization` metadata (HTTP Basic
+/// authentication, as for the token introspection):
+///
+///   authorization: Basic <base64 of client_id:client_secret>
+///
+/// Authenticated calls are marked with [AuthenticatedClient], while the calls
+/// with invalid credentials are rejected with `UNAUTHENTICATED`. Calls
+/// without the credentials are passed as is: whether they are required is up
+/// to the method.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientInterceptor {
+    cfg: &'static ServerConfig,
+}
+
+/// Request extension marking the calls made by an authenticated client (see
+/// [ClientInterceptor]).
+#[derive(Clone, Debug)]
+pub struct AuthenticatedClient {
+    /// Client identifier.
+    pub client_id: String,
+}
+
+impl Interceptor for ClientInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let Some(authorization) = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| value.starts_with("Basic "))
+        else {
+            return Ok(request);
+        };
+
+        match parse_basic_credentials(authorization) {
+            Some((client_id, client_secret))
+                if authenticate_client(&self.cfg.oauth, &client_id, &client_secret) =>
+            {
+                let _ = request
+                    .extensions_mut()
+                    .insert(AuthenticatedClient { client_id });
+                Ok(request)
+            }
+            _ => Err(problem_status(ProblemDetails::new(
+                ErrorCode::InvalidClient,
+            ))),
+        }
+    }
+}
+
+///
+/// Auth service: access tokens issuance and validation.
+#[derive(Clone, Debug)]
+pub struct AuthService {
+    db: web::Data<DbPool>,
+    cfg: &'static ServerConfig,
+}
+
+#[tonic::async_trait]
+impl Auth for AuthService {
+    async fn create_token(
+        &self,
+        request: Request<proto::CreateTokenRequest>,
+    ) -> Result<Response<proto::CreateTokenResponse>, Status> {
+        let request = request.into_inner();
+        let credentials = TokenCreateRequest {
+            email: request.email,
+            password: request.password,
+        };
+        let user = authenticate_user(self.db.clone(), &self.cfg.passwords, credentials)
+            .await
+            .map_err(status)?;
+        let token = generate_jwt_token(&user, &self.cfg.jwt).map_err(status)?;
+
+        Ok(Response::new(proto::CreateTokenResponse { token }))
+    }
+
+    async fn validate_token(
+        &self,
+        request: Request<proto::ValidateTokenRequest>,
+    ) -> Result<Response<proto::ValidateTokenResponse>, Status> {
+        if request.extensions().get::<AuthenticatedClient>().is_none() {
+            return Err(problem_status(ProblemDetails::new(
+                ErrorCode::InvalidClient,
+            )));
+        }
+        let token = request.into_inner().token;
+        match verify_token(&token, &self.cfg.jwt, self.db.clone()).await {
+            Ok(Some((claims, identity))) => Ok(Response::new(proto::ValidateTokenResponse {
+                valid: true,
+                user_id: identity.user_id,
+                email: identity.email,
+                roles: identity.roles,
+                expires_at: claims.exp.try_into().unwrap_or(i64::MAX),
+            })),
+            Ok(None) => Ok(Response::new(proto::ValidateTokenResponse::default())),
+            Err(e) => Err(status(e)),
+        }
+    }
+}
+
+///
+/// Users service: registration, lookup and listing.
+#[derive(Clone, Debug)]
+pub struct UsersService {
+    db: web::Data<DbPool>,
+    cfg: &'static ServerConfig,
+}
+
+impl UsersService {
+    /// Returns the identity of the authorized caller, failing with
+    /// `UNAUTHENTICATED` if the bearer token is missing or invalid.
+    async fn identity<T>(&self, request: &Request<T>) -> Result<Identity, Status> {
+        let unauthorized = || problem_status(ProblemDetails::new(ErrorCode::Unauthorized));
+        let token = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(unauthorized)?;
+
+        match verify_token(token, &self.cfg.jwt, self.db.clone()).await {
+            Ok(Some((_, identity))) => Ok(identity),
+            Ok(None) => Err(unauthorized()),
+            Err(e) => Err(status(e)),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Users for UsersService {
+    async fn register(
+        &self,
+        request: Request<proto::RegisterRequest>,
+    ) -> Result<Response<proto::User>, Status> {
+        let request = request.into_inner();
+        let item = InputUser {
+            name: request.name,
+            email: request.email,
+            password: request.password,
+        };
+        let user = register_single_user(self.db.clone(), &self.cfg.passwords, item)
+            .await
+            .map_err(status)?;
+
+        Ok(Response::new(OutputUser::for_owner(user).into()))
+    }
+
+    async fn get_me(
+        &self,
+        request: Request<proto::GetMeRequest>,
+    ) -> Result<Response<proto::User>, Status> {
+        let user_id = self.identity(&request).await?.user_id;
+        let db = self.db.clone();
+        let user = request_id::block(move || User::find_by_id(user_id, db))
+            .await
+            .map_err(ApiError::from)
+            .and_then(|user| user)
+            .map_err(status)?;
+
+        Ok(Response::new(OutputUser::for_owner(user).into()))
+    }
+
+    async fn get_user(
+        &self,
+        request: Request<proto::GetUserRequest>,
+    ) -> Result<Response<proto::User>, Status> {
+        let caller = self.identity(&request).await?;
+        let user_id = request.into_inner().id;
+        let db = self.db.clone();
+        let user = request_id::block(move || User::find_by_id(user_id, db))
+            .await
+            .map_err(ApiError::from)
+            .and_then(|user| user)
+            .map_err(status)?;
+
+        Ok(Response::new(OutputUser::for_caller(user, &caller).into()))
+    }
+
+    async fn list_users(
+        &self,
+        request: Request<proto::ListUsersRequest>,
+    ) -> Result<Response<proto::ListUsersResponse>, Status> {
+        let caller = self.identity(&request).await?;
+        let request = request.into_inner();
+        let created_after = request
+            .created_after
+            .as_deref()
+            .map(chrono::NaiveDateTime::from_str)
+            .transpose()
+            .map_err(|_| malformed_datetime("created_after"))?;
+        let created_before = request
+            .created_before
+            .as_deref()
+            .map(chrono::NaiveDateTime::from_str)
+            .transpose()
+            .map_err(|_| malformed_datetime("created_before"))?;
+        let query = ListRequest {
+            limit: request.limit,
+            include_total: Some(request.include_total),
+            sort: Some(request.sort().into()),
+            status: Some(request.status().into()),
+            expand: request.include_roles.then(|| "roles".to_string()),
+            after: request.after,
+            before: request.before,
+            email_prefix: request.email_prefix,
+            name_prefix: request.name_prefix,
+            role: request.role,
+            created_after,
+            created_before,
+            ..Default::default()
+        };
+
+        let listing = list_users(self.db.clone(), self.cfg, &caller, query)
+            .await
+            .map_err(status)?;
+        Ok(Response::new(proto::ListUsersResponse {
+            users: listing.response.users.into_iter().map(Into::into).collect(),
+            next_cursor: listing.response.next_cursor,
+            prev_cursor: listing.response.prev_cursor,
+            total: listing.response.total.map(|total| proto::UsersTotal {
+                count: total.count,
+                estimated: total.estimated,
+            }),
+        }))
+    }
+}
+
+impl From<OutputUser> for proto::User {
+    fn from(user: OutputUser) -> Self {
+        Self {
+            id: user.id,
+            email: user.email,
+            name: user.name,
+            created_at: format_datetime(user.created_at),
+            listed: user.listed,
+            roles: Vec::new(),
+        }
+    }
+}
+
+impl From<ListedUser> for proto::User {
+    /// Converts the listed user, fetched with all the fields (see
+    /// [ListRequest::fields]).
+    fn from(user: ListedUser) -> Self {
+        Self {
+            id: user.id,
+            email: user.email.unwrap_or_default(),
+            name: user.name.unwrap_or_default(),
+            created_at: user.created_at.map(format_datetime).unwrap_or_default(),
+            listed: user.listed,
+            roles: user.roles.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<proto::UserSort> for SortOrder {
+    fn from(sort: proto::UserSort) -> Self {
+        match sort {
+            proto::UserSort::IdAsc => Self::IdAsc,
+            proto::UserSort::IdDesc => Self::IdDesc,
+            proto::UserSort::NameAsc => Self::NameAsc,
+            proto::UserSort::NameDesc => Self::NameDesc,
+            proto::UserSort::CreatedAtAsc => Self::CreatedAtAsc,
+            proto::UserSort::CreatedAtDesc => Self::CreatedAtDesc,
+        }
+    }
+}
+
+impl From<proto::UserStatus> for UserStatus {
+    fn from(status: proto::UserStatus) -> Self {
+        match status {
+            proto::UserStatus::Active => Self::Active,
+            proto::UserStatus::Deleted => Self::Deleted,
+        }
+    }
+}
+
+/// Formats the datetime the same way the REST API renders it.
+fn format_datetime(datetime: chrono::NaiveDateTime) -> String {
+    datetime.format("%Y-%m-%dT%H:%M:%S%.f").to_string()
+}
+
+/// Returns the status rejecting the malformed datetime field (datetimes are
+/// formatted the same way the REST API accepts them).
+fn malformed_datetime(field: &str) -> Status {
+    problem_status(
+        ProblemDetails::new(ErrorCode::MalformedRequest)
+            .with_detail(format!("{field} must be an ISO 8601 datetime")),
+    )
+}
+
+/// Converts the API error into the gRPC status (see [problem_status]).
+fn status(e: ApiError) -> Status {
+    let code = e.code();
+    if code == ErrorCode::InternalError || code == ErrorCode::ServiceUnavailable {
+        log::error!("Responding a gRPC error due to error: {}", e);
+    }
+    problem_status(ProblemDetails::from(e))
+}
+
+/// Converts the problem details into the gRPC status: the detail (or the
+/// title, along with the failed validations) becomes the message, and the
+/// [ErrorCode] is passed as the [ERROR_CODE] metadata.
+fn problem_status(problem: ProblemDetails) -> Status {
+    let mut message = problem.detail.unwrap_or(problem.title);
+    if !problem.errors.is_empty() {
+        let fields: Vec<_> = problem
+            .errors
+            .iter()
+            .map(|error| format!("{} ({})", error.field, error.code))
+            .collect();
+        message = format!("{}: {}", message, fields.join(", "));
+    }
+
+    let mut status = Status::new(grpc_code(problem.code), message);
+    let _ = status.metadata_mut().insert(
+        ERROR_CODE,
+        MetadataValue::from_static(problem.code.as_str()),
+    );
+    status
+}
+
+/// Returns the gRPC status code corresponding to the error code.
+fn grpc_code(code: ErrorCode) -> Code {
+    match code {
+        ErrorCode::MalformedRequest | ErrorCode::ValidationFailed => Code::InvalidArgument,
+        ErrorCode::InvalidCredentials | ErrorCode::Unauthorized | ErrorCode::InvalidClient => {
+            Code::Unauthenticated
+        }
+        ErrorCode::Forbidden => Code::PermissionDenied,
+        ErrorCode::NotFound => Code::NotFound,
+        ErrorCode::Conflict => Code::AlreadyExists,
+        ErrorCode::IdempotencyKeyInUse => Code::Aborted,
+        ErrorCode::ConstraintViolation
+        | ErrorCode::IdempotencyKeyReused
+        | ErrorCode::PreconditionFailed
+        | ErrorCode::PreconditionRequired => Code::FailedPrecondition,
+        ErrorCode::MethodNotAllowed => Code::Unimplemented,
+        ErrorCode::NotAcceptable | ErrorCode::UnsupportedMediaType => Code::InvalidArgument,
+        ErrorCode::PayloadTooLarge => Code::ResourceExhausted,
+        ErrorCode::ServiceUnavailable => Code::Unavailable,
+        ErrorCode::InternalError => Code::Internal,
+    }
+}
@@ -3,37 +3,42 @@
 
 use std::borrow::Borrow;
 
-use actix_web::{web, HttpResponse};
-use argon2::{Argon2, PasswordVerifier};
+use actix_web::{http::header, web, HttpResponse};
 use chrono::Utc;
 use jsonwebtoken::{encode, EncodingKey, Header};
 
 use crate::{
-    config::{JwtConfig, ServerConfig},
-    errors::ApiError,
-    middleware::jwt::Claims,
+    config::{JwtConfig, PasswordsConfig, ServerConfig},
+    encoding::{Encoded, EncodedResponse, Encoding},
+    errors::{ApiError, ProblemDetails},
+    middleware::{jwt::Claims, request_id},
+    passwords::{self, Verification},
     schema::users::dsl::*,
+    validation::PASSWORD_MAX_LENGTH,
     DbPool,
 };
 use diesel::prelude::*;
+use validator::Validate;
 
 use super::User;
 
 ///
 /// Token create request representation.
 ///
-/// All fields are optional.
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
+/// Both fields are required.
+#[derive(Debug, serde::Deserialize, serde::Serialize, Validate, utoipa::ToSchema)]
 pub struct TokenCreateRequest {
     /// Corresponds to the same field in [User] struct.
+    #[validate(email)]
     pub email: String,
     /// Corresponds to the same field in [User] struct.
+    #[validate(length(min = 1, max = "PASSWORD_MAX_LENGTH"))]
     pub password: String,
 }
 
 ///
 /// Token create response representation.
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
 pub struct TokenCreateResponse {
     /// JWT token to be used within `Authorization` HTTP header.
     pub token: String,
@@ -48,23 +53,47 @@ pub struct TokenCreateResponse {
 ///
 /// Returns a JWT auth token.
 ///
+/// Legacy password hashes (see [crate::passwords]) are transparently
+/// upgraded to the configured Argon2 on successful authentication.
+///
+/// The response carries a credential, so it is never cached. Neither is it
+/// stored to be replayed: with the `Idempotency-Key` header (see
+/// [crate::middleware::idempotency]), a duplicate sent while the request is
+/// in progress is rejected, and the retries are handled again.
+///
 /// Example:
-/// POST /auth/token
+/// POST /v1/auth/token
 /// {
 ///   "email": "john@example.org",
-///   "password": "secr3t"
+///   "password": "secr3t-pass"
 /// }
 ///
 /// Returns
 /// {
 ///     "token": "eyJ0e...xb26ww"
 /// }
+#[utoipa::path(
+    post,
+    path = "/v1/auth/token",
+    operation_id = "create_token",
+    summary = "Create an authorization token",
+    tag = "auth",
+    request_body = TokenCreateRequest,
+    params(("Idempotency-Key" = Option<String>, Header, description = "Unique key rejecting the concurrent duplicates (see crate::middleware::idempotency)")),
+    responses(
+        (status = 201, description = "Token created", body = TokenCreateResponse),
+        (status = 400, description = "Invalid credentials", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "Request with the same idempotency key in progress", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Validation failed", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
 pub async fn token(
     db: web::Data<DbPool>,
     cfg: web::Data<&'static ServerConfig>,
-    credentials: web::Json<TokenCreateRequest>,
+    encoding: Encoding,
+    credentials: Encoded<TokenCreateRequest>,
 ) -> web::Either<HttpResponse, ApiError> {
-    let user = match authenticate_user(db, credentials.into_inner()).await {
+    let user = match authenticate_user(db, &cfg.passwords, credentials.into_inner()).await {
         Ok(user) => user,
         Err(e) => return web::Either::Right(e),
     };
@@ -72,34 +101,73 @@ pub async fn token(
         Ok(token) => token,
         Err(e) => return web::Either::Right(e),
     };
-    web::Either::Left(HttpResponse::Created().json(TokenCreateResponse { token }))
+    web::Either::Left(
+        HttpResponse::Created()
+            .insert_header(header::CacheControl(vec![header::CacheDirective::NoStore]))
+            .encoded(encoding, TokenCreateResponse { token }),
+    )
 }
 
-async fn authenticate_user(
+/// Validates the credentials, and returns the user they belong to.
+pub(crate) async fn authenticate_user(
     db: web::Data<DbPool>,
+    cfg: &'static PasswordsConfig,
     credentials: TokenCreateRequest,
 ) -> Result<User, ApiError> {
-    let user = web::block(move || -> Result<User, ApiError> {
-        let mut conn = db.get()?;
+    credentials.validate()?;
+
+    let conn_pool = db.clone();
+    let user = request_id::block(move || -> Result<User, ApiError> {
+        let mut conn = conn_pool.get()?;
         users
             .filter(email.eq(credentials.email))
+            .filter(deleted_at.is_null())
             .first::<User>(&mut conn)
             .map_err(|_| ApiError::InvalidCredentials {})
     })
     .await??;
 
-    let argon2 = Argon2::default();
-    let parsed_hash = argon2::PasswordHash::new(&user.hashed_password)
-        .map_err(|_| ApiError::InvalidCredentials {})?;
-
-    argon2
-        .verify_password(credentials.password.as_bytes(), &parsed_hash)
-        .map_err(|_| ApiError::InvalidCredentials {})?;
+    // Verification is CPU-intensive, especially for legacy schemes
+    request_id::block(move || -> Result<User, ApiError> {
+        match passwords::verify(&credentials.password, &user.hashed_password, cfg) {
+            Verification::Invalid => Err(ApiError::InvalidCredentials {}),
+            Verification::Valid => Ok(user),
+            Verification::Outdated => {
+                upgrade_password_hash(&user, &credentials.password, cfg, db);
+                Ok(user)
+            }
+        }
+    })
+    .await?
+}
 
-    Ok(user)
+/// Replaces the outdated password hash of the user with a new one.
+///
+/// Failures are logged only: the user is authenticated anyway, and the
+/// upgrade is retried on the next login.
+fn upgrade_password_hash(
+    user: &User,
+    password: &str,
+    cfg: &PasswordsConfig,
+    db: web::Data<DbPool>,
+) {
+    let upgraded = passwords::hash(password, cfg).and_then(|new_hash| {
+        User::replace_password_hash(user.id, &user.hashed_password, &new_hash, db)
+    });
+    match upgraded {
+        Ok(true) => log::info!("Upgraded the password hash of user {}", user.id),
+        // The password was changed concurrently
+        Ok(false) => {}
+        Err(e) => log::warn!(
+            "Cannot upgrade the password hash of user {}: {}",
+            user.id,
+            e
+        ),
+    }
 }
 
-fn generate_jwt_token(user: &User, jwt_cfg: &JwtConfig) -> Result<String, ApiError> {
+/// Issues the access token to the user, valid for 24 hours.
+pub(crate) fn generate_jwt_token(user: &User, jwt_cfg: &JwtConfig) -> Result<String, ApiError> {
     let expiration = Utc::now()
         .checked_add_signed(chrono::Duration::hours(24))
         .expect("Valid timestamp")
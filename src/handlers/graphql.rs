@@ -0,0 +1,105 @@
+//!
+//! Handler for serving the GraphQL endpoint (see [crate::graphql]).
+//!
+//! Relies on JWT middleware (with optional authorization), so both the
+//! authorized and anonymous clients are served.
+
+use actix_web::http::header;
+use actix_web::{web, HttpResponse};
+
+use crate::{
+    config::ServerConfig,
+    encoding::{Encoded, EncodedResponse, Encoding},
+    errors::ProblemDetails,
+    graphql::UsersSchema,
+    middleware::jwt::Identity,
+    DbPool,
+};
+
+///
+/// GraphQL endpoint.
+///
+/// Accepts the GraphQL request: `query`, along with `variables` and
+/// `operationName` (optional). Executes it on behalf of the authorized user,
+/// if any (see the `Authorization` header).
+///
+/// Responds with 200 OK, listing the errors (if any) within the response;
+/// see [crate::graphql] for the schema and the errors extensions.
+///
+/// The request may be retried safely with the `Idempotency-Key` header (see
+/// [crate::middleware::idempotency]), e.g. to register. Responses reporting
+/// server-side failures are marked `Cache-Control: no-store`, so they are
+/// not replayed to the retries.
+///
+/// Example:
+/// POST /v1/graphql
+/// Authorization: Bearer [token]
+/// {
+///   "query": "{ me { id name roles } users(first: 2) { edges { cursor node { id name } } pageInfo { hasNextPage endCursor } } }"
+/// }
+///
+/// Returns
+/// {
+///   "data": {
+///     "me": { "id": 9, "name": "john", "roles": [] },
+///     "users": {
+///       "edges": [
+///         { "cursor": "eyJzb...Zk", "node": { "id": 1, "name": "Joseph" } },
+///         { "cursor": "eyJzb...Qw", "node": { "id": 2, "name": "John" } }
+///       ],
+///       "pageInfo": { "hasNextPage": true, "endCursor": "eyJzb...Qw" }
+///     }
+///   }
+/// }
+#[utoipa::path(
+    post,
+    path = "/v1/graphql",
+    operation_id = "graphql",
+    summary = "Execute a GraphQL request",
+    tag = "graphql",
+    request_body(content = Object, description = "GraphQL request (`query`, `variables`, `operationName`)"),
+    security((), ("bearer_auth" = [])),
+    params(("Idempotency-Key" = Option<String>, Header, description = "Unique key making the retries safe (see crate::middleware::idempotency)")),
+    responses(
+        (status = 200, description = "GraphQL response (`data` and `errors`)", body = Object),
+        (status = 409, description = "Request with the same idempotency key in progress", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Idempotency key reused with a different request", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+pub async fn graphql(
+    schema: web::Data<UsersSchema>,
+    db: web::Data<DbPool>,
+    cfg: web::Data<&'static ServerConfig>,
+    identity: Option<web::ReqData<Identity>>,
+    encoding: Encoding,
+    request: Encoded<async_graphql::Request>,
+) -> HttpResponse {
+    let mut request = request.into_inner().data(db).data(*cfg.get_ref());
+    if let Some(identity) = identity {
+        request = request.data(identity.into_inner());
+    }
+
+    let response = schema.execute(request).await;
+    let no_store = is_server_error(&response);
+    let mut resp = HttpResponse::Ok().encoded(encoding, response);
+    if no_store {
+        let _ = resp.headers_mut().insert(
+            header::CACHE_CONTROL,
+            header::HeaderValue::from_static("no-store"),
+        );
+    }
+    resp
+}
+
+/// Returns `true` if any error of the response is a server-side failure (see
+/// the `status` error extension).
+fn is_server_error(response: &async_graphql::Response) -> bool {
+    response.errors.iter().any(|error| {
+        let status = error
+            .extensions
+            .as_ref()
+            .and_then(|extensions| extensions.get("status"));
+        matches!(status, Some(async_graphql::Value::Number(status))
+            if status.as_u64().is_some_and(|status| status >= 500))
+    })
+}
@@ -0,0 +1,105 @@
+//!
+//! Handler for exposing service metrics.
+//!
+//! Metrics are rendered in the Prometheus text exposition format, and are
+//! computed on each scrape, so there is no state to keep in sync.
+//!
+//! The endpoint does not require authorization (scrapers cannot renew JWT
+//! tokens), so it is served on its own listener (see
+//! [crate::config::MetricsConfig]), which should only be reachable from the
+//! internal network. It is not a part of the API, nor of its specification.
+
+use std::fmt::Write;
+
+use actix_web::{web, HttpResponse};
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Text};
+
+use crate::passwords::HashScheme;
+use crate::{errors::ApiError, middleware::request_id, DbPool};
+
+/// Counts the non-Argon2 password hashes per hash identifier (the part
+/// between the first two `$`). Deleted users are included, since they may
+/// still be restored. Matches the `idx_users_legacy_password_hash` index.
+const LEGACY_HASHES: &str = "\
+    SELECT split_part(hashed_password, '$', 2) AS ident, count(*) AS count \
+    FROM users \
+    WHERE hashed_password NOT LIKE '$argon2%' \
+    GROUP BY 1";
+
+#[derive(QueryableByName)]
+struct HashCount {
+    #[diesel(sql_type = Text)]
+    ident: String,
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+}
+
+///
+/// Metrics endpoint.
+///
+/// Returns the following metrics:
+/// - na_legacy_password_hashes: number of users with a legacy password hash
+///   (see [crate::passwords]), per scheme. Such hashes are upgraded on login,
+///   so the gauge is expected to go down over time.
+///
+/// Example:
+/// GET /metrics
+///
+/// Returns
+/// # HELP na_legacy_password_hashes Number of users with a legacy password hash.
+/// # TYPE na_legacy_password_hashes gauge
+/// na_legacy_password_hashes{scheme="bcrypt"} 12
+/// na_legacy_password_hashes{scheme="scrypt"} 0
+/// na_legacy_password_hashes{scheme="pbkdf2"} 3
+/// na_legacy_password_hashes{scheme="unknown"} 0
+pub async fn metrics(db: web::Data<DbPool>) -> web::Either<HttpResponse, ApiError> {
+    let counts = match request_id::block(move || count_legacy_hashes(db)).await {
+        Ok(Ok(counts)) => counts,
+        Ok(Err(e)) => return web::Either::Right(e),
+        Err(e) => return web::Either::Right(e.into()),
+    };
+
+    let mut body = String::new();
+    let _ = writeln!(
+        body,
+        "# HELP na_legacy_password_hashes Number of users with a legacy password hash."
+    );
+    let _ = writeln!(body, "# TYPE na_legacy_password_hashes gauge");
+    for (scheme, count) in counts {
+        let _ = writeln!(
+            body,
+            "na_legacy_password_hashes{{scheme=\"{scheme}\"}} {count}"
+        );
+    }
+
+    web::Either::Left(
+        HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4; charset=utf-8")
+            .body(body),
+    )
+}
+
+/// Counts the legacy password hashes per scheme, reporting every legacy
+/// scheme (even with no hashes left), and unrecognized hashes as `unknown`.
+/// Executes a database query, so it must be wrapped with actix' `web::block`.
+fn count_legacy_hashes(db: web::Data<DbPool>) -> Result<Vec<(&'static str, i64)>, ApiError> {
+    let mut conn = db.get()?;
+    let hash_counts = diesel::sql_query(LEGACY_HASHES).load::<HashCount>(&mut conn)?;
+
+    let mut counts: Vec<(&'static str, i64)> = HashScheme::LEGACY
+        .iter()
+        .map(|scheme| (scheme.name(), 0))
+        .chain([("unknown", 0)])
+        .collect();
+    for hash_count in hash_counts {
+        let name = match HashScheme::from_ident(&hash_count.ident) {
+            Some(scheme) => scheme.name(),
+            None => "unknown",
+        };
+        if let Some((_, count)) = counts.iter_mut().find(|(n, _)| *n == name) {
+            *count += hash_count.count;
+        }
+    }
+    Ok(counts)
+}
@@ -2,11 +2,20 @@
 //! Contains all REST API handlers.
 
 pub mod auth;
+pub mod graphql;
+pub mod metrics;
+pub mod oauth;
+pub mod openapi;
+pub mod search;
 pub mod user;
 pub mod users;
+pub mod users_export;
+pub mod users_import;
 
+use actix_web::http::header::EntityTag;
 use serde::{Deserialize, Serialize};
 
+use crate::middleware::jwt::Identity;
 use crate::models::*;
 
 ///
@@ -15,26 +24,75 @@ use crate::models::*;
 ///
 /// Omits sensitive fields (password and updated_at).
 ///
-/// Should be created from the database-sourced User struct.
-#[derive(Debug, Serialize, Deserialize)]
+/// Rendering depends on the caller: the user itself and admins see the whole
+/// record (see [OutputUser::for_owner]), while other users see the masked
+/// email and no privacy settings (see [OutputUser::for_caller]).
+#[derive(Debug, Serialize, Deserialize, utoipa::ToSchema)]
 pub struct OutputUser {
     /// User id
     pub id: i32,
-    /// User email
+    /// User email (masked for other users, e.g. `j***@example.org`)
     pub email: String,
     /// User name
     pub name: String,
     /// User creation datetime
     pub created_at: chrono::NaiveDateTime,
+    /// Whether the user is shown to other users in lists and search results
+    /// (only rendered for the user itself and admins)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listed: Option<bool>,
 }
 
-impl From<User> for OutputUser {
-    fn from(user: User) -> Self {
+impl OutputUser {
+    /// Renders the whole user record, for the user itself.
+    pub fn for_owner(user: User) -> Self {
         Self {
             id: user.id,
             email: user.email,
             name: user.name,
             created_at: user.created_at,
+            listed: Some(user.listed),
         }
     }
+
+    /// Renders the user record for the given caller.
+    pub fn for_caller(user: User, caller: &Identity) -> Self {
+        if sees_private(caller, user.id) {
+            return Self::for_owner(user);
+        }
+
+        Self {
+            id: user.id,
+            email: mask_email(&user.email),
+            name: user.name,
+            created_at: user.created_at,
+            listed: None,
+        }
+    }
+}
+
+/// Returns `true` if the caller may see private data of the given user: its
+/// own, or any user's if the caller is an admin.
+fn sees_private(caller: &Identity, user_id: i32) -> bool {
+    caller.user_id == user_id || caller.is_admin()
+}
+
+/// Masks the email, keeping only the first character of the local part and
+/// the domain (e.g. `john@example.org` becomes `j***@example.org`).
+fn mask_email(email: &str) -> String {
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            let first = local.chars().next().map(String::from).unwrap_or_default();
+            format!("{first}***@{domain}")
+        }
+        None => "***".to_string(),
+    }
+}
+
+///
+/// Returns the entity tag identifying the current version of the user record.
+///
+/// Derived from the `updated_at` field, so it changes on every update.
+pub fn user_etag(user: &User) -> EntityTag {
+    EntityTag::new_strong(user.updated_at.and_utc().timestamp_micros().to_string())
 }
@@ -0,0 +1,182 @@
+//!
+//! Handler for the OAuth 2.0 token introspection (RFC 7662), serving the
+//! resource servers which cannot verify the tokens locally.
+//!
+//! The tokens are verified exactly as the JWT middleware does (see
+//! [verify_token]): a token is active only if it is valid, not expired, and
+//! its subject is an existing (non-deleted) user.
+//!
+//! https://datatracker.ietf.org/doc/html/rfc7662
+
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::{OAuthConfig, ServerConfig},
+    errors::{ApiError, ProblemDetails},
+    middleware::jwt::verify_token,
+    DbPool,
+};
+
+/// Client identifier the tokens are issued to: tokens are created by
+/// POST /auth/token for the first-party clients, which are not registered
+/// separately.
+pub const TOKEN_CLIENT_ID: &str = "na";
+
+///
+/// Token introspection request representation (URL-encoded form).
+///
+/// The client credentials are passed either with the `Authorization` header
+/// (HTTP Basic authentication), or with the `client_id` and `client_secret`
+/// fields, but not both (see RFC 6749 2.3.1. Client Password).
+#[derive(Debug, serde::Deserialize, serde::Serialize, utoipa::ToSchema)]
+pub struct IntrospectionRequest {
+    /// Token to introspect.
+    pub token: String,
+    /// Type of the token (optional, ignored: only access tokens are issued).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_type_hint: Option<String>,
+    /// Client identifier, if not passed with the `Authorization` header.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    /// Client secret, if not passed with the `Authorization` header.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+}
+
+///
+/// Token introspection response representation.
+///
+/// Inactive tokens are described with the `active` member only, whatever the
+/// reason is.
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct IntrospectionResponse {
+    /// Whether the token is active.
+    pub active: bool,
+    /// Subject of the token: the email of the user it was issued to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    /// Space-separated names of the roles granted to the subject (tokens are
+    /// not scoped otherwise). Omitted if no roles are granted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// Expiration time (UNIX timestamp).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exp: Option<usize>,
+    /// Client identifier the token was issued to (see [TOKEN_CLIENT_ID]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+}
+
+///
+/// Token introspection endpoint.
+///
+/// Accepts [IntrospectionRequest] as a URL-encoded form, authenticated with
+/// the credentials of a client listed in `oauth.clients`. Responds with
+/// 401 Unauthorized (`invalid_client`) if the client authentication fails.
+///
+/// Returns [IntrospectionResponse]; the response is never cached.
+///
+/// Example:
+/// POST /oauth/introspect
+/// Authorization: Basic ZGV2OmRldg==
+/// Content-Type: application/x-www-form-urlencoded
+///
+/// token=eyJ0e...xb26ww
+///
+/// Returns
+/// {
+///   "active": true,
+///   "sub": "john@example.org",
+///   "scope": "admin",
+///   "exp": 1715941541,
+///   "client_id": "na"
+/// }
+#[utoipa::path(
+    post,
+    path = "/oauth/introspect",
+    operation_id = "introspect_token",
+    summary = "Introspect an access token",
+    tag = "auth",
+    request_body(content = IntrospectionRequest, content_type = "application/x-www-form-urlencoded"),
+    security((), ("client_basic" = [])),
+    responses(
+        (status = 200, description = "Token description", body = IntrospectionResponse),
+        (status = 401, description = "Client authentication failed", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+pub async fn introspect(
+    db: web::Data<DbPool>,
+    cfg: web::Data<&'static ServerConfig>,
+    req: HttpRequest,
+    form: web::Form<IntrospectionRequest>,
+) -> HttpResponse {
+    let form = form.into_inner();
+    let credentials = match (basic_credentials(&req), form.client_id, form.client_secret) {
+        (Some(credentials), None, None) => Some(credentials),
+        (None, Some(client_id), Some(client_secret)) => Some((client_id, client_secret)),
+        _ => None,
+    };
+    let authenticated = credentials.is_some_and(|(client_id, client_secret)| {
+        authenticate_client(&cfg.oauth, &client_id, &client_secret)
+    });
+    if !authenticated {
+        let mut resp = ProblemDetails::from(ApiError::InvalidClient {})
+            .with_request(&req)
+            .into_response();
+        let _ = resp.headers_mut().insert(
+            header::WWW_AUTHENTICATE,
+            header::HeaderValue::from_static("Basic realm=\"na\""),
+        );
+        return resp;
+    }
+
+    let response = match verify_token(&form.token, &cfg.jwt, db).await {
+        Ok(Some((claims, identity))) => IntrospectionResponse {
+            active: true,
+            sub: Some(claims.sub),
+            scope: Some(identity.roles.join(" ")).filter(|scope| !scope.is_empty()),
+            exp: Some(claims.exp),
+            client_id: Some(TOKEN_CLIENT_ID.to_string()),
+        },
+        Ok(None) => IntrospectionResponse::default(),
+        Err(e) => {
+            log::error!("Failed to introspect the token: {}", e);
+            return ProblemDetails::from(e).with_request(&req).into_response();
+        }
+    };
+
+    HttpResponse::Ok()
+        .insert_header(header::CacheControl(vec![header::CacheDirective::NoStore]))
+        .json(response)
+}
+
+/// Extracts the client credentials from the `Authorization` header (HTTP
+/// Basic authentication), if any.
+fn basic_credentials(req: &HttpRequest) -> Option<(String, String)> {
+    parse_basic_credentials(req.headers().get(header::AUTHORIZATION)?.to_str().ok()?)
+}
+
+/// Parses the client credentials from the `Authorization` value of the HTTP
+/// Basic authentication (`Basic <base64 of client_id:client_secret>`).
+pub(crate) fn parse_basic_credentials(authorization: &str) -> Option<(String, String)> {
+    let encoded = authorization.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded).ok()?).ok()?;
+    let (client_id, client_secret) = decoded.split_once(':')?;
+    Some((client_id.to_string(), client_secret.to_string()))
+}
+
+/// Returns `true` if the credentials are the ones of a client listed in
+/// `oauth.clients`.
+///
+/// Secrets are compared by their digests, so the comparison time does not
+/// reveal how much of the secret was guessed.
+pub(crate) fn authenticate_client(cfg: &OAuthConfig, client_id: &str, client_secret: &str) -> bool {
+    cfg.clients.iter().any(|client| {
+        client.client_id == client_id
+            && Sha256::digest(&client.client_secret) == Sha256::digest(client_secret)
+    })
+}
@@ -0,0 +1,110 @@
+//!
+//! Handler for serving the OpenAPI specification of the REST API.
+//!
+//! The specification is derived from the handlers (see the `utoipa::path`
+//! attributes) and the request and response types, so it cannot drift from
+//! the code. The interactive Swagger UI is served at /docs, unless disabled
+//! with `http.docs_ui`.
+
+use std::sync::OnceLock;
+
+use actix_web::HttpResponse;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use super::{auth, graphql, oauth, search, user, users, users_export, users_import};
+
+///
+/// OpenAPI specification of the REST API.
+///
+/// Every route served by the application must be listed in `paths`.
+#[derive(Clone, Copy, Debug, OpenApi)]
+#[openapi(
+    info(
+        title = "na",
+        description = "User accounts REST API. Errors are rendered as RFC 7807 problem details. \
+            Request and response bodies may be encoded with JSON (`application/json`), \
+            MessagePack (`application/msgpack`) or CBOR (`application/cbor`), negotiated \
+            with the `Content-Type` and `Accept` headers."
+    ),
+    paths(
+        user::register,
+        user::me,
+        user::update_me,
+        user::delete_me,
+        user::export_me,
+        user::get,
+        user::delete,
+        user::restore,
+        auth::token,
+        oauth::introspect,
+        users::list,
+        search::search,
+        users_export::export,
+        users_import::import,
+        graphql::graphql,
+        spec,
+    ),
+    modifiers(&BearerAuth),
+    tags(
+        (name = "user", description = "Single user management"),
+        (name = "users", description = "Users lists and bulk operations"),
+        (name = "auth", description = "Authorization tokens"),
+        (name = "graphql", description = "GraphQL endpoint"),
+        (name = "service", description = "Service information"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Registers the JWT bearer security scheme (see /auth/token handler), and
+/// the OAuth clients Basic authentication scheme (see /oauth/introspect
+/// handler).
+#[derive(Clone, Copy, Debug)]
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer_auth",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "client_basic",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Basic).build()),
+        );
+    }
+}
+
+///
+/// OpenAPI specification endpoint.
+///
+/// Returns the OpenAPI 3.1 specification of the REST API (see [ApiDoc]).
+///
+/// Example:
+/// GET /openapi.json
+///
+/// Returns
+/// {
+///   "openapi": "3.1.0",
+///   "info": {"title": "na", ...},
+///   "paths": {"/v1/auth/token": {...}, ...},
+///   "components": {...}
+/// }
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    operation_id = "get_openapi",
+    summary = "Get the OpenAPI specification",
+    tag = "service",
+    responses((status = 200, description = "OpenAPI specification", body = Object))
+)]
+pub async fn spec() -> HttpResponse {
+    static SPEC: OnceLock<utoipa::openapi::OpenApi> = OnceLock::new();
+    HttpResponse::Ok().json(SPEC.get_or_init(ApiDoc::openapi))
+}
@@ -0,0 +1,388 @@
+//!
+//! Handler for handling users search requests.
+//!
+//! Relies on JWT middleware to ensure authorization.
+//!
+//! Users are matched by the full-text search document (`users.search_vector`,
+//! tokens of the query are matched as prefixes), by trigram similarity of the
+//! name or email (so misspelled queries still match), or by a substring of
+//! either. Results are ranked by relevance, with ties broken by id.
+//!
+//! Emails are masked for non-admin callers (see [OutputUser]), so they are
+//! matched by names only, and unlisted users are omitted.
+//!
+//! Pages are navigated with opaque keyset cursors (see [crate::pagination]),
+//! encoding the query along with the rank and the id of the boundary record.
+
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Bool, Float4, Integer, Nullable, Text};
+use validator::Validate;
+
+use crate::config::{PaginationConfig, ServerConfig};
+use crate::encoding::{EncodedResponse, Encoding};
+use crate::pagination::{
+    decode_cursor, encode_cursor, link_header, validate_limit, CursorPaginated, Direction,
+};
+use crate::validation::{field_error, validate_name, NAME_MAX_LENGTH};
+use crate::{
+    errors::{ApiError, ProblemDetails},
+    middleware::{jwt::Identity, request_id},
+    models::User,
+    DbPool,
+};
+
+use super::users::{ListResponse, ListTotal};
+use super::OutputUser;
+
+/// Maximum length of the search cursor, in characters.
+const CURSOR_MAX_LENGTH: u64 = 2048;
+
+/// Users search request representation.
+#[derive(
+    Clone, Debug, Default, serde::Serialize, serde::Deserialize, Validate, utoipa::IntoParams,
+)]
+#[into_params(parameter_in = Query)]
+pub struct SearchRequest {
+    /// Search query: name or email, or parts of them.
+    #[validate(
+        length(min = 1, max = "NAME_MAX_LENGTH"),
+        custom(function = "validate_name")
+    )]
+    pub q: String,
+    /// A number of user records to retrieve (optional, see
+    /// [crate::handlers::users::ListRequest::limit]).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[validate(range(min = 1))]
+    pub limit: Option<i32>,
+    /// Whether to include the exact number of matching records (optional,
+    /// default: false).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_total: Option<bool>,
+    /// Cursor to start the list after (not inclusive, optional).
+    ///
+    /// Use `next_cursor` of the previous page (see [ListResponse]). Cursors
+    /// are only valid for the query they were issued for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[validate(length(max = "CURSOR_MAX_LENGTH"))]
+    pub after: Option<String>,
+    /// Cursor to end the list before (not inclusive, optional).
+    ///
+    /// Use `prev_cursor` of the next page (see [ListResponse]). Cannot be
+    /// combined with `after`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[validate(length(max = "CURSOR_MAX_LENGTH"))]
+    pub before: Option<String>,
+}
+
+impl SearchRequest {
+    /// Decodes the `after`/`before` cursor, ensuring it matches the query.
+    fn cursor(&self, secret: &[u8]) -> Result<Option<(SearchCursor, Direction)>, ApiError> {
+        let (field, raw, direction) = match (&self.after, &self.before) {
+            (None, None) => return Ok(None),
+            (Some(_), Some(_)) => return Err(field_error("before", "conflicts_with_after").into()),
+            (Some(raw), None) => ("after", raw, Direction::After),
+            (None, Some(raw)) => ("before", raw, Direction::Before),
+        };
+
+        match decode_cursor::<SearchCursor>(raw, secret) {
+            Some(cursor) if cursor.q == self.q => Ok(Some((cursor, direction))),
+            Some(_) => Err(field_error(field, "cursor_query_mismatch").into()),
+            None => Err(field_error(field, "invalid_cursor").into()),
+        }
+    }
+}
+
+impl CursorPaginated for SearchRequest {
+    fn with_cursor(&self, direction: Direction, cursor: &str) -> Self {
+        let mut query = self.clone();
+        (query.after, query.before) = match direction {
+            Direction::After => (Some(cursor.to_string()), None),
+            Direction::Before => (None, Some(cursor.to_string())),
+        };
+        query
+    }
+}
+
+///
+/// Cursor payload: the search query, and the rank and the id of the boundary
+/// record.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct SearchCursor {
+    q: String,
+    rank: f32,
+    id: i32,
+}
+
+///
+/// Search registered users endpoint.
+///
+/// Accepts [SearchRequest].
+/// Requires Authorization via JWT (see /auth/token handler).
+/// Returns [ListResponse] with the most relevant users first, along with
+/// RFC 8288 `Link` header containing `next` and `prev` pages links (when
+/// present). Deleted users are never returned.
+///
+/// Users are rendered for the caller (see [OutputUser]): other users' emails
+/// are masked, and unlisted users are omitted, unless the caller is an admin.
+///
+/// Example:
+/// GET /v1/users/search?q=jon%20jonatan&limit=2
+/// Authorization: Bearer [token]
+///
+/// Returns
+/// Link: </v1/users/search?q=jon+jonatan&limit=2&after=eyJxI...Uw>; rel="next"
+/// {
+///   "users": [
+///     {
+///       "created_at": "2024-05-15T19:49:55.314405",
+///       "email": "j***@example.org",
+///       "id": 17,
+///       "name": "John"
+///     },
+///     {
+///       "created_at": "2024-05-15T19:50:05.008961",
+///       "email": "j***@example.org",
+///       "id": 18,
+///       "name": "Jonathan"
+///     }
+///   ],
+///   "next_cursor": "eyJxI...Uw",
+///   "prev_cursor": null
+/// }
+///
+#[utoipa::path(
+    get,
+    path = "/v1/users/search",
+    operation_id = "search_users",
+    summary = "Search registered users by name or email",
+    tag = "users",
+    params(SearchRequest),
+    security(("bearer_auth" = [])),
+    responses(
+        (
+            status = 200,
+            description = "Page of users, most relevant first",
+            body = ListResponse,
+            headers(("Link" = String, description = "`next` and `prev` pages links")),
+        ),
+        (status = 401, description = "Missing or invalid token", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Validation failed", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+pub async fn search(
+    db: web::Data<DbPool>,
+    cfg: web::Data<&'static ServerConfig>,
+    identity: web::ReqData<Identity>,
+    req: HttpRequest,
+    encoding: Encoding,
+    query: web::Query<SearchRequest>,
+) -> web::Either<HttpResponse, ApiError> {
+    let query = query.into_inner();
+    if let Err(e) = query.validate() {
+        return web::Either::Right(e.into());
+    }
+    if let Err(e) = validate_limit(query.limit, &cfg.pagination) {
+        return web::Either::Right(e.into());
+    }
+    let secret = cfg.pagination.cursor_secret.as_bytes();
+    let cursor = match query.cursor(secret) {
+        Ok(cursor) => cursor,
+        Err(e) => return web::Either::Right(e),
+    };
+
+    let caller = identity.into_inner();
+    match search_users(db, &cfg.pagination, &caller, query.clone(), cursor).await {
+        Ok(page) => {
+            let next_cursor = page.next.map(|cursor| encode_cursor(&cursor, secret));
+            let prev_cursor = page.prev.map(|cursor| encode_cursor(&cursor, secret));
+
+            let link = link_header(
+                req.path(),
+                &query,
+                next_cursor.as_deref(),
+                prev_cursor.as_deref(),
+            );
+            let mut response = HttpResponse::Ok().encoded(
+                encoding,
+                ListResponse {
+                    users: page
+                        .users
+                        .into_iter()
+                        .map(|user| OutputUser::for_caller(user, &caller).into())
+                        .collect(),
+                    next_cursor,
+                    prev_cursor,
+                    total: page.total,
+                },
+            );
+            if let Some(link) = link {
+                response.headers_mut().append(header::LINK, link);
+            }
+            web::Either::Left(response)
+        }
+        Err(e) => web::Either::Right(e),
+    }
+}
+
+/// Single page of the search results, along with the adjacent pages cursors
+/// (if those pages exist).
+struct SearchPage {
+    users: Vec<User>,
+    next: Option<SearchCursor>,
+    prev: Option<SearchCursor>,
+    total: Option<ListTotal>,
+}
+
+/// Matching users along with their rank.
+///
+/// Bind parameters: `$1` - the lowercase query, `$2` - the full-text query
+/// (see [ts_query]), `$3` - the substring `LIKE` pattern, `$4` - whether the
+/// caller is an admin, `$5` - the caller id.
+const SEARCH_HITS: &str = "\
+    SELECT users.id, users.email, users.name, users.hashed_password, \
+           users.created_at, users.updated_at, users.deleted_at, users.listed, \
+           (ts_rank(users.search_vector, query) \
+            + greatest(similarity(lower(users.name), $1), \
+                       CASE WHEN $4 THEN similarity(lower(users.email), $1) ELSE 0 END) \
+           )::real AS rank \
+    FROM users, to_tsquery('simple', $2) AS query \
+    WHERE users.deleted_at IS NULL \
+      AND (users.listed OR $4 OR users.id = $5) \
+      AND (($4 AND users.search_vector @@ query) \
+           OR (NOT $4 AND to_tsvector('simple', users.name) @@ query) \
+           OR lower(users.name) % $1 OR ($4 AND lower(users.email) % $1) \
+           OR lower(users.name) LIKE $3 OR ($4 AND lower(users.email) LIKE $3))";
+
+#[derive(QueryableByName)]
+struct SearchHit {
+    #[diesel(embed)]
+    user: User,
+    #[diesel(sql_type = Float4)]
+    rank: f32,
+}
+
+#[derive(QueryableByName)]
+struct SearchCount {
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+}
+
+async fn search_users(
+    db: web::Data<DbPool>,
+    pagination: &'static PaginationConfig,
+    caller: &Identity,
+    request: SearchRequest,
+    cursor: Option<(SearchCursor, Direction)>,
+) -> Result<SearchPage, ApiError> {
+    let limit = request.limit.unwrap_or(pagination.default_page_size) as usize;
+    let direction = cursor
+        .as_ref()
+        .map(|(_, direction)| *direction)
+        .unwrap_or(Direction::After);
+    let privileged = caller.is_admin();
+    let caller = caller.clone();
+
+    // Paging backwards scans in the reverse order, then flips the page
+    let keyset = match direction {
+        Direction::After => {
+            "($6 IS NULL OR rank < $6 OR (rank = $6 AND id > $7)) ORDER BY rank DESC, id ASC"
+        }
+        Direction::Before => {
+            "($6 IS NULL OR rank > $6 OR (rank = $6 AND id < $7)) ORDER BY rank ASC, id DESC"
+        }
+    };
+    let sql = format!("SELECT * FROM ({SEARCH_HITS}) AS hits WHERE {keyset} LIMIT $8");
+
+    request_id::block(move || -> Result<SearchPage, ApiError> {
+        let mut conn = db.get()?;
+        let q = request.q.to_lowercase();
+        let ts_query = ts_query(&q);
+        let pattern = substring_pattern(&q);
+
+        let total = match request.include_total {
+            Some(true) => {
+                let count = diesel::sql_query(format!(
+                    "SELECT count(*) AS count FROM ({SEARCH_HITS}) AS hits"
+                ))
+                .bind::<Text, _>(&q)
+                .bind::<Text, _>(&ts_query)
+                .bind::<Text, _>(&pattern)
+                .bind::<Bool, _>(privileged)
+                .bind::<Integer, _>(caller.user_id)
+                .get_result::<SearchCount>(&mut conn)?
+                .count;
+                Some(ListTotal {
+                    count,
+                    estimated: false,
+                })
+            }
+            _ => None,
+        };
+
+        let (cursor_rank, cursor_id) = match &cursor {
+            Some((cursor, _)) => (Some(cursor.rank), Some(cursor.id)),
+            None => (None, None),
+        };
+        // Fetch an extra record to find out whether there are more records
+        let mut hits = diesel::sql_query(sql)
+            .bind::<Text, _>(&q)
+            .bind::<Text, _>(&ts_query)
+            .bind::<Text, _>(&pattern)
+            .bind::<Bool, _>(privileged)
+            .bind::<Integer, _>(caller.user_id)
+            .bind::<Nullable<Float4>, _>(cursor_rank)
+            .bind::<Nullable<Integer>, _>(cursor_id)
+            .bind::<BigInt, _>(limit as i64 + 1)
+            .load::<SearchHit>(&mut conn)?;
+        let has_more = hits.len() > limit;
+        hits.truncate(limit);
+        if direction == Direction::Before {
+            hits.reverse();
+        }
+
+        let (has_next, has_prev) = match direction {
+            Direction::After => (has_more, cursor.is_some()),
+            Direction::Before => (true, has_more),
+        };
+        let to_cursor = |hit: &SearchHit| SearchCursor {
+            q: request.q.clone(),
+            rank: hit.rank,
+            id: hit.user.id,
+        };
+        let next = has_next.then(|| hits.last().map(to_cursor)).flatten();
+        let prev = has_prev.then(|| hits.first().map(to_cursor)).flatten();
+
+        Ok(SearchPage {
+            users: hits.into_iter().map(|hit| hit.user).collect(),
+            next,
+            prev,
+            total,
+        })
+    })
+    .await?
+}
+
+/// Builds the full-text query matching all alphanumeric tokens of the search
+/// query as prefixes (e.g. `jo:* & example:*`).
+///
+/// Other characters are dropped, so the user input never reaches the tsquery
+/// parser syntax. The result is empty (matching nothing) if there are no
+/// tokens.
+fn ts_query(q: &str) -> String {
+    q.split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(|token| format!("{token}:*"))
+        .collect::<Vec<_>>()
+        .join(" & ")
+}
+
+/// Builds a `LIKE` pattern matching the given substring, escaping the pattern
+/// special characters.
+fn substring_pattern(q: &str) -> String {
+    let escaped = q
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{escaped}%")
+}
@@ -1,29 +1,116 @@
 //!
-//! Handler for handling new user registrations.
+//! Handlers for handling new user registrations, single user lookups, updates
+//! and deletions.
+//!
+//! All handlers except registration rely on JWT middleware to ensure authorization.
 
-use actix_web::{web, HttpResponse};
-use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
-    Argon2,
+use actix_web::{
+    http::header::{self, Header, IfMatch},
+    web, HttpRequest, HttpResponse,
 };
 
-use crate::{errors::ApiError, DbPool};
+use crate::{
+    config::{PasswordsConfig, ServerConfig},
+    encoding::{Encoded, EncodedResponse, Encoding},
+    errors::{ApiError, ProblemDetails},
+    export::UserDataExport,
+    middleware::{jwt::Identity, request_id},
+    passwords, DbPool,
+};
 
-use super::OutputUser;
-use crate::models::{NewUser, User};
+use super::{user_etag, OutputUser};
+use crate::models::{NewUser, User, UserChangeset};
+use crate::validation::{validate_name, NAME_MAX_LENGTH, PASSWORD_MAX_LENGTH, PASSWORD_MIN_LENGTH};
+use validator::{Validate, ValidationError};
 
 /// User creation request representation.
-/// No fields validation is set.
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+/// See [crate::validation] for the fields validation rules.
+#[derive(
+    Debug,
+    serde::Serialize,
+    serde::Deserialize,
+    Validate,
+    utoipa::ToSchema,
+    async_graphql::InputObject,
+)]
+#[graphql(name = "RegisterInput")]
 pub struct InputUser {
     /// User email, corresponds to the field in [User].
+    #[validate(email)]
     pub email: String,
     /// User name, corresponds to the field in [User].
+    #[validate(
+        length(min = 1, max = "NAME_MAX_LENGTH"),
+        custom(function = "validate_name")
+    )]
     pub name: String,
     /// User password, corresponds to the field in [User].
+    #[validate(length(min = "PASSWORD_MIN_LENGTH", max = "PASSWORD_MAX_LENGTH"))]
     pub password: String,
 }
 
+/// User profile update request representation (JSON merge patch, RFC 7396).
+///
+/// Absent fields are left untouched. Unknown fields are rejected.
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize, Validate, utoipa::ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct UserPatch {
+    /// User name, corresponds to the field in [User].
+    ///
+    /// `null` is rejected, since the name cannot be removed.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[validate(
+        length(min = 1, max = "NAME_MAX_LENGTH"),
+        custom(function = "validate_name")
+    )]
+    pub name: Option<Option<String>>,
+    /// Whether the user is shown to other users in lists and search results
+    /// (see [OutputUser]).
+    ///
+    /// `null` is rejected.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub listed: Option<Option<bool>>,
+}
+
+/// Distinguishes between absent (`None`) and `null` (`Some(None)`) fields.
+fn deserialize_present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::Deserialize<'de>,
+{
+    serde::Deserialize::deserialize(deserializer).map(Some)
+}
+
+impl UserPatch {
+    /// Validates the patch and converts it into a database changeset.
+    fn into_changeset(self) -> Result<UserChangeset, ApiError> {
+        // `null` values are not visible to the derived rules, so check them separately
+        let mut errors = self.validate().err().unwrap_or_default();
+        if let Some(None) = self.name {
+            errors.add("name", ValidationError::new("not_null"));
+        }
+        if let Some(None) = self.listed {
+            errors.add("listed", ValidationError::new("not_null"));
+        }
+        if !errors.is_empty() {
+            return Err(errors.into());
+        }
+
+        Ok(UserChangeset {
+            name: self.name.flatten(),
+            listed: self.listed.flatten(),
+        })
+    }
+}
+
 ///
 /// Register a new user endpoint.
 ///
@@ -34,27 +121,49 @@ pub struct InputUser {
 ///
 /// Returns a created user record.
 ///
-/// POST /user
+/// The request may be retried safely with the `Idempotency-Key` header (see
+/// [crate::middleware::idempotency]).
+///
+/// POST /v1/user
 /// Example:
 /// {
 ///   "name": "John",
 ///   "email": "john@example.org",
-///   "password": "secr3t"
-/// }'
+///   "password": "secr3t-pass"
+/// }
 ///
 /// Returns
 /// {
 ///   "created_at": "2024-05-16T10:25:41.800997",
 ///   "email": "john@example.org",
 ///   "id": 9,
+///   "listed": true,
 ///   "name": "john"
 /// }
+#[utoipa::path(
+    post,
+    path = "/v1/user",
+    operation_id = "register_user",
+    summary = "Register a new user",
+    tag = "user",
+    request_body = InputUser,
+    params(("Idempotency-Key" = Option<String>, Header, description = "Unique key making the retries safe (see crate::middleware::idempotency)")),
+    responses(
+        (status = 201, description = "User created", body = OutputUser),
+        (status = 409, description = "Email is already registered, or request with the same idempotency key is in progress", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Validation failed", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
 pub async fn register(
     db: web::Data<DbPool>,
-    item: web::Json<InputUser>,
+    cfg: web::Data<&'static ServerConfig>,
+    encoding: Encoding,
+    item: Encoded<InputUser>,
 ) -> web::Either<HttpResponse, ApiError> {
-    match register_single_user(db, item.into_inner()).await {
-        Ok(user) => web::Either::Left(HttpResponse::Created().json(OutputUser::from(user))),
+    match register_single_user(db, &cfg.passwords, item.into_inner()).await {
+        Ok(user) => web::Either::Left(
+            HttpResponse::Created().encoded(encoding, OutputUser::for_owner(user)),
+        ),
         Err(e) => {
             log::warn!("Cannot register the user: {}", e);
             web::Either::Right(e)
@@ -62,20 +171,434 @@ pub async fn register(
     }
 }
 
-async fn register_single_user(db: web::Data<DbPool>, item: InputUser) -> Result<User, ApiError> {
-    let salt = SaltString::generate(&mut OsRng);
-    let hash = Argon2::default()
-        .hash_password(item.password.as_bytes(), &salt)?
-        .to_string();
+pub(crate) async fn register_single_user(
+    db: web::Data<DbPool>,
+    cfg: &PasswordsConfig,
+    item: InputUser,
+) -> Result<User, ApiError> {
+    item.validate()?;
 
     let new_user = NewUser {
         name: item.name,
         email: item.email,
-        hashed_password: hash,
+        hashed_password: passwords::hash(&item.password, cfg)?,
     };
 
-    match web::block(move || new_user.write(db)).await? {
+    match request_id::block(move || new_user.write(db)).await? {
         Ok(user) => Ok(user),
         Err(e) => Err(e),
     }
 }
+
+///
+/// Get the authorized user endpoint.
+///
+/// The user is resolved from the JWT token subject.
+/// Requires Authorization via JWT (see /auth/token handler).
+/// Returns [OutputUser], with the `ETag` header set (see PATCH /user/me).
+///
+/// Example:
+/// GET /v1/user/me
+/// Authorization: Bearer [token]
+///
+/// Returns
+/// {
+///   "created_at": "2024-05-16T10:25:41.800997",
+///   "email": "john@example.org",
+///   "id": 9,
+///   "listed": true,
+///   "name": "john"
+/// }
+#[utoipa::path(
+    get,
+    path = "/v1/user/me",
+    operation_id = "get_me",
+    summary = "Get the authorized user",
+    tag = "user",
+    security(("bearer_auth" = [])),
+    responses(
+        (
+            status = 200,
+            description = "Authorized user",
+            body = OutputUser,
+            headers(("ETag" = String, description = "Current profile version")),
+        ),
+        (status = 401, description = "Missing or invalid token", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+pub async fn me(
+    db: web::Data<DbPool>,
+    identity: web::ReqData<Identity>,
+    encoding: Encoding,
+) -> web::Either<HttpResponse, ApiError> {
+    let user_id = identity.user_id;
+    match request_id::block(move || User::find_by_id(user_id, db)).await {
+        Ok(Ok(user)) => web::Either::Left(
+            HttpResponse::Ok()
+                .insert_header(header::ETag(user_etag(&user)))
+                .encoded(encoding, OutputUser::for_owner(user)),
+        ),
+        Ok(Err(e)) => web::Either::Right(e),
+        Err(e) => web::Either::Right(ApiError::from(e)),
+    }
+}
+
+///
+/// Get a single user by id endpoint.
+///
+/// Requires Authorization via JWT (see /auth/token handler).
+/// Returns [OutputUser] rendered for the caller (the email is masked unless
+/// the caller is the user itself or an admin), or 404 if there is no user
+/// with the given id.
+///
+/// Example:
+/// GET /v1/user/9
+/// Authorization: Bearer [token]
+///
+/// Returns
+/// {
+///   "created_at": "2024-05-16T10:25:41.800997",
+///   "email": "j***@example.org",
+///   "id": 9,
+///   "name": "john"
+/// }
+#[utoipa::path(
+    get,
+    path = "/v1/user/{id}",
+    operation_id = "get_user",
+    summary = "Get a single user by id",
+    tag = "user",
+    params(("id" = i32, Path, description = "User id")),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "User, rendered for the caller", body = OutputUser),
+        (status = 401, description = "Missing or invalid token", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such user", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get(
+    db: web::Data<DbPool>,
+    identity: web::ReqData<Identity>,
+    encoding: Encoding,
+    path: web::Path<i32>,
+) -> web::Either<HttpResponse, ApiError> {
+    let user_id = path.into_inner();
+    match request_id::block(move || User::find_by_id(user_id, db)).await {
+        Ok(Ok(user)) => web::Either::Left(
+            HttpResponse::Ok()
+                .insert_header(header::ETag(user_etag(&user)))
+                .encoded(encoding, OutputUser::for_caller(user, &identity)),
+        ),
+        Ok(Err(e)) => web::Either::Right(e),
+        Err(e) => web::Either::Right(ApiError::from(e)),
+    }
+}
+
+///
+/// Update the authorized user profile endpoint.
+///
+/// Accepts [UserPatch] as a JSON merge patch (RFC 7396), with either
+/// `application/merge-patch+json` or `application/json` content type (the
+/// same patch may be encoded with MessagePack or CBOR, see [crate::encoding]).
+/// Requires Authorization via JWT (see /auth/token handler).
+/// Requires the `If-Match` header, containing the `ETag` value, previously
+/// returned by GET /user/me. Responds with 412 if the profile was modified
+/// in the meantime, and with 428 if the header is missing.
+/// Returns the updated [OutputUser], with the new `ETag` header set.
+///
+/// Example:
+/// PATCH /v1/user/me
+/// Authorization: Bearer [token]
+/// If-Match: "1715855141800997"
+/// {
+///   "name": "John",
+///   "listed": false
+/// }
+///
+/// Returns
+/// {
+///   "created_at": "2024-05-16T10:25:41.800997",
+///   "email": "john@example.org",
+///   "id": 9,
+///   "listed": false,
+///   "name": "John"
+/// }
+#[utoipa::path(
+    patch,
+    path = "/v1/user/me",
+    operation_id = "update_me",
+    summary = "Update the authorized user profile",
+    tag = "user",
+    params(("If-Match" = String, Header, description = "`ETag` of the profile version being updated")),
+    request_body(content = UserPatch, content_type = "application/merge-patch+json"),
+    security(("bearer_auth" = [])),
+    responses(
+        (
+            status = 200,
+            description = "Updated user",
+            body = OutputUser,
+            headers(("ETag" = String, description = "New profile version")),
+        ),
+        (status = 401, description = "Missing or invalid token", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 412, description = "Profile was modified concurrently", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Validation failed", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 428, description = "If-Match header is missing", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+pub async fn update_me(
+    db: web::Data<DbPool>,
+    identity: web::ReqData<Identity>,
+    req: HttpRequest,
+    encoding: Encoding,
+    patch: Encoded<UserPatch>,
+) -> web::Either<HttpResponse, ApiError> {
+    // Absent header is parsed as an empty list of tags, so check it first
+    if !req.headers().contains_key(header::IF_MATCH) {
+        return web::Either::Right(ApiError::PreconditionRequired {});
+    }
+    let if_match = match IfMatch::parse(&req) {
+        Ok(if_match) => if_match,
+        Err(_) => return web::Either::Right(ApiError::PreconditionFailed {}),
+    };
+
+    match update_user_profile(db, identity.user_id, if_match, patch.into_inner()).await {
+        Ok(user) => web::Either::Left(
+            HttpResponse::Ok()
+                .insert_header(header::ETag(user_etag(&user)))
+                .encoded(encoding, OutputUser::for_owner(user)),
+        ),
+        Err(e) => web::Either::Right(e),
+    }
+}
+
+pub(crate) async fn update_user_profile(
+    db: web::Data<DbPool>,
+    user_id: i32,
+    if_match: IfMatch,
+    patch: UserPatch,
+) -> Result<User, ApiError> {
+    let changeset = patch.into_changeset()?;
+
+    request_id::block(move || -> Result<User, ApiError> {
+        let user = User::find_by_id(user_id, db.clone())?;
+        let matches = match &if_match {
+            IfMatch::Any => true,
+            IfMatch::Items(tags) => tags.iter().any(|tag| tag.strong_eq(&user_etag(&user))),
+        };
+        if !matches {
+            return Err(ApiError::PreconditionFailed {});
+        }
+        if changeset.is_empty() {
+            return Ok(user);
+        }
+
+        User::update_if_unmodified(user.id, user.updated_at, &changeset, db)?
+            .ok_or(ApiError::PreconditionFailed {})
+    })
+    .await?
+}
+
+///
+/// Delete the authorized user endpoint.
+///
+/// The user is soft-deleted: it can no longer authenticate, and all its tokens
+/// stop working immediately. The user may be restored by an admin during the
+/// configured grace period (see POST /user/{id}/restore), and is purged
+/// permanently afterwards.
+/// Requires Authorization via JWT (see /auth/token handler).
+/// Returns 204 No Content.
+///
+/// Example:
+/// DELETE /v1/user/me
+/// Authorization: Bearer [token]
+#[utoipa::path(
+    delete,
+    path = "/v1/user/me",
+    operation_id = "delete_me",
+    summary = "Delete the authorized user",
+    tag = "user",
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 204, description = "User deleted"),
+        (status = 401, description = "Missing or invalid token", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+pub async fn delete_me(
+    db: web::Data<DbPool>,
+    identity: web::ReqData<Identity>,
+) -> web::Either<HttpResponse, ApiError> {
+    let user_id = identity.user_id;
+    match request_id::block(move || User::soft_delete(user_id, db)).await {
+        Ok(Ok(())) => web::Either::Left(HttpResponse::NoContent().finish()),
+        Ok(Err(e)) => web::Either::Right(e),
+        Err(e) => web::Either::Right(ApiError::from(e)),
+    }
+}
+
+///
+/// Delete a user by id endpoint.
+///
+/// Same as DELETE /user/me, but for an arbitrary user.
+/// Requires Authorization via JWT (see /auth/token handler) of an admin user.
+/// Returns 204 No Content, or 404 if there is no (non-deleted) user with the given id.
+///
+/// Example:
+/// DELETE /v1/user/9
+/// Authorization: Bearer [token]
+#[utoipa::path(
+    delete,
+    path = "/v1/user/{id}",
+    operation_id = "delete_user",
+    summary = "Delete a user by id (admin only)",
+    tag = "user",
+    params(("id" = i32, Path, description = "User id")),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 204, description = "User deleted"),
+        (status = 401, description = "Missing or invalid token", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Caller is not an admin", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such user", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+pub async fn delete(
+    db: web::Data<DbPool>,
+    identity: web::ReqData<Identity>,
+    path: web::Path<i32>,
+) -> web::Either<HttpResponse, ApiError> {
+    if !identity.is_admin() {
+        return web::Either::Right(ApiError::Forbidden {});
+    }
+
+    let user_id = path.into_inner();
+    match request_id::block(move || User::soft_delete(user_id, db)).await {
+        Ok(Ok(())) => web::Either::Left(HttpResponse::NoContent().finish()),
+        Ok(Err(e)) => web::Either::Right(e),
+        Err(e) => web::Either::Right(ApiError::from(e)),
+    }
+}
+
+///
+/// Restore a deleted user endpoint.
+///
+/// Requires Authorization via JWT (see /auth/token handler) of an admin user.
+/// Returns the restored [OutputUser], or 404 if there is no user with the
+/// given id deleted within the grace period.
+///
+/// The request may be retried safely with the `Idempotency-Key` header (see
+/// [crate::middleware::idempotency]).
+///
+/// Example:
+/// POST /v1/user/9/restore
+/// Authorization: Bearer [token]
+///
+/// Returns
+/// {
+///   "created_at": "2024-05-16T10:25:41.800997",
+///   "email": "john@example.org",
+///   "id": 9,
+///   "listed": true,
+///   "name": "john"
+/// }
+#[utoipa::path(
+    post,
+    path = "/v1/user/{id}/restore",
+    operation_id = "restore_user",
+    summary = "Restore a deleted user (admin only)",
+    tag = "user",
+    params(("id" = i32, Path, description = "User id"), ("Idempotency-Key" = Option<String>, Header, description = "Unique key making the retries safe (see crate::middleware::idempotency)")),
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Restored user", body = OutputUser),
+        (status = 401, description = "Missing or invalid token", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Caller is not an admin", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No user deleted within the grace period", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "Request with the same idempotency key is in progress", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+pub async fn restore(
+    db: web::Data<DbPool>,
+    cfg: web::Data<&'static ServerConfig>,
+    identity: web::ReqData<Identity>,
+    encoding: Encoding,
+    path: web::Path<i32>,
+) -> web::Either<HttpResponse, ApiError> {
+    if !identity.is_admin() {
+        return web::Either::Right(ApiError::Forbidden {});
+    }
+
+    let user_id = path.into_inner();
+    let deleted_after = chrono::Utc::now().naive_utc() - cfg.accounts.deletion_grace_period();
+    match request_id::block(move || User::restore(user_id, deleted_after, db)).await {
+        Ok(Ok(user)) => web::Either::Left(
+            HttpResponse::Ok().encoded(encoding, OutputUser::for_caller(user, &identity)),
+        ),
+        Ok(Err(e)) => web::Either::Right(e),
+        Err(e) => web::Either::Right(ApiError::from(e)),
+    }
+}
+
+///
+/// Export the authorized user personal data endpoint.
+///
+/// Returns a JSON archive (see [UserDataExport]) of all the data held about
+/// the user, as an attachment.
+/// Requires Authorization via JWT (see /auth/token handler).
+///
+/// Example:
+/// GET /v1/user/me/export
+/// Authorization: Bearer [token]
+///
+/// Returns
+/// {
+///   "user_id": 9,
+///   "generated_at": "2024-05-16T10:30:12.123456",
+///   "data": {
+///     "profile": {
+///       "id": 9,
+///       "email": "john@example.org",
+///       "name": "john",
+///       "created_at": "2024-05-16T10:25:41.800997",
+///       "updated_at": "2024-05-16T10:25:41.800997",
+///       "deleted_at": null,
+///       "listed": true
+///     },
+///     "roles": []
+///   }
+/// }
+#[utoipa::path(
+    get,
+    path = "/v1/user/me/export",
+    operation_id = "export_me",
+    summary = "Export the authorized user personal data",
+    tag = "user",
+    security(("bearer_auth" = [])),
+    responses(
+        (status = 200, description = "Personal data archive", body = UserDataExport),
+        (status = 401, description = "Missing or invalid token", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+pub async fn export_me(
+    db: web::Data<DbPool>,
+    identity: web::ReqData<Identity>,
+    encoding: Encoding,
+) -> web::Either<HttpResponse, ApiError> {
+    let user_id = identity.user_id;
+    let export = request_id::block(move || -> Result<UserDataExport, ApiError> {
+        let mut conn = db.get()?;
+        UserDataExport::collect(user_id, &mut conn)
+    })
+    .await;
+
+    match export {
+        Ok(Ok(export)) => web::Either::Left(
+            HttpResponse::Ok()
+                .insert_header(header::ContentDisposition::attachment(format!(
+                    "user-{user_id}-export.{}",
+                    encoding.extension()
+                )))
+                .encoded(encoding, export),
+        ),
+        Ok(Err(e)) => web::Either::Right(e),
+        Err(e) => web::Either::Right(ApiError::from(e)),
+    }
+}
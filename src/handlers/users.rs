@@ -2,33 +2,484 @@
 //! Handler for handling users listing requests.
 //!
 //! Relies on JWT middleware to ensure authorization.
+//!
+//! Lists are paginated with opaque keyset cursors (see [crate::pagination]),
+//! encoding the sort key and the id (as a tie-breaker) of the boundary record.
+
+use std::collections::HashMap;
 
-use actix_web::{web, HttpResponse};
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use diesel::pg::Pg;
 use diesel::prelude::*;
-use diesel::ExpressionMethods;
+use diesel::sql_types::{Bool, Nullable, Text, Timestamp};
+use diesel::{dsl, ExpressionMethods};
+use validator::{Validate, ValidationError};
 
-use crate::schema::users::dsl::*;
-use crate::{errors::ApiError, models::User, DbPool};
+use crate::config::{PaginationConfig, ServerConfig};
+use crate::encoding::{EncodedResponse, Encoding};
+use crate::pagination::{
+    decode_cursor, encode_cursor, link_header, validate_limit, CursorPaginated, Direction,
+};
+use crate::schema::user_roles;
+use crate::schema::users::{self, dsl::*};
+use crate::validation::{field_error, NAME_MAX_LENGTH};
+use crate::{
+    errors::{ApiError, ProblemDetails},
+    middleware::{jwt::Identity, request_id},
+    models::lower,
+    DbPool,
+};
 
-use super::OutputUser;
+use super::{mask_email, sees_private, OutputUser};
 
-// Might be put into server config
-const DEFAULT_QUERY_LIMIT: i32 = 10;
+/// Maximum length of the list cursor, in characters.
+const CURSOR_MAX_LENGTH: u64 = 1024;
 
 /// Users list request representation.
-#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+///
+/// All filters are optional and combined with AND.
+#[derive(
+    Clone, Debug, Default, serde::Serialize, serde::Deserialize, Validate, utoipa::IntoParams,
+)]
+#[into_params(parameter_in = Query)]
+#[validate(schema(function = "validate_created_range"))]
 pub struct ListRequest {
-    /// A number of user records to retrieve (optional, default: 10)
+    /// A number of user records to retrieve (optional).
+    ///
+    /// Defaults to `pagination.default_page_size`, and must not exceed
+    /// `pagination.max_page_size` (see [PaginationConfig]).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[validate(range(min = 1))]
     pub limit: Option<i32>,
-    /// User id to start the list from (not inclusive, optional, default: 0)
-    pub after: Option<i32>,
+    /// Whether to include the total number of matching records (optional,
+    /// default: false).
+    ///
+    /// The count is exact, unless an admin sets no filters and the table is
+    /// large (see `pagination.exact_count_threshold`), in which case it is
+    /// estimated from the table statistics.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_total: Option<bool>,
+    /// Cursor to start the list after (not inclusive, optional).
+    ///
+    /// Use `next_cursor` of the previous page (see [ListResponse]). For
+    /// backwards compatibility, a plain user id is accepted as well when
+    /// sorting by id.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[validate(length(max = "CURSOR_MAX_LENGTH"))]
+    pub after: Option<String>,
+    /// Cursor to end the list before (not inclusive, optional).
+    ///
+    /// Use `prev_cursor` of the next page (see [ListResponse]). Cannot be
+    /// combined with `after`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[validate(length(max = "CURSOR_MAX_LENGTH"))]
+    pub before: Option<String>,
+    /// Sort order (optional, default: id)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<SortOrder>,
+    /// Case-insensitive email prefix (optional).
+    ///
+    /// Only admins may filter by email, since it is masked for other users.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[validate(length(min = 1, max = "NAME_MAX_LENGTH"))]
+    pub email_prefix: Option<String>,
+    /// Case-insensitive name prefix (optional)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[validate(length(min = 1, max = "NAME_MAX_LENGTH"))]
+    pub name_prefix: Option<String>,
+    /// Lower bound of the user creation datetime (not inclusive, optional)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_after: Option<chrono::NaiveDateTime>,
+    /// Upper bound of the user creation datetime (not inclusive, optional)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_before: Option<chrono::NaiveDateTime>,
+    /// Role granted to the user (optional).
+    ///
+    /// Only admins may filter by role, so the privileged accounts are not
+    /// disclosed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[validate(length(min = 1, max = "NAME_MAX_LENGTH"))]
+    pub role: Option<String>,
+    /// User status (optional, default: active).
+    ///
+    /// Only admins may list deleted users.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<UserStatus>,
+    /// Comma-separated list of the user fields to return (optional, default:
+    /// all fields), e.g. `id,name`.
+    ///
+    /// `id` is always returned, other fields are `email`, `name`, `created_at`
+    /// and `listed`. Columns of fields which are not requested are not
+    /// fetched from the database.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[validate(custom(function = "validate_fields"))]
+    pub fields: Option<String>,
+    /// Comma-separated list of the related data to embed (optional), e.g.
+    /// `roles`.
+    ///
+    /// Supported relations: `roles` (names of the roles granted to the user,
+    /// admins only). The linked `identities` are not stored yet, so they are
+    /// rejected as an unknown name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[validate(custom(function = "validate_expand"))]
+    pub expand: Option<String>,
 }
 
-/// Users list response representation.
+/// User status, used as a [ListRequest] filter.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    utoipa::ToSchema,
+    async_graphql::Enum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum UserStatus {
+    /// Regular (non-deleted) users.
+    #[default]
+    Active,
+    /// Deleted users, which are not purged yet.
+    Deleted,
+}
+
+/// Users list sort order.
+///
+/// Descending orders are prefixed with `-`. Records sharing the same sort key
+/// are ordered by id in the same direction.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    utoipa::ToSchema,
+    async_graphql::Enum,
+)]
+#[graphql(name = "UserSort")]
+pub enum SortOrder {
+    /// By id, ascending.
+    #[default]
+    #[serde(rename = "id")]
+    IdAsc,
+    /// By id, descending.
+    #[serde(rename = "-id")]
+    IdDesc,
+    /// By name, ascending.
+    #[serde(rename = "name")]
+    NameAsc,
+    /// By name, descending.
+    #[serde(rename = "-name")]
+    NameDesc,
+    /// By creation datetime, ascending.
+    #[serde(rename = "created_at")]
+    CreatedAtAsc,
+    /// By creation datetime, descending.
+    #[serde(rename = "-created_at")]
+    CreatedAtDesc,
+}
+
+impl SortOrder {
+    fn is_ascending(self) -> bool {
+        matches!(self, Self::IdAsc | Self::NameAsc | Self::CreatedAtAsc)
+    }
+}
+
+///
+/// Cursor payload: the sort order and the sort key of the boundary record.
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct UserCursor {
+    sort: SortOrder,
+    id: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<chrono::NaiveDateTime>,
+}
+
+impl UserCursor {
+    fn new(sort: SortOrder, user: &UserRow) -> Self {
+        Self {
+            sort,
+            id: user.id,
+            name: matches!(sort, SortOrder::NameAsc | SortOrder::NameDesc)
+                .then(|| user.name.clone())
+                .flatten(),
+            created_at: matches!(sort, SortOrder::CreatedAtAsc | SortOrder::CreatedAtDesc)
+                .then_some(user.created_at)
+                .flatten(),
+        }
+    }
+
+    /// Returns `true` if the cursor carries the key of its sort order.
+    fn has_sort_key(&self) -> bool {
+        match self.sort {
+            SortOrder::IdAsc | SortOrder::IdDesc => true,
+            SortOrder::NameAsc | SortOrder::NameDesc => self.name.is_some(),
+            SortOrder::CreatedAtAsc | SortOrder::CreatedAtDesc => self.created_at.is_some(),
+        }
+    }
+}
+
+impl ListRequest {
+    /// Decodes the `after`/`before` cursor, ensuring it matches the sort order
+    /// and carries its sort key.
+    fn cursor(&self, secret: &[u8]) -> Result<Option<(UserCursor, Direction)>, ApiError> {
+        let sort = self.sort.unwrap_or_default();
+        let (field, raw, direction) = match (&self.after, &self.before) {
+            (None, None) => return Ok(None),
+            (Some(_), Some(_)) => return Err(field_error("before", "conflicts_with_after").into()),
+            (Some(raw), None) => ("after", raw, Direction::After),
+            (None, Some(raw)) => ("before", raw, Direction::Before),
+        };
+
+        if let (SortOrder::IdAsc, Ok(legacy_id)) = (sort, raw.parse::<i32>()) {
+            let cursor = UserCursor {
+                sort,
+                id: legacy_id,
+                name: None,
+                created_at: None,
+            };
+            return Ok(Some((cursor, direction)));
+        }
+
+        match decode_cursor::<UserCursor>(raw, secret) {
+            Some(cursor) if cursor.sort != sort => {
+                Err(field_error(field, "cursor_sort_mismatch").into())
+            }
+            Some(cursor) if cursor.has_sort_key() => Ok(Some((cursor, direction))),
+            _ => Err(field_error(field, "invalid_cursor").into()),
+        }
+    }
+}
+
+impl ListRequest {
+    /// Returns `true` if any filters narrowing down the default list are set.
+    fn has_filters(&self) -> bool {
+        self.status.unwrap_or_default() != UserStatus::Active
+            || self.email_prefix.is_some()
+            || self.name_prefix.is_some()
+            || self.created_after.is_some()
+            || self.created_before.is_some()
+            || self.role.is_some()
+    }
+}
+
+impl ListRequest {
+    /// Returns the requested fields (all fields by default).
+    fn field_set(&self) -> FieldSet {
+        match &self.fields {
+            Some(list) => FieldSet::parse(list).unwrap_or(FieldSet::ALL),
+            None => FieldSet::ALL,
+        }
+    }
+
+    /// Returns the requested related data (none by default).
+    fn expansions(&self) -> Expansions {
+        match &self.expand {
+            Some(list) => Expansions::parse(list).unwrap_or_default(),
+            None => Expansions::default(),
+        }
+    }
+}
+
+impl CursorPaginated for ListRequest {
+    fn with_cursor(&self, direction: Direction, cursor: &str) -> Self {
+        let mut query = self.clone();
+        (query.after, query.before) = match direction {
+            Direction::After => (Some(cursor.to_string()), None),
+            Direction::Before => (None, Some(cursor.to_string())),
+        };
+        query
+    }
+}
+
+fn validate_created_range(request: &ListRequest) -> Result<(), ValidationError> {
+    match (request.created_after, request.created_before) {
+        (Some(after_dt), Some(before_dt)) if after_dt >= before_dt => {
+            Err(ValidationError::new("empty_range"))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Optional user fields, selected with [ListRequest::fields].
+#[derive(Clone, Copy, Debug)]
+struct FieldSet {
+    email: bool,
+    name: bool,
+    created_at: bool,
+    listed: bool,
+}
+
+impl FieldSet {
+    const ALL: Self = Self {
+        email: true,
+        name: true,
+        created_at: true,
+        listed: true,
+    };
+
+    /// Parses the comma-separated list of field names, returning the first
+    /// unknown name on error.
+    fn parse(list: &str) -> Result<Self, String> {
+        let mut fields = Self {
+            email: false,
+            name: false,
+            created_at: false,
+            listed: false,
+        };
+        for field in list.split(',') {
+            match field {
+                "id" => {}
+                "email" => fields.email = true,
+                "name" => fields.name = true,
+                "created_at" => fields.created_at = true,
+                "listed" => fields.listed = true,
+                unknown => return Err(unknown.to_string()),
+            }
+        }
+        Ok(fields)
+    }
+}
+
+/// Related data, embedded with [ListRequest::expand].
+#[derive(Clone, Copy, Debug, Default)]
+struct Expansions {
+    roles: bool,
+}
+
+impl Expansions {
+    /// Parses the comma-separated list of relation names, returning the first
+    /// unknown name on error.
+    fn parse(list: &str) -> Result<Self, String> {
+        let mut expansions = Self::default();
+        for relation in list.split(',') {
+            match relation {
+                "roles" => expansions.roles = true,
+                unknown => return Err(unknown.to_string()),
+            }
+        }
+        Ok(expansions)
+    }
+}
+
+fn validate_fields(list: &str) -> Result<(), ValidationError> {
+    FieldSet::parse(list)
+        .map(|_| ())
+        .map_err(unknown_name_error)
+}
+
+fn validate_expand(list: &str) -> Result<(), ValidationError> {
+    Expansions::parse(list)
+        .map(|_| ())
+        .map_err(unknown_name_error)
+}
+
+fn unknown_name_error(unknown: String) -> ValidationError {
+    let mut error = ValidationError::new("unknown_field");
+    error.add_param("name".into(), &unknown);
+    error
+}
+
+/// Users list response representation.
+#[derive(Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
 pub struct ListResponse {
-    /// A list of user records (see [OutputUser]).
-    pub users: Vec<OutputUser>,
+    /// A list of user records (see [ListedUser]).
+    pub users: Vec<ListedUser>,
+    /// Cursor of the next page, to be passed as `after` (null if there are no
+    /// more records).
+    pub next_cursor: Option<String>,
+    /// Cursor of the previous page, to be passed as `before` (null if there
+    /// are no records before).
+    pub prev_cursor: Option<String>,
+    /// Total number of matching records (only if requested with `include_total`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<ListTotal>,
+}
+
+/// Users list record: the [OutputUser] fields requested with
+/// [ListRequest::fields], along with the related data requested with
+/// [ListRequest::expand].
+#[derive(Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ListedUser {
+    /// User id
+    pub id: i32,
+    /// User email (see [OutputUser::email])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    /// User name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// User creation datetime
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<chrono::NaiveDateTime>,
+    /// User listing visibility (see [OutputUser::listed])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listed: Option<bool>,
+    /// Names of the roles granted to the user (only with `expand=roles`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub roles: Option<Vec<String>>,
+}
+
+impl From<OutputUser> for ListedUser {
+    fn from(user: OutputUser) -> Self {
+        Self {
+            id: user.id,
+            email: Some(user.email),
+            name: Some(user.name),
+            created_at: Some(user.created_at),
+            listed: user.listed,
+            roles: None,
+        }
+    }
+}
+
+impl ListedUser {
+    /// Renders the projected user record for the caller (see [OutputUser]),
+    /// omitting the fields which were not requested.
+    fn for_caller(user: UserRow, fields: FieldSet, caller: &Identity) -> Self {
+        let private = sees_private(caller, user.id);
+        Self {
+            id: user.id,
+            email: user
+                .email
+                .filter(|_| fields.email)
+                .map(|address| match private {
+                    true => address,
+                    false => mask_email(&address),
+                }),
+            name: user.name.filter(|_| fields.name),
+            created_at: user.created_at.filter(|_| fields.created_at),
+            listed: user.listed.filter(|_| fields.listed && private),
+            roles: None,
+        }
+    }
+}
+
+/// Total number of records matching the list request.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    serde::Serialize,
+    serde::Deserialize,
+    utoipa::ToSchema,
+    async_graphql::SimpleObject,
+)]
+#[graphql(name = "UsersTotal")]
+pub struct ListTotal {
+    /// Number of records.
+    pub count: i64,
+    /// Whether the number is estimated from the table statistics.
+    pub estimated: bool,
 }
 
 ///
@@ -36,60 +487,420 @@ pub struct ListResponse {
 ///
 /// Accepts [ListRequest].
 /// Requires Authorization via JWT (see /auth/token handler).
-/// Returns [ListResponse].
+/// Returns [ListResponse], along with RFC 8288 `Link` header containing
+/// `next` and `prev` pages links (when present). With `include_total=true`,
+/// the response contains `"total": {"count": 42, "estimated": false}` as well.
+/// Users may be narrowed down to the fields requested with `fields`, and
+/// extended with the related data requested with `expand` (see [ListedUser]).
+///
+/// Users are rendered for the caller (see [OutputUser]): other users' emails
+/// are masked, and unlisted users are omitted, unless the caller is an admin.
 ///
 /// Example:
-/// GET /users?limit=5&after=16
+/// GET /v1/users?limit=2&sort=-name&name_prefix=jo&created_after=2024-05-01T00:00:00
 /// Authorization: Bearer [token]
 ///
-/// Returns (assuming only two records left)
+/// Returns
+/// Link: </v1/users?limit=2&sort=-name&...&after=eyJzb...Zk>; rel="next"
 /// {
 ///   "users": [
 ///     {
+///       "created_at": "2024-05-15T19:50:05.008961",
+///       "email": "j***@example.org",
+///       "id": 18,
+///       "name": "Joseph"
+///     },
+///     {
 ///       "created_at": "2024-05-15T19:49:55.314405",
-///       "email": "john@exmaple.org",
+///       "email": "j***@exmaple.org",
 ///       "id": 17,
 ///       "name": "John"
-///     },
-///     {
-///       "created_at": "2024-05-15T19:50:05.008961",
-///       "email": "mary@example.org",
-///       "id": 18,
-///       "name": "Mary"
 ///     }
-///   ]
+///   ],
+///   "next_cursor": "eyJzb...Zk",
+///   "prev_cursor": null
 /// }
 ///
+#[utoipa::path(
+    get,
+    path = "/v1/users",
+    operation_id = "list_users",
+    summary = "List registered users",
+    tag = "users",
+    params(ListRequest),
+    security(("bearer_auth" = [])),
+    responses(
+        (
+            status = 200,
+            description = "Page of users",
+            body = ListResponse,
+            headers(("Link" = String, description = "`next` and `prev` pages links")),
+        ),
+        (status = 401, description = "Missing or invalid token", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Filter is only available to admins", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Validation failed", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
 pub async fn list(
     db: web::Data<DbPool>,
+    cfg: web::Data<&'static ServerConfig>,
+    identity: web::ReqData<Identity>,
+    req: HttpRequest,
+    encoding: Encoding,
     query: web::Query<ListRequest>,
 ) -> web::Either<HttpResponse, ApiError> {
-    let limit = query
-        .limit
-        .unwrap_or(DEFAULT_QUERY_LIMIT)
-        .min(DEFAULT_QUERY_LIMIT);
-    let after = query.after.unwrap_or(0);
-
-    match load_users(db, limit, after).await {
-        Ok(user_list) => {
-            let response = HttpResponse::Ok().json(ListResponse {
-                users: user_list.into_iter().map(OutputUser::from).collect(),
-            });
+    let query = query.into_inner();
+    match list_users(db, **cfg, &identity, query.clone()).await {
+        Ok(listing) => {
+            let link = link_header(
+                req.path(),
+                &query,
+                listing.response.next_cursor.as_deref(),
+                listing.response.prev_cursor.as_deref(),
+            );
+            let mut response = HttpResponse::Ok().encoded(encoding, listing.response);
+            if let Some(link) = link {
+                response.headers_mut().append(header::LINK, link);
+            }
             web::Either::Left(response)
         }
         Err(e) => web::Either::Right(e),
     }
 }
 
-async fn load_users(db: web::Data<DbPool>, limit: i32, after: i32) -> Result<Vec<User>, ApiError> {
-    web::block(move || -> Result<Vec<User>, ApiError> {
+/// Page of the users list rendered for the caller, along with the cursors of
+/// the listed users (shared by the REST and GraphQL endpoints).
+pub(crate) struct UsersListing {
+    /// Rendered page.
+    pub(crate) response: ListResponse,
+    /// Cursors of the listed users, in the same order: any of them may be
+    /// passed as `after` or `before`.
+    pub(crate) cursors: Vec<String>,
+}
+
+/// Validates the request, and loads the page of users for the caller.
+pub(crate) async fn list_users(
+    db: web::Data<DbPool>,
+    cfg: &'static ServerConfig,
+    caller: &Identity,
+    query: ListRequest,
+) -> Result<UsersListing, ApiError> {
+    query.validate()?;
+    validate_limit(query.limit, &cfg.pagination)?;
+    let privileged = query.status == Some(UserStatus::Deleted)
+        || query.email_prefix.is_some()
+        || query.role.is_some()
+        || query.expansions().roles;
+    if privileged && !caller.is_admin() {
+        return Err(ApiError::Forbidden {});
+    }
+    let secret = cfg.pagination.cursor_secret.as_bytes();
+    let cursor = query.cursor(secret)?;
+
+    let fields = query.field_set();
+    let sort = query.sort.unwrap_or_default();
+    let page = load_users(db, &cfg.pagination, caller, query, cursor).await?;
+    let cursors = page
+        .users
+        .iter()
+        .map(|user| encode_cursor(&UserCursor::new(sort, user), secret))
+        .collect();
+    let listed_users = page
+        .users
+        .into_iter()
+        .map(|user| {
+            let roles = page
+                .roles
+                .as_ref()
+                .map(|roles| roles.get(&user.id).cloned().unwrap_or_default());
+            ListedUser {
+                roles,
+                ..ListedUser::for_caller(user, fields, caller)
+            }
+        })
+        .collect();
+
+    Ok(UsersListing {
+        response: ListResponse {
+            users: listed_users,
+            next_cursor: page.next.map(|cursor| encode_cursor(&cursor, secret)),
+            prev_cursor: page.prev.map(|cursor| encode_cursor(&cursor, secret)),
+            total: page.total,
+        },
+        cursors,
+    })
+}
+
+/// Single page of the users list, along with the adjacent pages cursors (if
+/// those pages exist).
+struct UsersPage {
+    users: Vec<UserRow>,
+    next: Option<UserCursor>,
+    prev: Option<UserCursor>,
+    total: Option<ListTotal>,
+    /// Granted role names by user id (only with `expand=roles`).
+    roles: Option<HashMap<i32, Vec<String>>>,
+}
+
+/// Users record, projected to the requested fields (the rest are `None`).
+#[derive(Queryable)]
+struct UserRow {
+    id: i32,
+    email: Option<String>,
+    name: Option<String>,
+    created_at: Option<chrono::NaiveDateTime>,
+    listed: Option<bool>,
+}
+
+/// Builds the users query with all [ListRequest] filters applied.
+///
+/// Unlisted users are only visible to admins and to themselves.
+pub(super) fn filtered_users(
+    filter: ListRequest,
+    caller: &Identity,
+) -> users::BoxedQuery<'static, Pg> {
+    let mut query = users.into_boxed();
+
+    if !caller.is_admin() {
+        query = query.filter(listed.eq(true).or(id.eq(caller.user_id)));
+    }
+    query = match filter.status.unwrap_or_default() {
+        UserStatus::Active => query.filter(deleted_at.is_null()),
+        UserStatus::Deleted => query.filter(deleted_at.is_not_null()),
+    };
+    if let Some(prefix) = filter.email_prefix {
+        query = query.filter(lower(email).like(prefix_pattern(&prefix)));
+    }
+    if let Some(prefix) = filter.name_prefix {
+        query = query.filter(lower(name).like(prefix_pattern(&prefix)));
+    }
+    if let Some(created_after) = filter.created_after {
+        query = query.filter(created_at.gt(created_after));
+    }
+    if let Some(created_before) = filter.created_before {
+        query = query.filter(created_at.lt(created_before));
+    }
+    if let Some(role) = filter.role {
+        query = query.filter(
+            id.eq_any(
+                user_roles::table
+                    .filter(user_roles::role.eq(role))
+                    .select(user_roles::user_id),
+            ),
+        );
+    }
+
+    query
+}
+
+/// Selects the column if requested, or `NULL` otherwise.
+macro_rules! projected {
+    ($selected:expr, $column:expr, $sql_type:ty) => {{
+        let expression: Box<
+            dyn BoxableExpression<users::table, Pg, SqlType = Nullable<$sql_type>>,
+        > = match $selected {
+            true => Box::new($column.nullable()),
+            false => Box::new(dsl::sql::<Nullable<$sql_type>>("NULL")),
+        };
+        expression
+    }};
+}
+
+/// Applies the keyset condition and the ordering to the query.
+///
+/// `ascending` is the effective scan direction: the sort order direction,
+/// flipped when paging backwards.
+macro_rules! keyset {
+    ($query:expr, $column:expr, $key:expr, $cursor_id:expr, $ascending:expr) => {{
+        let query = $query;
+        let query = match ($key, $cursor_id) {
+            (Some(key), Some(cursor_id)) if $ascending => query.filter(
+                $column
+                    .gt(key.clone())
+                    .or($column.eq(key).and(id.gt(cursor_id))),
+            ),
+            (Some(key), Some(cursor_id)) => query.filter(
+                $column
+                    .lt(key.clone())
+                    .or($column.eq(key).and(id.lt(cursor_id))),
+            ),
+            _ => query,
+        };
+        match $ascending {
+            true => query.order_by(($column.asc(), id.asc())),
+            false => query.order_by(($column.desc(), id.desc())),
+        }
+    }};
+}
+
+async fn load_users(
+    db: web::Data<DbPool>,
+    pagination: &'static PaginationConfig,
+    caller: &Identity,
+    filter: ListRequest,
+    cursor: Option<(UserCursor, Direction)>,
+) -> Result<UsersPage, ApiError> {
+    let caller = caller.clone();
+    let limit = filter.limit.unwrap_or(pagination.default_page_size) as usize;
+    let sort = filter.sort.unwrap_or_default();
+    let direction = cursor
+        .as_ref()
+        .map(|(_, direction)| *direction)
+        .unwrap_or(Direction::After);
+    let ascending = sort.is_ascending() == (direction == Direction::After);
+    // Sort keys are always fetched, since cursors are built from them
+    let fields = filter.field_set();
+    let fetch_name = fields.name || matches!(sort, SortOrder::NameAsc | SortOrder::NameDesc);
+    let fetch_created_at =
+        fields.created_at || matches!(sort, SortOrder::CreatedAtAsc | SortOrder::CreatedAtDesc);
+    let expansions = filter.expansions();
+
+    request_id::block(move || -> Result<UsersPage, ApiError> {
         let mut conn = db.get()?;
-        users
-            .limit(limit as i64)
-            .order_by(id)
-            .filter(id.gt(after))
-            .load::<User>(&mut conn)
-            .map_err(ApiError::from)
+        let total = match filter.include_total {
+            Some(true) => Some(count_users(&mut conn, pagination, &caller, filter.clone())?),
+            _ => None,
+        };
+        let query = filtered_users(filter, &caller);
+        let (cursor_id, cursor_name, cursor_created_at) = match cursor {
+            Some((cursor, _)) => (Some(cursor.id), cursor.name, cursor.created_at),
+            None => (None, None, None),
+        };
+
+        // Cursors are known to carry the sort key (see [ListRequest::cursor]),
+        // so the list starts from the beginning only when there is no cursor
+        let query = match sort {
+            SortOrder::IdAsc | SortOrder::IdDesc => match (cursor_id, ascending) {
+                (Some(cursor_id), true) => query.filter(id.gt(cursor_id)).order_by(id.asc()),
+                (Some(cursor_id), false) => query.filter(id.lt(cursor_id)).order_by(id.desc()),
+                (None, true) => query.order_by(id.asc()),
+                (None, false) => query.order_by(id.desc()),
+            },
+            SortOrder::NameAsc | SortOrder::NameDesc => {
+                keyset!(query, name, cursor_name, cursor_id, ascending)
+            }
+            SortOrder::CreatedAtAsc | SortOrder::CreatedAtDesc => {
+                keyset!(query, created_at, cursor_created_at, cursor_id, ascending)
+            }
+        };
+
+        // Fetch an extra record to find out whether there are more records
+        let mut user_list = query
+            .select((
+                id,
+                projected!(fields.email, email, Text),
+                projected!(fetch_name, name, Text),
+                projected!(fetch_created_at, created_at, Timestamp),
+                projected!(fields.listed, listed, Bool),
+            ))
+            .limit(limit as i64 + 1)
+            .load::<UserRow>(&mut conn)
+            .map_err(ApiError::from)?;
+        let has_more = user_list.len() > limit;
+        user_list.truncate(limit);
+        if direction == Direction::Before {
+            user_list.reverse();
+        }
+
+        let (has_next, has_prev) = match direction {
+            Direction::After => (has_more, cursor_id.is_some()),
+            Direction::Before => (true, has_more),
+        };
+        let next = has_next
+            .then(|| user_list.last().map(|user| UserCursor::new(sort, user)))
+            .flatten();
+        let prev = has_prev
+            .then(|| user_list.first().map(|user| UserCursor::new(sort, user)))
+            .flatten();
+
+        let roles = match expansions.roles {
+            true => Some(load_roles(&mut conn, &user_list)?),
+            false => None,
+        };
+
+        Ok(UsersPage {
+            users: user_list,
+            next,
+            prev,
+            total,
+            roles,
+        })
     })
     .await?
 }
+
+/// Loads the names of the roles granted to the listed users.
+fn load_roles(
+    conn: &mut PgConnection,
+    user_list: &[UserRow],
+) -> Result<HashMap<i32, Vec<String>>, ApiError> {
+    let ids: Vec<i32> = user_list.iter().map(|user| user.id).collect();
+    let grants = user_roles::table
+        .filter(user_roles::user_id.eq_any(ids))
+        .select((user_roles::user_id, user_roles::role))
+        .order_by((user_roles::user_id, user_roles::role))
+        .load::<(i32, String)>(conn)?;
+
+    let mut roles: HashMap<i32, Vec<String>> = HashMap::new();
+    for (user_id, role) in grants {
+        roles.entry(user_id).or_default().push(role);
+    }
+    Ok(roles)
+}
+
+/// Counts the users matching the filters (see [ListRequest::include_total]).
+fn count_users(
+    conn: &mut PgConnection,
+    pagination: &PaginationConfig,
+    caller: &Identity,
+    filter: ListRequest,
+) -> Result<ListTotal, ApiError> {
+    // Table statistics count every row: the unlisted users (so only admins,
+    // who see them, get the estimate) and the soft-deleted ones, which are not
+    // listed without the status filter, so they are subtracted
+    if !filter.has_filters() && caller.is_admin() {
+        let estimate = diesel::sql_query(
+            "SELECT reltuples::bigint AS estimate FROM pg_class WHERE oid = 'users'::regclass",
+        )
+        .get_result::<TableEstimate>(conn)?
+        .estimate;
+
+        // The estimate is negative if the table was never analyzed
+        if estimate >= pagination.exact_count_threshold.max(0) {
+            // Counted with the partial index of the deleted users
+            let deleted = users::table
+                .filter(deleted_at.is_not_null())
+                .select(dsl::count_star())
+                .first::<i64>(conn)?;
+            return Ok(ListTotal {
+                count: (estimate - deleted).max(0),
+                estimated: true,
+            });
+        }
+    }
+
+    let count = filtered_users(filter, caller)
+        .select(diesel::dsl::count_star())
+        .first::<i64>(conn)?;
+    Ok(ListTotal {
+        count,
+        estimated: false,
+    })
+}
+
+#[derive(QueryableByName)]
+struct TableEstimate {
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    estimate: i64,
+}
+
+/// Builds a lowercase `LIKE` pattern matching the given prefix, escaping the
+/// pattern special characters.
+fn prefix_pattern(prefix: &str) -> String {
+    let escaped = prefix
+        .to_lowercase()
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("{escaped}%")
+}
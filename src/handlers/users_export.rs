@@ -0,0 +1,295 @@
+//!
+//! Handler for handling users bulk export requests.
+//!
+//! Relies on JWT middleware to ensure authorization.
+//!
+//! Users are read with a server-side cursor (`DECLARE ... CURSOR`) within a
+//! read-only transaction, and streamed to the client batch by batch, so the
+//! memory usage does not depend on the number of exported users. Only a few
+//! encoded batches are buffered: a slow client slows the reading down.
+
+use std::io;
+
+use actix_web::http::header::{self, Header};
+use actix_web::{web, web::Bytes, HttpRequest, HttpResponse};
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::query_builder::{AstPass, QueryFragment, QueryId};
+use tokio::sync::mpsc;
+use validator::Validate;
+
+use crate::schema::users;
+use crate::{
+    errors::{ApiError, ProblemDetails},
+    middleware::{
+        jwt::Identity,
+        request_id::{self, RequestId},
+    },
+    DbPool,
+};
+
+use super::users::{filtered_users, ListRequest};
+
+/// Number of users fetched from the cursor at once.
+const BATCH_SIZE: usize = 1000;
+
+/// Number of encoded batches buffered between the database reader and the
+/// response body.
+const BUFFERED_BATCHES: usize = 2;
+
+/// Name of the server-side cursor (scoped to the export transaction).
+const CURSOR_NAME: &str = "users_export";
+
+/// Export file format, negotiated with the `Accept` header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ExportFormat {
+    /// Newline-delimited JSON: a JSON object per line.
+    NdJson,
+    /// Comma-separated values, with a header line.
+    Csv,
+}
+
+impl ExportFormat {
+    /// Picks the most preferred supported format, defaulting to NDJSON if
+    /// there is no `Accept` header.
+    fn negotiate(req: &HttpRequest) -> Option<Self> {
+        let accept = header::Accept::parse(req).ok()?;
+        if accept.is_empty() {
+            return Some(Self::NdJson);
+        }
+
+        accept
+            .ranked()
+            .iter()
+            .find_map(|mime| match mime.essence_str() {
+                "application/x-ndjson" | "application/*" | "*/*" => Some(Self::NdJson),
+                "text/csv" | "text/*" => Some(Self::Csv),
+                _ => None,
+            })
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::NdJson => "application/x-ndjson",
+            Self::Csv => "text/csv; charset=utf-8",
+        }
+    }
+
+    /// Encodes the CSV header line (nothing for NDJSON).
+    fn header(self) -> Result<Option<Bytes>, ApiError> {
+        match self {
+            Self::NdJson => Ok(None),
+            Self::Csv => {
+                let mut writer = csv::Writer::from_writer(vec![]);
+                writer
+                    .write_record(ExportedUser::FIELDS)
+                    .map_err(io::Error::from)?;
+                let buffer = writer.into_inner().map_err(|e| e.into_error())?;
+                Ok(Some(Bytes::from(buffer)))
+            }
+        }
+    }
+
+    /// Encodes the batch of users.
+    fn encode(self, batch: &[ExportedUser]) -> Result<Bytes, ApiError> {
+        match self {
+            Self::NdJson => {
+                let mut buffer = vec![];
+                for user in batch {
+                    serde_json::to_writer(&mut buffer, user).map_err(io::Error::from)?;
+                    buffer.push(b'\n');
+                }
+                Ok(Bytes::from(buffer))
+            }
+            Self::Csv => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(vec![]);
+                for user in batch {
+                    writer.serialize(user).map_err(io::Error::from)?;
+                }
+                let buffer = writer.into_inner().map_err(|e| e.into_error())?;
+                Ok(Bytes::from(buffer))
+            }
+        }
+    }
+}
+
+///
+/// Exported user record.
+///
+/// Contains all non-sensitive fields, admins see all of them anyway.
+#[derive(Debug, serde::Serialize, QueryableByName)]
+#[diesel(table_name = users)]
+struct ExportedUser {
+    id: i32,
+    email: String,
+    name: String,
+    created_at: chrono::NaiveDateTime,
+    listed: bool,
+    deleted_at: Option<chrono::NaiveDateTime>,
+}
+
+impl ExportedUser {
+    /// Field names, in the serialization order (used as the CSV header).
+    const FIELDS: [&'static str; 6] = ["id", "email", "name", "created_at", "listed", "deleted_at"];
+}
+
+///
+/// Export registered users endpoint (admin only).
+///
+/// Accepts the [ListRequest] filters (`email_prefix`, `name_prefix`,
+/// `created_after`, `created_before`, `role` and `status`). Paging, sorting
+/// and projection parameters are not applicable: all matching users are
+/// exported, ordered by id.
+/// Requires Authorization via JWT (see /auth/token handler).
+/// Streams the users as `application/x-ndjson` (default) or `text/csv`,
+/// depending on the `Accept` header. Responds with 406 if neither is
+/// acceptable.
+///
+/// Example:
+/// GET /v1/users/export?created_after=2024-05-01T00:00:00
+/// Authorization: Bearer [token]
+/// Accept: text/csv
+///
+/// Returns
+/// id,email,name,created_at,listed,deleted_at
+/// 17,john@example.org,John,2024-05-15T19:49:55.314405,true,
+/// 18,joseph@example.org,Joseph,2024-05-15T19:50:05.008961,false,
+///
+#[utoipa::path(
+    get,
+    path = "/v1/users/export",
+    operation_id = "export_users",
+    summary = "Export registered users as NDJSON or CSV (admin only)",
+    tag = "users",
+    params(ListRequest),
+    security(("bearer_auth" = [])),
+    responses(
+        (
+            status = 200,
+            description = "Matching users, a record per line",
+            content(
+                (String = "application/x-ndjson"),
+                (String = "text/csv"),
+            ),
+        ),
+        (status = 401, description = "Missing or invalid token", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Caller is not an admin", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 406, description = "Neither NDJSON nor CSV is acceptable", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Validation failed", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+pub async fn export(
+    db: web::Data<DbPool>,
+    identity: web::ReqData<Identity>,
+    req: HttpRequest,
+    query: web::Query<ListRequest>,
+) -> web::Either<HttpResponse, ApiError> {
+    if !identity.is_admin() {
+        return web::Either::Right(ApiError::Forbidden {});
+    }
+    let filter = query.into_inner();
+    if let Err(e) = filter.validate() {
+        return web::Either::Right(e.into());
+    }
+    let format = match ExportFormat::negotiate(&req) {
+        Some(format) => format,
+        None => return web::Either::Right(ApiError::NotAcceptable {}),
+    };
+
+    // Acquire the connection upfront, so that failing to do so is reported
+    // with the response status rather than with a truncated body
+    let mut conn = match request_id::block(move || db.get()).await {
+        Ok(Ok(conn)) => conn,
+        Ok(Err(e)) => return web::Either::Right(e.into()),
+        Err(e) => return web::Either::Right(e.into()),
+    };
+
+    let (sender, receiver) = mpsc::channel(BUFFERED_BATCHES);
+    let caller = identity.into_inner();
+    // The reader is detached: it stops once the response body is dropped
+    drop(actix_web::rt::task::spawn_blocking(
+        RequestId::scope_blocking(move || {
+            if let Err(e) = stream_users(&mut conn, filter, &caller, format, &sender) {
+                log::error!("Cannot export users: {}", e);
+                let _ = sender.blocking_send(Err(e));
+            }
+        }),
+    ));
+
+    let body = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+    web::Either::Left(
+        HttpResponse::Ok()
+            .content_type(format.content_type())
+            .streaming(body),
+    )
+}
+
+/// Reads the matching users with a server-side cursor and sends them to the
+/// response body, batch by batch.
+fn stream_users(
+    conn: &mut PgConnection,
+    filter: ListRequest,
+    caller: &Identity,
+    format: ExportFormat,
+    sender: &mpsc::Sender<Result<Bytes, ApiError>>,
+) -> Result<(), ApiError> {
+    conn.build_transaction()
+        .read_only()
+        .repeatable_read()
+        .run(|conn| -> Result<(), ApiError> {
+            let query = filtered_users(filter, caller)
+                .select((
+                    users::id,
+                    users::email,
+                    users::name,
+                    users::created_at,
+                    users::listed,
+                    users::deleted_at,
+                ))
+                .order_by(users::id.asc());
+            let _ = DeclareCursor { query }.execute(conn)?;
+
+            if let Some(header) = format.header()? {
+                if sender.blocking_send(Ok(header)).is_err() {
+                    return Ok(());
+                }
+            }
+            loop {
+                let batch = diesel::sql_query(format!("FETCH {BATCH_SIZE} FROM {CURSOR_NAME}"))
+                    .load::<ExportedUser>(conn)?;
+                if batch.is_empty() {
+                    return Ok(());
+                }
+                // The receiver is gone if the client has disconnected
+                if sender.blocking_send(format.encode(&batch)).is_err() {
+                    return Ok(());
+                }
+            }
+        })
+}
+
+/// `DECLARE` statement of the export cursor for the given query.
+#[derive(Debug)]
+struct DeclareCursor<Q> {
+    query: Q,
+}
+
+impl<Q: QueryFragment<Pg>> QueryFragment<Pg> for DeclareCursor<Q> {
+    fn walk_ast<'b>(&'b self, mut out: AstPass<'_, 'b, Pg>) -> QueryResult<()> {
+        out.push_sql("DECLARE ");
+        out.push_identifier(CURSOR_NAME)?;
+        out.push_sql(" NO SCROLL CURSOR FOR ");
+        self.query.walk_ast(out.reborrow())
+    }
+}
+
+impl<Q> QueryId for DeclareCursor<Q> {
+    type QueryId = ();
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<Q> RunQueryDsl<PgConnection> for DeclareCursor<Q> {}
@@ -0,0 +1,467 @@
+//!
+//! Handler for handling users bulk import requests.
+//!
+//! Relies on JWT middleware to ensure authorization.
+//!
+//! The request body is read line by line as it arrives. Valid records are
+//! inserted in batches, each within its own transaction, and the per-line
+//! results are streamed back as soon as the batch is committed, so neither
+//! the request nor the response is held in memory as a whole.
+
+use std::collections::{HashSet, VecDeque};
+use std::io;
+
+use actix_web::{web, web::Bytes, web::BytesMut, HttpResponse};
+use diesel::prelude::*;
+use futures_util::StreamExt;
+use validator::{Validate, ValidateArgs, ValidationError, ValidationErrors};
+
+use crate::config::{PasswordsConfig, ServerConfig};
+use crate::db::with_retries;
+use crate::errors::{ApiError, FieldErrorPayload, ProblemDetails};
+use crate::models::NewUser;
+use crate::schema::users;
+use crate::validation::{
+    validate_name, validate_password_hash, NAME_MAX_LENGTH, PASSWORD_MAX_LENGTH,
+    PASSWORD_MIN_LENGTH,
+};
+use crate::{
+    middleware::{jwt::Identity, request_id},
+    passwords, DbPool,
+};
+
+/// Number of records inserted within a single transaction.
+const BATCH_SIZE: usize = 500;
+
+/// Maximum length of a single request body line, in bytes.
+const LINE_MAX_LENGTH: usize = 4096;
+
+/// Users import request parameters.
+#[derive(Clone, Copy, Debug, Default, serde::Serialize, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportRequest {
+    /// Whether to validate the records and check them for conflicts only,
+    /// without creating any users (optional, default: false).
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+///
+/// Imported user record, a single line of the request body.
+///
+/// Either `password` or `password_hash` is required. See
+/// [crate::validation] for the fields validation rules; the password hash
+/// is validated against the [PasswordsConfig] limits.
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize, Validate, utoipa::ToSchema)]
+#[serde(deny_unknown_fields)]
+#[validate(context = PasswordsConfig)]
+#[validate(schema(function = "validate_credentials"))]
+pub struct ImportRecord {
+    /// User email, corresponds to the field in [crate::models::User].
+    #[validate(email)]
+    pub email: String,
+    /// User name, corresponds to the field in [crate::models::User].
+    #[validate(
+        length(min = 1, max = "NAME_MAX_LENGTH"),
+        custom(function = "validate_name")
+    )]
+    pub name: String,
+    /// Plaintext password, hashed before storing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(length(min = "PASSWORD_MIN_LENGTH", max = "PASSWORD_MAX_LENGTH"))]
+    pub password: Option<String>,
+    /// Password hash of a supported scheme (see [crate::passwords]), stored
+    /// as is. Legacy hashes are upgraded on the first login.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(custom(function = "validate_password_hash", use_context))]
+    pub password_hash: Option<String>,
+}
+
+fn validate_credentials(record: &ImportRecord) -> Result<(), ValidationError> {
+    match (&record.password, &record.password_hash) {
+        (Some(_), Some(_)) => Err(ValidationError::new("conflicting_passwords")),
+        (None, None) => Err(ValidationError::new("password_required")),
+        _ => Ok(()),
+    }
+}
+
+/// Import result of a single record.
+#[derive(Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ImportResult {
+    /// Request body line number (starting from 1).
+    pub line: usize,
+    /// Record status.
+    pub status: ImportStatus,
+    /// Created user id (not set in the dry run mode).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<i32>,
+    /// Validation errors of the invalid record (see [FieldErrorPayload]).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldErrorPayload>,
+}
+
+/// Import status of a single record.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, utoipa::ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportStatus {
+    /// The user was created (or would be, in the dry run mode).
+    Created,
+    /// A user with the same email already exists (or appears earlier in the
+    /// request body).
+    Conflict,
+    /// The record is malformed or failed validation.
+    Invalid,
+}
+
+impl ImportResult {
+    fn conflict(line: usize) -> Self {
+        Self {
+            line,
+            status: ImportStatus::Conflict,
+            id: None,
+            errors: vec![],
+        }
+    }
+
+    fn invalid(line: usize, errors: &ValidationErrors) -> Self {
+        Self {
+            line,
+            status: ImportStatus::Invalid,
+            id: None,
+            errors: FieldErrorPayload::collect(errors),
+        }
+    }
+}
+
+///
+/// Import users endpoint (admin only).
+///
+/// Accepts NDJSON body: an [ImportRecord] per line (empty lines are skipped),
+/// and [ImportRequest] query parameters.
+/// Requires Authorization via JWT (see /auth/token handler).
+/// Streams back an [ImportResult] per record as NDJSON, in the request body
+/// order. Records are committed in batches: if the import fails midway, the
+/// response is cut short, and the users with the streamed results are kept.
+///
+/// Example:
+/// POST /v1/users/import?dry_run=false
+/// Authorization: Bearer [token]
+/// Content-Type: application/x-ndjson
+///
+/// {"email": "john@example.org", "name": "John", "password": "secr3t-pass"}
+/// {"email": "jane@example.org", "name": "Jane", "password_hash": "$argon2id$v=19$..."}
+/// {"email": "john@example.org", "name": "John", "password": "secr3t-pass"}
+/// {"email": "not an email", "name": "Joe", "password": "secr3t-pass"}
+///
+/// Returns
+/// {"line":1,"status":"created","id":17}
+/// {"line":2,"status":"created","id":18}
+/// {"line":3,"status":"conflict"}
+/// {"line":4,"status":"invalid","errors":[{"field":"email","code":"email","params":{}}]}
+///
+#[utoipa::path(
+    post,
+    path = "/v1/users/import",
+    operation_id = "import_users",
+    summary = "Import users from NDJSON (admin only)",
+    tag = "users",
+    params(ImportRequest),
+    request_body(
+        content = ImportRecord,
+        description = "A record per line",
+        content_type = "application/x-ndjson",
+    ),
+    security(("bearer_auth" = [])),
+    responses(
+        (
+            status = 200,
+            description = "A result per record, in the request body order",
+            body = ImportResult,
+            content_type = "application/x-ndjson",
+        ),
+        (status = 401, description = "Missing or invalid token", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Caller is not an admin", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+pub async fn import(
+    db: web::Data<DbPool>,
+    cfg: web::Data<&'static ServerConfig>,
+    identity: web::ReqData<Identity>,
+    query: web::Query<ImportRequest>,
+    payload: web::Payload,
+) -> web::Either<HttpResponse, ApiError> {
+    if !identity.is_admin() {
+        return web::Either::Right(ApiError::Forbidden {});
+    }
+
+    let importer = Importer {
+        db,
+        cfg: &cfg.passwords,
+        dry_run: query.dry_run,
+        payload,
+        buffer: BytesMut::new(),
+        line: 0,
+        skipping_line: false,
+        finished: false,
+        batch: Vec::new(),
+        results: VecDeque::new(),
+        dry_run_emails: HashSet::new(),
+    };
+    let body = futures_util::stream::unfold(importer, |mut importer| async move {
+        importer.next_chunk().await.map(|chunk| (chunk, importer))
+    });
+    web::Either::Left(
+        HttpResponse::Ok()
+            .content_type("application/x-ndjson")
+            .streaming(body),
+    )
+}
+
+/// Import state, driven by the response body stream.
+struct Importer {
+    db: web::Data<DbPool>,
+    cfg: &'static PasswordsConfig,
+    dry_run: bool,
+    payload: web::Payload,
+    /// Incomplete line read from the payload.
+    buffer: BytesMut,
+    /// Number of the last line taken from the buffer.
+    line: usize,
+    /// Whether the rest of the current (too long) line is being dropped.
+    skipping_line: bool,
+    /// Whether the payload was read to the end.
+    finished: bool,
+    /// Valid records to be inserted, along with their line numbers.
+    batch: Vec<(usize, ImportRecord)>,
+    /// Results which are ready to be sent (invalid records, mostly).
+    results: VecDeque<ImportResult>,
+    /// Emails of the records batched so far, in the dry run mode only: the
+    /// batches are rolled back, so the database does not report the emails
+    /// repeated across the batches as conflicting.
+    dry_run_emails: HashSet<String>,
+}
+
+impl Importer {
+    /// Reads the payload until the batch is full (or the payload is over),
+    /// inserts the batch, and returns the encoded results.
+    ///
+    /// Returns `None` once everything is sent, and an error if the import
+    /// cannot be continued.
+    async fn next_chunk(&mut self) -> Option<Result<Bytes, ApiError>> {
+        while self.batch.len() < BATCH_SIZE && !self.finished {
+            if let Some(line) = self.take_line() {
+                self.parse_line(&line);
+                continue;
+            }
+
+            match self.payload.next().await {
+                Some(Ok(chunk)) => self.buffer.extend_from_slice(&chunk),
+                Some(Err(e)) => {
+                    self.finished = true;
+                    self.batch.clear();
+                    return Some(Err(io::Error::new(io::ErrorKind::InvalidData, e).into()));
+                }
+                None => {
+                    self.finished = true;
+                    if let Some(line) = self.take_last_line() {
+                        self.parse_line(&line);
+                    }
+                }
+            }
+        }
+
+        if !self.batch.is_empty() {
+            let batch = std::mem::take(&mut self.batch);
+            match insert_batch(self.db.clone(), self.cfg, batch, self.dry_run).await {
+                Ok(results) => self.results.extend(results),
+                Err(e) => {
+                    log::error!("Cannot import users: {}", e);
+                    self.finished = true;
+                    self.results.clear();
+                    return Some(Err(e));
+                }
+            }
+        }
+        if self.results.is_empty() {
+            return None;
+        }
+
+        // Invalid records are reported as soon as they are read, so the
+        // results of the batch may be interleaved with them
+        let mut results: Vec<ImportResult> = self.results.drain(..).collect();
+        results.sort_by_key(|result| result.line);
+        let mut buffer = vec![];
+        for result in results {
+            if let Err(e) = serde_json::to_writer(&mut buffer, &result) {
+                return Some(Err(io::Error::from(e).into()));
+            }
+            buffer.push(b'\n');
+        }
+        Some(Ok(Bytes::from(buffer)))
+    }
+
+    /// Takes the next complete line from the buffer, dropping the lines which
+    /// are too long (they are reported as invalid).
+    fn take_line(&mut self) -> Option<Bytes> {
+        loop {
+            let end = self.buffer.iter().position(|&b| b == b'\n');
+            if self.skipping_line {
+                match end {
+                    Some(end) => {
+                        let _ = self.buffer.split_to(end + 1);
+                        self.skipping_line = false;
+                        continue;
+                    }
+                    None => {
+                        self.buffer.clear();
+                        return None;
+                    }
+                }
+            }
+
+            match end {
+                Some(end) if end <= LINE_MAX_LENGTH => {
+                    self.line += 1;
+                    let line = self.buffer.split_to(end + 1).freeze();
+                    return Some(line.slice(..end));
+                }
+                None if self.buffer.len() <= LINE_MAX_LENGTH => return None,
+                _ => {
+                    self.line += 1;
+                    self.skipping_line = true;
+                    self.results
+                        .push_back(ImportResult::invalid(self.line, &line_error("too_long")));
+                }
+            }
+        }
+    }
+
+    /// Takes the last line (not terminated with a newline) from the buffer.
+    ///
+    /// Expects [Self::take_line] to be called first: the rest of the buffer
+    /// is then not too long.
+    fn take_last_line(&mut self) -> Option<Bytes> {
+        match self.buffer.is_empty() {
+            true => None,
+            false => {
+                self.line += 1;
+                Some(self.buffer.split().freeze())
+            }
+        }
+    }
+
+    /// Parses and validates the line, adding the record to the batch, or
+    /// reporting it as invalid.
+    fn parse_line(&mut self, line: &[u8]) {
+        if line.trim_ascii().is_empty() {
+            return;
+        }
+
+        // Parsing errors are not detailed, since they may quote the password
+        let record = match serde_json::from_slice::<ImportRecord>(line) {
+            Ok(record) => record,
+            Err(_) => {
+                let result = ImportResult::invalid(self.line, &line_error("malformed"));
+                return self.results.push_back(result);
+            }
+        };
+        match record.validate_with_args(self.cfg) {
+            Ok(()) if self.dry_run && !self.dry_run_emails.insert(record.email.clone()) => {
+                self.results.push_back(ImportResult::conflict(self.line))
+            }
+            Ok(()) => self.batch.push((self.line, record)),
+            Err(errors) => self
+                .results
+                .push_back(ImportResult::invalid(self.line, &errors)),
+        }
+    }
+}
+
+/// Builds validation errors of the whole line.
+fn line_error(code: &'static str) -> ValidationErrors {
+    let mut errors = ValidationErrors::new();
+    errors.add("line", ValidationError::new(code));
+    errors
+}
+
+/// Batch transaction failure.
+enum BatchError {
+    Api(ApiError),
+    /// Dry run rollback, carrying the batch results.
+    DryRun(Vec<ImportResult>),
+}
+
+impl From<diesel::result::Error> for BatchError {
+    fn from(e: diesel::result::Error) -> Self {
+        Self::Api(e.into())
+    }
+}
+
+/// Inserts the batch of valid records within a single transaction, skipping
+/// the conflicting ones.
+///
+/// In the dry run mode, the transaction is rolled back.
+async fn insert_batch(
+    db: web::Data<DbPool>,
+    cfg: &'static PasswordsConfig,
+    batch: Vec<(usize, ImportRecord)>,
+    dry_run: bool,
+) -> Result<Vec<ImportResult>, ApiError> {
+    request_id::block(move || -> Result<Vec<ImportResult>, ApiError> {
+        // Hash the passwords before starting the transaction, to keep it short
+        let mut new_users = Vec::with_capacity(batch.len());
+        for (line, record) in batch {
+            let hashed_password = match (record.password, record.password_hash) {
+                // The transaction is rolled back anyway, so save the effort
+                (Some(_), _) if dry_run => String::new(),
+                (Some(password), _) => passwords::hash(&password, cfg)?,
+                (None, Some(password_hash)) => password_hash,
+                (None, None) => unreachable!("Records are validated"),
+            };
+            let new_user = NewUser {
+                email: record.email,
+                name: record.name,
+                hashed_password,
+            };
+            new_users.push((line, new_user));
+        }
+
+        // Concurrent imports may deadlock on conflicting emails, so the whole
+        // transaction is retried
+        with_retries(|| {
+            let mut conn = db.get()?;
+            let outcome = conn.transaction(|conn| -> Result<Vec<ImportResult>, BatchError> {
+                let mut results = Vec::with_capacity(new_users.len());
+                for (line, new_user) in &new_users {
+                    let created_id = diesel::insert_into(users::table)
+                        .values(new_user)
+                        .on_conflict_do_nothing()
+                        .returning(users::id)
+                        .get_result::<i32>(conn)
+                        .optional()?;
+                    results.push(ImportResult {
+                        line: *line,
+                        status: match created_id {
+                            Some(_) => ImportStatus::Created,
+                            None => ImportStatus::Conflict,
+                        },
+                        id: created_id.filter(|_| !dry_run),
+                        errors: vec![],
+                    });
+                }
+                match dry_run {
+                    true => Err(BatchError::DryRun(results)),
+                    false => Ok(results),
+                }
+            });
+
+            match outcome {
+                Ok(results) | Err(BatchError::DryRun(results)) => Ok(results),
+                Err(BatchError::Api(e)) => Err(e),
+            }
+        })
+    })
+    .await?
+}
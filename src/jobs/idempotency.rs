@@ -0,0 +1,39 @@
+//!
+//! Expired idempotency keys purge job.
+//!
+//! Removes idempotency keys (along with the stored responses) whose time to
+//! live has passed, so the table does not grow unbounded. Expired keys are
+//! never replayed anyway.
+
+use std::time::Duration;
+
+use actix_web::web;
+
+use crate::{config::IdempotencyConfig, errors::ApiError, models::IdempotencyKey, DbPool};
+
+///
+/// Run the purge job forever, once per configured interval.
+///
+/// Errors are logged and do not stop the job.
+pub async fn run(db: web::Data<DbPool>, cfg: &'static IdempotencyConfig) {
+    let mut interval =
+        actix_rt::time::interval(Duration::from_secs(cfg.purge_interval_secs.max(1)));
+
+    loop {
+        let _ = interval.tick().await;
+        match purge(db.clone()).await {
+            Ok(0) => {}
+            Ok(purged) => log::info!("Purged {} expired idempotency key(s)", purged),
+            Err(e) => log::error!("Failed to purge expired idempotency keys: {}", e),
+        }
+    }
+}
+
+///
+/// Permanently remove the idempotency keys which have expired.
+///
+/// Returns the number of removed keys.
+pub async fn purge(db: web::Data<DbPool>) -> Result<usize, ApiError> {
+    let now = chrono::Utc::now().naive_utc();
+    web::block(move || IdempotencyKey::purge_expired(now, db)).await?
+}
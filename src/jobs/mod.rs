@@ -0,0 +1,5 @@
+//!
+//! Module contains background jobs run by the server.
+
+pub mod idempotency;
+pub mod purge;
@@ -0,0 +1,41 @@
+//!
+//! Deleted users purge job.
+//!
+//! Permanently removes users that were deleted more than the configured grace
+//! period ago. Roles are removed along with the user (via `ON DELETE CASCADE`).
+
+use std::time::Duration;
+
+use actix_web::web;
+
+use crate::{config::AccountsConfig, errors::ApiError, models::User, DbPool};
+
+///
+/// Run the purge job forever, once per configured interval.
+///
+/// Errors are logged and do not stop the job.
+pub async fn run(db: web::Data<DbPool>, cfg: &'static AccountsConfig) {
+    let mut interval =
+        actix_rt::time::interval(Duration::from_secs(cfg.purge_interval_secs.max(1)));
+
+    loop {
+        let _ = interval.tick().await;
+        match purge(db.clone(), cfg.deletion_grace_period()).await {
+            Ok(0) => {}
+            Ok(purged) => log::info!("Purged {} deleted user(s)", purged),
+            Err(e) => log::error!("Failed to purge deleted users: {}", e),
+        }
+    }
+}
+
+///
+/// Permanently remove users deleted more than `grace_period` ago.
+///
+/// Returns the number of removed users.
+pub async fn purge(
+    db: web::Data<DbPool>,
+    grace_period: chrono::Duration,
+) -> Result<usize, ApiError> {
+    let deleted_before = chrono::Utc::now().naive_utc() - grace_period;
+    web::block(move || User::purge_deleted(deleted_before, db)).await?
+}
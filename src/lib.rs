@@ -1,4 +1,4 @@
-//! Library crate containing REST API service code.
+//! Library crate containing REST (and gRPC) API service code.
 //!
 //! Used by binary target and by integration tests.
 
@@ -10,12 +10,22 @@
 )]
 
 pub mod config;
+pub mod db;
+pub mod encoding;
 pub mod errors;
+pub mod export;
+pub mod graphql;
+pub mod grpc;
 pub mod handlers;
+pub mod jobs;
 pub mod middleware;
 pub mod models;
+pub mod pagination;
+pub mod passwords;
+pub mod routes;
 #[allow(missing_docs)]
 pub mod schema;
+pub mod validation;
 
 use diesel::{r2d2::ConnectionManager, PgConnection};
 /// Database pool datatype.
@@ -1,64 +1,122 @@
 //!
 //! The entry point for the web service.
 //!
-//! The endpoints are:
-//! - POST /user: create a new user.
-//! - POST /auth/token: crate a new access token
-//! - GET /users: get a list of registered users
+//! The endpoints are (the API ones are also served under the unversioned
+//! paths, e.g. /user, see na::middleware::api_version):
+//! - POST /v1/user: create a new user.
+//! - GET /v1/user/me: get the authorized user
+//! - PATCH /v1/user/me: update the authorized user profile
+//! - DELETE /v1/user/me: delete the authorized user
+//! - GET /v1/user/me/export: export the authorized user personal data
+//! - DELETE /v1/user/{id}: delete a user by id (admin only)
+//! - POST /v1/user/{id}/restore: restore a deleted user (admin only)
+//! - GET /v1/user/{id}: get a single user by id
+//! - POST /v1/auth/token: crate a new access token
+//! - GET /v1/users: get a list of registered users
+//! - GET /v1/users/search: search registered users by name or email
+//! - POST /v1/graphql: query users and their roles, register and update the profile (GraphQL)
+//! - GET /v1/users/export: export registered users as NDJSON or CSV (admin only)
+//! - POST /v1/users/import: import users from NDJSON (admin only)
+//! - POST /oauth/introspect: introspect an access token (RFC 7662, OAuth clients only)
+//! - GET /openapi.json: get the OpenAPI specification of the API
+//! - GET /docs: browse the API documentation (Swagger UI, if enabled)
+//!
+//! The gRPC services (see na::grpc and proto/na.proto) are served on their
+//! own port:
+//! - na.v1.Auth: create and validate access tokens
+//! - na.v1.Users: register, get and list users
+//!
+//! The service metrics (Prometheus text format) are served at /metrics on
+//! their own port as well, for the internal network only (see
+//! na::config::MetricsConfig).
 
-use actix_web::{error::*, web, App, HttpResponse, HttpServer};
+use actix_web::{web, App, HttpServer};
 use diesel::{r2d2::ConnectionManager, PgConnection};
 use na::config::ServerConfig;
-use na::middleware::jwt::JwtMiddleware;
-use na::{errors, handlers, DbPool};
+use na::middleware::request_id::RequestId;
+use na::{grpc, jobs, routes, DbPool};
+use std::io::Write;
 
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
-    env_logger::init();
+    init_logger();
 
     let cfg = ServerConfig::new_leaked();
 
     start_http_listener(cfg).await
 }
 
+/// Initializes the logger, attaching the id of the request being handled (if
+/// any) to the log lines (see na::middleware::request_id).
+fn init_logger() {
+    env_logger::Builder::from_default_env()
+        .format(|buf, record| {
+            let request_id = RequestId::current()
+                .map(|id| format!(" request_id={id}"))
+                .unwrap_or_default();
+            writeln!(
+                buf,
+                "[{} {:<5} {}{}] {}",
+                buf.timestamp(),
+                record.level(),
+                record.target(),
+                request_id,
+                record.args()
+            )
+        })
+        .init();
+}
+
 async fn start_http_listener(cfg: &'static ServerConfig) -> std::io::Result<()> {
     let manager = ConnectionManager::<PgConnection>::new(&cfg.database.url);
     let db_pool: DbPool = r2d2::Pool::builder()
         .build(manager)
         .expect("Failed to create pool.");
 
+    actix_rt::spawn(jobs::purge::run(
+        web::Data::new(db_pool.clone()),
+        &cfg.accounts,
+    ));
+    actix_rt::spawn(jobs::idempotency::run(
+        web::Data::new(db_pool.clone()),
+        &cfg.idempotency,
+    ));
+
+    let grpc_addr = cfg.grpc.as_bind_str();
+    let grpc_listener = tokio::net::TcpListener::bind(&grpc_addr).await?;
+    log::info!("Starting gRPC API listener on {grpc_addr}");
+    let grpc_db = web::Data::new(db_pool.clone());
+    actix_rt::spawn(async move {
+        if let Err(e) = grpc::serve(grpc_listener, grpc_db, cfg).await {
+            log::error!("gRPC API listener failed: {}", e);
+        }
+    });
+
+    let metrics_addr = cfg.metrics.as_bind_str();
+    log::info!("Starting metrics listener on {metrics_addr}");
+    let metrics_db = db_pool.clone();
+    let metrics_server = HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(metrics_db.clone()))
+            .configure(routes::configure_metrics())
+    })
+    .workers(1)
+    .bind(&metrics_addr)?
+    .run();
+
     let bind_addr = cfg.http.as_bind_str();
     log::info!("Starting REST API listener on {bind_addr}");
 
-    HttpServer::new(move || {
+    let api_server = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(db_pool.clone()))
             .app_data(web::Data::new(cfg))
-            .app_data(
-                web::JsonConfig::default()
-                    .limit(4096)
-                    .error_handler(|err, _req| {
-                        let error_reason = err.to_string();
-                        InternalError::from_response(
-                            err,
-                            HttpResponse::BadRequest().json(errors::ErrorPayload {
-                                reason: error_reason.as_str(),
-                            }),
-                        )
-                        .into()
-                    }),
-            )
-            .service(web::resource("/user").route(web::post().to(handlers::user::register)))
-            .service(web::resource("/auth/token").route(web::post().to(handlers::auth::token)))
-            .service(
-                web::resource("/users")
-                    .wrap(JwtMiddleware {
-                        jwt_config: &cfg.jwt,
-                    })
-                    .route(web::get().to(handlers::users::list)),
-            )
+            .configure(routes::configure(cfg))
     })
     .bind(cfg.http.as_bind_str())?
-    .run()
-    .await
+    .run();
+
+    futures_util::future::try_join(api_server, metrics_server)
+        .await
+        .map(|_| ())
 }
@@ -0,0 +1,202 @@
+//!
+//! API version middleware
+//!
+//! The API is served under versioned paths (`/v1/...`), so a version keeps
+//! its shapes while the next one changes them. The unversioned paths are
+//! kept for the existing clients: the version is negotiated with the
+//! `Api-Version` request header there.
+//!
+//! Every response carries the `Api-Version` header, naming the version which
+//! served the request.
+//!
+//! Deprecated requests (currently, the requests to the unversioned paths
+//! which do not specify the version) are served as usual, but their
+//! responses carry the deprecation headers:
+//! - `Deprecation`: the deprecation datetime (RFC 9745)
+//! - `Sunset`: the datetime the requests may stop being served (RFC 8594)
+//! - `Link`: the path of the successor version (`rel="successor-version"`)
+
+use crate::config::VersioningConfig;
+use crate::errors::{ErrorCode, ProblemDetails};
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{self, HeaderMap, HeaderName, HeaderValue},
+    Error,
+};
+use chrono::{DateTime, Utc};
+use futures_util::future::LocalBoxFuture;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+/// Request (and response) header carrying the API version.
+pub const API_VERSION: HeaderName = HeaderName::from_static("api-version");
+
+/// Response header carrying the deprecation datetime (RFC 9745).
+pub const DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+
+/// Response header carrying the sunset datetime (RFC 8594).
+pub const SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+/// Versions served by the application, oldest first.
+pub const API_VERSIONS: [u32; 1] = [1];
+
+/// Returns the path prefix of the given API version (e.g. `/v1`).
+pub fn version_prefix(version: u32) -> String {
+    format!("/v{version}")
+}
+
+///
+/// Deprecation of the requests, signalled with the response headers.
+#[derive(Copy, Clone, Debug)]
+pub struct Deprecation {
+    /// Datetime since which the requests are deprecated.
+    pub deprecated_at: DateTime<Utc>,
+    /// Datetime after which the requests may stop being served, if any.
+    pub sunset_at: Option<DateTime<Utc>>,
+}
+
+impl Deprecation {
+    /// Deprecation of the requests not specifying the version.
+    pub fn unversioned(cfg: &VersioningConfig) -> Self {
+        Self {
+            deprecated_at: cfg.unversioned_deprecated_at,
+            sunset_at: cfg.unversioned_sunset_at,
+        }
+    }
+
+    /// Adds the deprecation headers to the response, pointing to the given
+    /// successor path.
+    fn insert_headers(&self, headers: &mut HeaderMap, successor: &str) {
+        let deprecation = format!("@{}", self.deprecated_at.timestamp());
+        let mut values = vec![(DEPRECATION, deprecation)];
+        if let Some(sunset_at) = self.sunset_at {
+            let sunset = sunset_at.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+            values.push((SUNSET, sunset));
+        }
+        values.push((
+            header::LINK,
+            format!("<{successor}>; rel=\"successor-version\""),
+        ));
+
+        for (name, value) in values {
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                headers.append(name, value);
+            }
+        }
+    }
+}
+
+///
+/// API version middleware factory.
+#[derive(Copy, Clone, Debug)]
+pub struct ApiVersionMiddleware {
+    /// Version served by the wrapped routes.
+    pub version: u32,
+    /// Deprecation of the requests not specifying the version, if the wrapped
+    /// routes are unversioned (so the version is negotiated with the
+    /// `Api-Version` header). `None` for the versioned routes.
+    pub unversioned: Option<Deprecation>,
+}
+
+impl ApiVersionMiddleware {
+    /// Creates the middleware for the routes under the version path prefix.
+    pub fn versioned(version: u32) -> Self {
+        Self {
+            version,
+            unversioned: None,
+        }
+    }
+
+    /// Creates the middleware for the unversioned routes, served by the given
+    /// version.
+    pub fn unversioned(version: u32, cfg: &VersioningConfig) -> Self {
+        Self {
+            version,
+            unversioned: Some(Deprecation::unversioned(cfg)),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ApiVersionMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ApiVersionMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ApiVersionMiddlewareService {
+            service: Rc::new(service),
+            version: self.version,
+            unversioned: self.unversioned,
+        }))
+    }
+}
+
+/// API version middleware service, responsible for the version negotiation
+/// and the deprecation signalling.
+#[derive(Debug)]
+pub struct ApiVersionMiddlewareService<S> {
+    service: Rc<S>,
+    version: u32,
+    unversioned: Option<Deprecation>,
+}
+
+impl<S, B> Service<ServiceRequest> for ApiVersionMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let version = self.version;
+
+        // The path version takes precedence over the header
+        let mut deprecation = None;
+        if let Some(unversioned) = self.unversioned {
+            match req.headers().get(API_VERSION) {
+                None => deprecation = Some(unversioned),
+                Some(value) if value.to_str().ok() == Some(&version.to_string()) => {}
+                Some(_) => return Box::pin(ready(Ok(reject(req)))),
+            }
+        }
+        let successor = format!("{}{}", version_prefix(version), req.path());
+
+        Box::pin(async move {
+            let mut res = service.call(req).await?.map_into_left_body();
+            let headers = res.headers_mut();
+            let _ = headers.insert(API_VERSION, HeaderValue::from(version));
+            if let Some(deprecation) = deprecation {
+                deprecation.insert_headers(headers, &successor);
+            }
+            Ok(res)
+        })
+    }
+}
+
+/// Builds the response rejecting the request of an unsupported version,
+/// rendered as [ProblemDetails].
+fn reject<B>(req: ServiceRequest) -> ServiceResponse<EitherBody<B>> {
+    let supported: Vec<String> = API_VERSIONS.iter().map(u32::to_string).collect();
+    let resp = ProblemDetails::new(ErrorCode::NotAcceptable)
+        .with_detail(format!(
+            "Unsupported API version, supported versions: {}",
+            supported.join(", ")
+        ))
+        .with_request(req.request())
+        .into_response();
+    req.into_response(resp).map_into_right_body()
+}
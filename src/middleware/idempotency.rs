@@ -0,0 +1,347 @@
+//!
+//! Idempotency middleware
+//!
+//! Makes the retries of POST requests safe: a client may send the
+//! `Idempotency-Key` header (a unique value, e.g. a UUID), and repeat the
+//! request with the same key if the response is lost. The request is handled
+//! once, and its response is stored for the configured period
+//! (`idempotency.ttl_secs`) and replayed on the retries, along with the
+//! `Idempotent-Replayed: true` header.
+//!
+//! Keys are scoped to the caller (the authorized user, if any), and bound to
+//! the request fingerprint (the method, the path, the `Content-Type` and
+//! `Accept` headers and the body): reusing a key with a different request is
+//! rejected with 422, while a retry racing the original request is rejected
+//! with 409. The original request holds the key for a limited period only
+//! (`idempotency.lock_timeout_secs`): if it is still in progress after that,
+//! e.g. because the server crashed meanwhile, a retry takes the key over.
+//! The request the key was taken from then neither stores its response nor
+//! releases the key (its lease is fenced off, see [IdempotencyKey::complete]).
+//! Server-side failures (5xx) are not stored, so such requests may be
+//! retried.
+//!
+//! The fingerprint is an HMAC keyed with `idempotency.fingerprint_secret`, so
+//! the stored ones reveal nothing of the request bodies, which may carry
+//! passwords. Responses marked with `Cache-Control: no-store` (e.g. the ones
+//! carrying credentials) are never stored either: the key is released, and
+//! the retries are handled again.
+//!
+//! Requests without the header are handled as usual. If the wrapped resource
+//! requires authorization, the middleware must be wrapped by the JWT one, so
+//! the keys are scoped to the authorized user.
+
+use crate::config::IdempotencyConfig;
+use crate::errors::{ApiError, ErrorCode, ProblemDetails};
+use crate::middleware::{jwt::Identity, request_id};
+use crate::models::{IdempotencyClaim, IdempotencyKey};
+use crate::DbPool;
+use actix_web::{
+    body::{self, BoxBody, MessageBody},
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    error::PayloadError,
+    http::{
+        header::{self, HeaderName, HeaderValue},
+        Method, StatusCode,
+    },
+    web, Error, HttpMessage, HttpResponse, Responder,
+};
+use futures_util::{future::LocalBoxFuture, Stream};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::future::{ready, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+
+/// Request header carrying the idempotency key.
+pub const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+
+/// Response header marking the replayed responses.
+pub const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+/// Maximum length of the idempotency key, in characters.
+const KEY_MAX_LENGTH: usize = 255;
+
+type HmacSha256 = Hmac<Sha256>;
+
+///
+/// Idempotency middleware factory.
+/// Contains idempotency part of server configuration.
+#[derive(Copy, Clone, Debug)]
+pub struct IdempotencyMiddleware {
+    /// Idempotency part of configuration.
+    pub idempotency_config: &'static IdempotencyConfig,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for IdempotencyMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = IdempotencyMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(IdempotencyMiddlewareService {
+            idempotency_cfg: self.idempotency_config,
+            service: Rc::new(service),
+        }))
+    }
+}
+
+/// Idempotency middleware service, responsible for replaying the responses
+/// to the retried requests.
+#[derive(Debug)]
+pub struct IdempotencyMiddlewareService<S> {
+    service: Rc<S>,
+    idempotency_cfg: &'static IdempotencyConfig,
+}
+
+impl<S, B> Service<ServiceRequest> for IdempotencyMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let cfg = self.idempotency_cfg;
+        let (ttl, lock_timeout) = (cfg.ttl(), cfg.lock_timeout());
+
+        Box::pin(async move {
+            let key = match req.headers().get(IDEMPOTENCY_KEY) {
+                Some(value) if req.method() == Method::POST => value.to_str().ok(),
+                _ => return Ok(service.call(req).await?.map_into_boxed_body()),
+            };
+            let Some(key) = key.filter(|key| is_valid_key(key)).map(str::to_string) else {
+                let problem =
+                    ProblemDetails::new(ErrorCode::MalformedRequest).with_detail(format!(
+                        "Idempotency-Key must be 1 to {KEY_MAX_LENGTH} visible ASCII characters"
+                    ));
+                return Ok(reject(req, problem));
+            };
+            let Some(db) = req.app_data::<web::Data<DbPool>>().cloned() else {
+                return Ok(reject(req, ProblemDetails::new(ErrorCode::InternalError)));
+            };
+            let principal = match req.extensions().get::<Identity>() {
+                Some(identity) => IdempotencyKey::user_principal(identity.user_id),
+                None => "anonymous".to_string(),
+            };
+
+            // The body is needed for the fingerprint, so it is read here and
+            // handed over to the handler afterwards
+            let body = match req.extract::<web::Bytes>().await {
+                Ok(body) => body,
+                Err(e) => return Ok(req.error_response(e)),
+            };
+            let fingerprint = fingerprint(&req, &body, &cfg.fingerprint_secret);
+            req.set_payload(bytes_payload(body));
+
+            let claim = {
+                let (principal, key, db) = (principal.clone(), key.clone(), db.clone());
+                request_id::block(move || {
+                    IdempotencyKey::claim(&principal, &key, &fingerprint, ttl, lock_timeout, db)
+                })
+                .await
+                .map_err(ApiError::from)
+                .and_then(|claim| claim)
+            };
+            let lease = match claim {
+                Ok(IdempotencyClaim::Claimed(lease)) => lease,
+                Ok(IdempotencyClaim::InProgress) => {
+                    return Ok(reject(
+                        req,
+                        ProblemDetails::new(ErrorCode::IdempotencyKeyInUse),
+                    ))
+                }
+                Ok(IdempotencyClaim::Mismatch) => {
+                    return Ok(reject(
+                        req,
+                        ProblemDetails::new(ErrorCode::IdempotencyKeyReused),
+                    ))
+                }
+                Ok(IdempotencyClaim::Completed(record)) => {
+                    let resp = replay(&record);
+                    return Ok(req.into_response(resp));
+                }
+                Err(e) => {
+                    let resp = e.respond_to(req.request());
+                    return Ok(req.into_response(resp));
+                }
+            };
+
+            let res = match service.call(req).await {
+                Ok(res) => res,
+                Err(e) => {
+                    release(principal, key, lease, db).await;
+                    return Err(e);
+                }
+            };
+            let (http_req, resp) = res.into_parts();
+            let (head, resp_body) = resp.into_parts();
+            let resp_body = match body::to_bytes(resp_body).await {
+                Ok(resp_body) => resp_body,
+                Err(e) => {
+                    release(principal, key, lease, db).await;
+                    return Err(actix_web::error::ErrorInternalServerError(e.into()));
+                }
+            };
+            let resp = head.set_body(resp_body);
+
+            if resp.status().is_server_error() || is_no_store(&resp) {
+                release(principal, key, lease, db).await;
+            } else {
+                store(principal, key, lease, &resp, db).await;
+            }
+            Ok(ServiceResponse::new(http_req, resp.map_into_boxed_body()))
+        })
+    }
+}
+
+/// Returns `true` if the key is not too long, and consists of visible ASCII
+/// characters.
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && key.len() <= KEY_MAX_LENGTH && key.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// Computes the request fingerprint: the HMAC of the method, the path (along
+/// with the query string), the negotiation headers and the body.
+fn fingerprint(req: &ServiceRequest, body: &[u8], secret: &str) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(req.method().as_str().as_bytes());
+    mac.update(b"\n");
+    mac.update(req.path().as_bytes());
+    mac.update(b"?");
+    mac.update(req.query_string().as_bytes());
+    mac.update(b"\n");
+    for name in [header::CONTENT_TYPE, header::ACCEPT] {
+        if let Some(value) = req.headers().get(name) {
+            mac.update(value.as_bytes());
+        }
+        mac.update(b"\n");
+    }
+    mac.update(body);
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+/// Returns `true` if the response must not be stored (`Cache-Control:
+/// no-store`), e.g. because it carries credentials.
+fn is_no_store<B>(resp: &HttpResponse<B>) -> bool {
+    resp.headers()
+        .get_all(header::CACHE_CONTROL)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|directive| directive.trim().eq_ignore_ascii_case("no-store"))
+}
+
+/// Wraps the already read body into a payload, to be read by the handler.
+fn bytes_payload(body: web::Bytes) -> Payload {
+    let stream = futures_util::stream::once(async { Ok::<_, PayloadError>(body) });
+    let stream: Pin<Box<dyn Stream<Item = Result<web::Bytes, PayloadError>>>> = Box::pin(stream);
+    Payload::from(stream)
+}
+
+/// Builds the response rejecting the request, rendered as [ProblemDetails].
+fn reject(req: ServiceRequest, problem: ProblemDetails) -> ServiceResponse<BoxBody> {
+    let resp = problem.with_request(req.request()).into_response();
+    req.into_response(resp)
+}
+
+/// Rebuilds the stored response.
+fn replay(record: &IdempotencyKey) -> HttpResponse {
+    let status = u16::try_from(record.status.unwrap_or_default())
+        .ok()
+        .and_then(|status| StatusCode::from_u16(status).ok())
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let headers: Vec<(String, String)> = serde_json::from_str(&record.headers).unwrap_or_default();
+
+    let mut resp = HttpResponse::with_body(status, record.body.clone());
+    for (name, value) in headers {
+        if let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::try_from(value)) {
+            resp.headers_mut().append(name, value);
+        }
+    }
+    let _ = resp
+        .headers_mut()
+        .insert(IDEMPOTENT_REPLAYED, HeaderValue::from_static("true"));
+    resp.map_into_boxed_body()
+}
+
+/// Stores the response, to be replayed on retries.
+///
+/// Failures are logged only: the request was handled anyway. The key is
+/// released then, so the retries are not rejected until the key expires.
+/// Neither is done if the key was taken over by a retry meanwhile.
+async fn store(
+    principal: String,
+    key: String,
+    lease: chrono::NaiveDateTime,
+    resp: &HttpResponse<web::Bytes>,
+    db: web::Data<DbPool>,
+) {
+    let status = resp.status().as_u16() as i16;
+    let headers: Vec<(&str, &str)> = resp
+        .headers()
+        .iter()
+        .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)))
+        .collect();
+    let headers = serde_json::to_string(&headers).unwrap_or_default();
+    let resp_body = resp.body().clone();
+
+    let stored = {
+        let (principal, key, db) = (principal.clone(), key.clone(), db.clone());
+        request_id::block(move || {
+            IdempotencyKey::complete(&principal, &key, lease, status, &headers, &resp_body, db)
+        })
+        .await
+    };
+    match stored {
+        Ok(Ok(true)) => {}
+        Ok(Ok(false)) => log_lease_lost(&key),
+        Ok(Err(e)) => {
+            log::error!("Failed to store the idempotent response: {}", e);
+            release(principal, key, lease, db).await;
+        }
+        Err(e) => {
+            log::error!("Failed to store the idempotent response: {}", e);
+            release(principal, key, lease, db).await;
+        }
+    }
+}
+
+/// Releases the key, so the request may be retried, unless the key was taken
+/// over by a retry meanwhile. Failures are logged only.
+async fn release(
+    principal: String,
+    key: String,
+    lease: chrono::NaiveDateTime,
+    db: web::Data<DbPool>,
+) {
+    let log_key = key.clone();
+    match request_id::block(move || IdempotencyKey::release(&principal, &key, lease, db)).await {
+        Ok(Ok(true)) => {}
+        Ok(Ok(false)) => log_lease_lost(&log_key),
+        Ok(Err(e)) => log::error!("Failed to release the idempotency key: {}", e),
+        Err(e) => log::error!("Failed to release the idempotency key: {}", e),
+    }
+}
+
+/// Logs that the request outlived the lease of its key, and a retry took the
+/// key over: the request was handled more than once.
+fn log_lease_lost(key: &str) {
+    log::warn!(
+        "Lost the lease of the idempotency key {:?}: the request outlived \
+         idempotency.lock_timeout_secs and was retried meanwhile",
+        key
+    );
+}
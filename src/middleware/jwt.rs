@@ -2,27 +2,60 @@
 //! JWT middleware
 //!
 //! Checks is the authorization token is valid.
+//!
+//! The token subject must be an existing (non-deleted) user.
+//!
+//! On success, decoded [Claims] and the subject [Identity] are put into the
+//! request extensions, so handlers may extract them with `web::ReqData<_>`.
+//!
+//! The authorization may be optional (see [JwtMiddleware::optional]): then
+//! anonymous requests are let through, with no [Identity] in the extensions.
 
-use crate::config::JwtConfig;
+use crate::errors::{ApiError, ErrorCode, ProblemDetails};
+use crate::middleware::request_id;
+use crate::{config::JwtConfig, models::User, models::ROLE_ADMIN, DbPool};
 use actix_web::{
+    body::EitherBody,
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    http, Error,
+    http, web, Error, HttpMessage,
 };
 use futures_util::future::LocalBoxFuture;
 use jsonwebtoken::{decode, DecodingKey, Validation};
 use std::future::{ready, Ready};
+use std::rc::Rc;
 
 ///
 /// Represents JWT claims that are used when creating/validating the JWT tokens.
 ///
 /// See RFC 7519 4. JWT Claims
 /// https://datatracker.ietf.org/doc/html/rfc7519#section-4
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
-pub(crate) struct Claims {
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Claims {
+    /// Subject: the email of the user the token was issued to.
     pub sub: String,
+    /// Expiration time (UNIX timestamp).
     pub exp: usize,
 }
 
+///
+/// Represents the authorized user (the subject of the JWT token).
+#[derive(Clone, Debug)]
+pub struct Identity {
+    /// User id, corresponds to the field in [User].
+    pub user_id: i32,
+    /// User email, corresponds to the field in [User].
+    pub email: String,
+    /// Roles granted to the user.
+    pub roles: Vec<String>,
+}
+
+impl Identity {
+    /// Returns `true` if the user is granted the admin role.
+    pub fn is_admin(&self) -> bool {
+        self.roles.iter().any(|role| role == ROLE_ADMIN)
+    }
+}
+
 ///
 /// JWT middleware factory.
 /// Contains JWT configuration part of server configuration.
@@ -30,15 +63,19 @@ pub(crate) struct Claims {
 pub struct JwtMiddleware {
     /// JWT part of configuration.
     pub jwt_config: &'static JwtConfig,
+    /// Whether the requests without the `Authorization` header are let
+    /// through, for the resources serving anonymous clients as well. Invalid
+    /// tokens are rejected anyway.
+    pub optional: bool,
 }
 
 impl<S, B> Transform<S, ServiceRequest> for JwtMiddleware
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
     type InitError = ();
     type Transform = JwtMiddlewareService<S>;
@@ -47,7 +84,8 @@ where
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(JwtMiddlewareService {
             jwt_cfg: self.jwt_config,
-            service,
+            optional: self.optional,
+            service: Rc::new(service),
         }))
     }
 }
@@ -55,46 +93,132 @@ where
 /// JWT middleware service, responsible for authorization token validation.
 #[derive(Debug)]
 pub struct JwtMiddlewareService<S> {
-    service: S,
+    service: Rc<S>,
     jwt_cfg: &'static JwtConfig,
+    optional: bool,
 }
 
 impl<S, B> Service<ServiceRequest> for JwtMiddlewareService<S>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
     type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
 
     forward_ready!(service);
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
-        let secret = self.jwt_cfg.secret.as_bytes();
-        let fut = self.service.call(req);
+        let service = Rc::clone(&self.service);
+        let anonymous = !req.headers().contains_key(http::header::AUTHORIZATION);
+        if self.optional && anonymous {
+            return Box::pin(
+                async move { service.call(req).await.map(|res| res.map_into_left_body()) },
+            );
+        }
+        let token = bearer_token(&req).map(str::to_string);
+        let jwt_cfg = self.jwt_cfg;
 
         Box::pin(async move {
-            let req = fut.await?;
-            let headers = req.request().headers();
-
-            if let Some(auth_header) = headers.get(http::header::AUTHORIZATION) {
-                if let Ok(auth_str) = auth_header.to_str() {
-                    if auth_str.starts_with("Bearer ") {
-                        let token = &auth_str[7..];
-                        if let Ok(_token_data) = decode::<Claims>(
-                            token,
-                            &DecodingKey::from_secret(secret),
-                            &Validation::default(),
-                        ) {
-                            return Ok(req);
-                        }
-                    }
+            let Some(token) = token else {
+                return Ok(reject(req, ErrorCode::Unauthorized));
+            };
+            let Some(db) = req.app_data::<web::Data<DbPool>>().cloned() else {
+                return Ok(reject(req, ErrorCode::InternalError));
+            };
+
+            let (claims, identity) = match verify_token(&token, jwt_cfg, db).await {
+                Ok(Some(verified)) => verified,
+                Ok(None) => return Ok(reject(req, ErrorCode::Unauthorized)),
+                Err(e) => {
+                    log::error!("Failed to load the token subject identity: {}", e);
+                    return Ok(reject(req, e.code()));
                 }
-            }
+            };
 
-            Err(actix_web::error::ErrorUnauthorized("Unauthorized"))
+            let _ = req.extensions_mut().insert(claims);
+            let _ = req.extensions_mut().insert(identity);
+            service.call(req).await.map(|res| res.map_into_left_body())
         })
     }
 }
+
+/// Builds the response rejecting the request, rendered as [ProblemDetails].
+///
+/// Unauthorized responses carry the `WWW-Authenticate` challenge (see
+/// RFC 6750 3. The WWW-Authenticate Response Header Field).
+fn reject<B>(req: ServiceRequest, code: ErrorCode) -> ServiceResponse<EitherBody<B>> {
+    let mut resp = ProblemDetails::new(code)
+        .with_request(req.request())
+        .into_response();
+    if code == ErrorCode::Unauthorized {
+        let _ = resp.headers_mut().insert(
+            http::header::WWW_AUTHENTICATE,
+            http::header::HeaderValue::from_static("Bearer"),
+        );
+    }
+    req.into_response(resp).map_into_right_body()
+}
+
+/// Extracts the bearer token from the `Authorization` header.
+fn bearer_token(req: &ServiceRequest) -> Option<&str> {
+    req.headers()
+        .get(http::header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+///
+/// Verifies the token, returning its claims along with the subject identity,
+/// or `None` if the token is not active: malformed, forged or expired, or
+/// issued to a user who does not exist anymore (or was deleted).
+///
+/// The single source of truth on the tokens validity, shared by the
+/// middleware, the token introspection (see [crate::handlers::oauth]) and the
+/// gRPC API. Fails on the database errors only.
+pub async fn verify_token(
+    token: &str,
+    jwt_cfg: &JwtConfig,
+    db: web::Data<DbPool>,
+) -> Result<Option<(Claims, Identity)>, ApiError> {
+    let Some(claims) = decode_claims(token, jwt_cfg) else {
+        return Ok(None);
+    };
+
+    match load_identity(db, claims.sub.clone()).await {
+        Ok(identity) => Ok(Some((claims, identity))),
+        Err(ApiError::NotFound {}) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Decodes the token, verifying its signature and expiration.
+fn decode_claims(token: &str, jwt_cfg: &JwtConfig) -> Option<Claims> {
+    decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(jwt_cfg.secret.as_bytes()),
+        &Validation::default(),
+    )
+    .ok()
+    .map(|token_data| token_data.claims)
+}
+
+/// Loads the identity of the token subject.
+///
+/// Fails with [ApiError::NotFound] if the user does not exist or was deleted,
+/// so tokens issued to deleted users stop working immediately.
+async fn load_identity(db: web::Data<DbPool>, user_email: String) -> Result<Identity, ApiError> {
+    request_id::block(move || -> Result<Identity, ApiError> {
+        let user = User::find_by_email(user_email, db.clone())?;
+        let roles = User::roles(user.id, db)?;
+        Ok(Identity {
+            user_id: user.id,
+            email: user.email,
+            roles,
+        })
+    })
+    .await?
+}
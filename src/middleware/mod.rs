@@ -1,4 +1,7 @@
 //!
 //! Module contains middlewares used by the server.
 
+pub mod api_version;
+pub mod idempotency;
 pub mod jwt;
+pub mod request_id;
@@ -0,0 +1,199 @@
+//!
+//! Request ID middleware
+//!
+//! Identifies every request, so its log lines and error responses can be
+//! correlated.
+//!
+//! The id is taken from the `X-Request-Id` request header, or from the
+//! trace id of the W3C `traceparent` header (see
+//! https://www.w3.org/TR/trace-context/#traceparent-header). Otherwise, a new
+//! id is generated in the trace id format (32 lowercase hex digits), so it may
+//! be used to start a trace as well.
+//!
+//! The id is put into the request extensions (so handlers may extract it with
+//! `web::ReqData<RequestId>`), echoed in the `X-Request-Id` response header
+//! and included in the error responses (see
+//! crate::errors::ProblemDetails::request_id). While the request is handled,
+//! it is also available via [RequestId::current], so it can be attached to
+//! log lines (including the ones of the blocking work, see [block]).
+
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    error::BlockingError,
+    http::header::{HeaderName, HeaderValue},
+    web, Error, HttpMessage,
+};
+use futures_util::future::LocalBoxFuture;
+use rand::Rng;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+/// Request (and response) header carrying the request id.
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// W3C Trace Context header the request id may be taken from.
+pub const TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
+
+/// Maximum length of the request id accepted from clients.
+const REQUEST_ID_MAX_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static CURRENT: RequestId;
+}
+
+///
+/// Represents the id of the request being handled.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// Returns the id of the request handled by the current task, if any.
+    ///
+    /// In the blocking threads, it is available to the work run with [block]
+    /// (or wrapped with [RequestId::scope_blocking]) only.
+    pub fn current() -> Option<RequestId> {
+        CURRENT.try_with(RequestId::clone).ok()
+    }
+
+    /// Wraps the closure to be run in the blocking threads, so the id of the
+    /// request handled by the current task is available there as well (see
+    /// [block]).
+    pub fn scope_blocking<F, R>(f: F) -> impl FnOnce() -> R + Send + 'static
+    where
+        F: FnOnce() -> R + Send + 'static,
+    {
+        let request_id = Self::current();
+        move || match request_id {
+            Some(request_id) => CURRENT.sync_scope(request_id, f),
+            None => f(),
+        }
+    }
+
+    /// Generates a new id, formatted as a W3C trace id.
+    fn generate() -> Self {
+        let trace_id: u128 = rand::thread_rng().gen_range(1..=u128::MAX);
+        Self(format!("{trace_id:032x}"))
+    }
+
+    /// Takes the id from the request headers, if it is present and valid.
+    fn from_headers(req: &ServiceRequest) -> Option<Self> {
+        let headers = req.headers();
+        if let Some(value) = headers.get(X_REQUEST_ID) {
+            if let Some(request_id) = value.to_str().ok().filter(|v| is_valid_request_id(v)) {
+                return Some(Self(request_id.to_string()));
+            }
+        }
+        let traceparent = headers.get(TRACEPARENT)?.to_str().ok()?;
+        parse_trace_id(traceparent).map(|trace_id| Self(trace_id.to_string()))
+    }
+}
+
+///
+/// Runs the blocking work (database queries, password hashing) on the
+/// blocking threads pool, as actix' `web::block` does, keeping the id of the
+/// request handled by the current task available to its log lines.
+pub async fn block<F, R>(f: F) -> Result<R, BlockingError>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    web::block(RequestId::scope_blocking(f)).await
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Returns `true` if the client supplied request id is safe to be logged and
+/// echoed back: not too long, and consisting of visible ASCII characters.
+fn is_valid_request_id(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= REQUEST_ID_MAX_LENGTH
+        && value.chars().all(|c| {
+            c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':' | '/' | '+' | '=')
+        })
+}
+
+/// Extracts the trace id from the `traceparent` header value
+/// (`{version}-{trace-id}-{parent-id}-{trace-flags}`).
+///
+/// Future versions may append fields, so only the known prefix is checked.
+fn parse_trace_id(traceparent: &str) -> Option<&str> {
+    let mut parts = traceparent.split('-');
+    let version = parts.next()?;
+    let trace_id = parts.next()?;
+    let parent_id = parts.next()?;
+    let flags = parts.next()?;
+    let is_hex = |s: &str, len: usize| {
+        s.len() == len && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    };
+    let is_zero = |s: &str| s.bytes().all(|b| b == b'0');
+    let valid = is_hex(version, 2)
+        && version != "ff"
+        && (version != "00" || parts.next().is_none())
+        && is_hex(trace_id, 32)
+        && !is_zero(trace_id)
+        && is_hex(parent_id, 16)
+        && !is_zero(parent_id)
+        && is_hex(flags, 2);
+    valid.then_some(trace_id)
+}
+
+///
+/// Request ID middleware factory.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RequestIdMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestIdMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestIdMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestIdMiddlewareService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+/// Request ID middleware service, responsible for identifying the requests.
+#[derive(Debug)]
+pub struct RequestIdMiddlewareService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestIdMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let request_id = RequestId::from_headers(&req).unwrap_or_else(RequestId::generate);
+        let _ = req.extensions_mut().insert(request_id.clone());
+
+        Box::pin(CURRENT.scope(request_id.clone(), async move {
+            let mut res = service.call(req).await?;
+            if let Ok(value) = HeaderValue::from_str(&request_id.0) {
+                let _ = res.headers_mut().insert(X_REQUEST_ID, value);
+            }
+            Ok(res)
+        }))
+    }
+}
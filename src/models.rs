@@ -2,12 +2,18 @@
 //! Module contains database schemas representations.
 
 use crate::schema::users::dsl::users as users_dsl;
-use crate::{errors::ApiError, schema::*, DbPool};
+use crate::{db::with_retries, errors::ApiError, schema::*, DbPool};
 use actix_web::web;
+use chrono::SubsecRound;
 use diesel::prelude::*;
-use diesel::{Insertable, Queryable};
+use diesel::{AsChangeset, Insertable, Queryable};
 use serde::Deserialize;
 
+diesel::define_sql_function! {
+    /// Represents the SQL `lower` function.
+    fn lower(x: diesel::sql_types::Text) -> diesel::sql_types::Text;
+}
+
 ///
 /// Data structure representing the registered user.
 ///
@@ -15,7 +21,8 @@ use serde::Deserialize;
 /// and we don't want this data to pass outside of the system.
 /// If you want to serialize the data to pass it somewhere, use a separate
 /// data structure (see crate::handlers::OutputUser for example).
-#[derive(Debug, Queryable)]
+#[derive(Debug, Queryable, QueryableByName)]
+#[diesel(table_name = users)]
 pub struct User {
     /// User id, generated automatically.
     pub id: i32,
@@ -29,6 +36,226 @@ pub struct User {
     pub created_at: chrono::NaiveDateTime,
     /// User last update datetime, generated automatically.
     pub updated_at: chrono::NaiveDateTime,
+    /// User deletion datetime, set when the user is (soft-)deleted.
+    ///
+    /// Deleted users cannot authenticate and are not visible via API. They may
+    /// be restored until purged (see crate::jobs::purge).
+    pub deleted_at: Option<chrono::NaiveDateTime>,
+    /// Whether the user is shown to other users in lists and search results.
+    ///
+    /// Unlisted users are still visible to admins (see crate::handlers::OutputUser).
+    pub listed: bool,
+}
+
+impl User {
+    /// Load a (non-deleted) user by id.
+    /// Executes a database query, so it must be wrapped with actix' `web::block`.
+    pub fn find_by_id(user_id: i32, db: web::Data<DbPool>) -> Result<User, ApiError> {
+        let mut conn = db.get()?;
+        users_dsl
+            .find(user_id)
+            .filter(users::deleted_at.is_null())
+            .first::<User>(&mut conn)
+            .optional()?
+            .ok_or(ApiError::NotFound {})
+    }
+
+    /// Load a (non-deleted) user by email.
+    /// Executes a database query, so it must be wrapped with actix' `web::block`.
+    pub fn find_by_email(user_email: String, db: web::Data<DbPool>) -> Result<User, ApiError> {
+        let mut conn = db.get()?;
+        users_dsl
+            .filter(users::email.eq(user_email))
+            .filter(users::deleted_at.is_null())
+            .first::<User>(&mut conn)
+            .optional()?
+            .ok_or(ApiError::NotFound {})
+    }
+
+    /// Apply the changeset to the user, given the user was not modified since `last_updated_at`.
+    /// Returns `None` if the user was modified concurrently (or does not exist anymore).
+    /// Executes a database query, so it must be wrapped with actix' `web::block`.
+    pub fn update_if_unmodified(
+        user_id: i32,
+        last_updated_at: chrono::NaiveDateTime,
+        changeset: &UserChangeset,
+        db: web::Data<DbPool>,
+    ) -> Result<Option<User>, ApiError> {
+        with_retries(|| {
+            let mut conn = db.get()?;
+            let updated_user = diesel::update(
+                users_dsl
+                    .filter(users::id.eq(user_id))
+                    .filter(users::updated_at.eq(last_updated_at))
+                    .filter(users::deleted_at.is_null()),
+            )
+            .set(changeset)
+            .get_result::<User>(&mut conn)
+            .optional()?;
+
+            Ok(updated_user)
+        })
+    }
+
+    /// Replace the password hash of the user, given it is still `old_hash`.
+    /// Returns `false` if the hash was changed concurrently (or the user does not exist anymore).
+    /// Executes a database query, so it must be wrapped with actix' `web::block`.
+    pub fn replace_password_hash(
+        user_id: i32,
+        old_hash: &str,
+        new_hash: &str,
+        db: web::Data<DbPool>,
+    ) -> Result<bool, ApiError> {
+        with_retries(|| {
+            let mut conn = db.get()?;
+            let replaced = diesel::update(
+                users_dsl
+                    .filter(users::id.eq(user_id))
+                    .filter(users::hashed_password.eq(old_hash)),
+            )
+            .set(users::hashed_password.eq(new_hash))
+            .execute(&mut conn)?;
+
+            Ok(replaced > 0)
+        })
+    }
+
+    /// Mark the user as deleted.
+    /// Executes a database query, so it must be wrapped with actix' `web::block`.
+    pub fn soft_delete(user_id: i32, db: web::Data<DbPool>) -> Result<(), ApiError> {
+        with_retries(|| {
+            let mut conn = db.get()?;
+            let deleted = diesel::update(
+                users_dsl
+                    .filter(users::id.eq(user_id))
+                    .filter(users::deleted_at.is_null()),
+            )
+            .set(users::deleted_at.eq(diesel::dsl::now))
+            .execute(&mut conn)?;
+
+            match deleted {
+                0 => Err(ApiError::NotFound {}),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    /// Restore the user, given it was deleted after `deleted_after`.
+    /// Executes a database query, so it must be wrapped with actix' `web::block`.
+    pub fn restore(
+        user_id: i32,
+        deleted_after: chrono::NaiveDateTime,
+        db: web::Data<DbPool>,
+    ) -> Result<User, ApiError> {
+        with_retries(|| {
+            let mut conn = db.get()?;
+            diesel::update(
+                users_dsl
+                    .filter(users::id.eq(user_id))
+                    .filter(users::deleted_at.gt(deleted_after)),
+            )
+            .set(users::deleted_at.eq(None::<chrono::NaiveDateTime>))
+            .get_result::<User>(&mut conn)
+            .optional()?
+            .ok_or(ApiError::NotFound {})
+        })
+    }
+
+    /// Permanently remove users deleted before `deleted_before`, along with
+    /// their idempotency keys (which hold the stored responses).
+    /// Returns the number of removed users.
+    /// Executes a database query, so it must be wrapped with actix' `web::block`.
+    pub fn purge_deleted(
+        deleted_before: chrono::NaiveDateTime,
+        db: web::Data<DbPool>,
+    ) -> Result<usize, ApiError> {
+        with_retries(|| {
+            let mut conn = db.get()?;
+            conn.transaction(|conn| {
+                let user_ids = users_dsl
+                    .filter(users::deleted_at.lt(deleted_before))
+                    .select(users::id)
+                    .for_update()
+                    .load::<i32>(conn)?;
+                let principals: Vec<String> = user_ids
+                    .iter()
+                    .map(|&user_id| IdempotencyKey::user_principal(user_id))
+                    .collect();
+                let _ = diesel::delete(
+                    idempotency_keys::table.filter(idempotency_keys::principal.eq_any(principals)),
+                )
+                .execute(conn)?;
+                let purged =
+                    diesel::delete(users_dsl.filter(users::id.eq_any(user_ids))).execute(conn)?;
+
+                Ok(purged)
+            })
+        })
+    }
+
+    /// Load the roles granted to the user.
+    /// Executes a database query, so it must be wrapped with actix' `web::block`.
+    pub fn roles(user_id: i32, db: web::Data<DbPool>) -> Result<Vec<String>, ApiError> {
+        let mut conn = db.get()?;
+        let roles = user_roles::table
+            .filter(user_roles::user_id.eq(user_id))
+            .select(user_roles::role)
+            .order_by(user_roles::role)
+            .load::<String>(&mut conn)?;
+
+        Ok(roles)
+    }
+
+    /// Grant the role to the user. Granting an already granted role is a no-op.
+    /// Executes a database query, so it must be wrapped with actix' `web::block`.
+    pub fn grant_role(user_id: i32, role: &str, db: web::Data<DbPool>) -> Result<(), ApiError> {
+        with_retries(|| {
+            let mut conn = db.get()?;
+            let _ = diesel::insert_into(user_roles::table)
+                .values((user_roles::user_id.eq(user_id), user_roles::role.eq(role)))
+                .on_conflict_do_nothing()
+                .execute(&mut conn)?;
+
+            Ok(())
+        })
+    }
+}
+
+/// Role granting access to administrative endpoints.
+pub const ROLE_ADMIN: &str = "admin";
+
+///
+/// Data structure representing a role granted to the user.
+///
+/// Roles are granted out of band (there is no API to manage them).
+#[derive(Debug, Queryable)]
+pub struct UserRole {
+    /// Corresponds to the [User] id.
+    pub user_id: i32,
+    /// Role name (see [ROLE_ADMIN] for example).
+    pub role: String,
+    /// Role grant datetime, generated automatically.
+    pub created_at: chrono::NaiveDateTime,
+}
+
+///
+/// Data structure representing the mutable part of the registered user.
+///
+/// Fields set to `None` are left untouched.
+#[derive(Debug, Default, AsChangeset)]
+#[diesel(table_name = users)]
+pub struct UserChangeset {
+    /// Corresponds to the same field in [User].
+    pub name: Option<String>,
+    /// Corresponds to the same field in [User].
+    pub listed: Option<bool>,
+}
+
+impl UserChangeset {
+    /// Returns `true` if the changeset contains no changes.
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.listed.is_none()
+    }
 }
 
 ///
@@ -53,11 +280,219 @@ impl NewUser {
     /// Write a new user to the database.
     /// Executes a database query, so it must be wrapped with actix' `web::block`.
     pub fn write(&self, db: web::Data<DbPool>) -> Result<User, ApiError> {
-        let mut conn = db.get()?;
-        let inserted_user = diesel::insert_into(users_dsl)
-            .values(self)
-            .get_result(&mut conn)?;
+        with_retries(|| {
+            let mut conn = db.get()?;
+            let inserted_user = diesel::insert_into(users_dsl)
+                .values(self)
+                .get_result(&mut conn)?;
+
+            Ok(inserted_user)
+        })
+    }
+}
+
+///
+/// Data structure representing the idempotency key of a request, along with
+/// the response to replay (see crate::middleware::idempotency).
+#[derive(Debug, Queryable)]
+#[diesel(table_name = idempotency_keys)]
+pub struct IdempotencyKey {
+    /// Caller the key belongs to (`user:<id>`, or `anonymous`).
+    pub principal: String,
+    /// Key, as sent by the client.
+    pub idempotency_key: String,
+    /// Request fingerprint: the HMAC of the method, the path and the body.
+    pub fingerprint: String,
+    /// Response status, `None` while the request is in progress.
+    pub status: Option<i16>,
+    /// Response headers, as a JSON array of `[name, value]` pairs.
+    pub headers: String,
+    /// Response body.
+    pub body: Vec<u8>,
+    /// Key creation datetime, generated automatically.
+    pub created_at: chrono::NaiveDateTime,
+    /// Key expiration datetime, after which the key may be reused.
+    pub expires_at: chrono::NaiveDateTime,
+    /// End of the lease of the request in progress, after which a retry may
+    /// take the key over. `None` once the response is stored.
+    pub locked_until: Option<chrono::NaiveDateTime>,
+}
+
+/// Query selecting the idempotency key in progress by its lease (see
+/// [IdempotencyKey::complete]).
+type LeasedKey<'a> = diesel::dsl::Filter<
+    diesel::dsl::Find<idempotency_keys::table, (&'a str, &'a str)>,
+    diesel::dsl::And<
+        diesel::dsl::IsNull<idempotency_keys::status>,
+        diesel::dsl::Eq<idempotency_keys::locked_until, chrono::NaiveDateTime>,
+    >,
+>;
+
+/// Outcome of claiming the idempotency key (see [IdempotencyKey::claim]).
+#[derive(Debug)]
+pub enum IdempotencyClaim {
+    /// The key was not used yet (or its lease expired): the request is to be
+    /// handled, holding the lease until the given datetime. The lease fences
+    /// off the completion of the request the key was taken over from (see
+    /// [IdempotencyKey::complete]).
+    Claimed(chrono::NaiveDateTime),
+    /// The key is used by another request, which is still in progress (and
+    /// its lease did not expire).
+    InProgress,
+    /// The key was used with a different request.
+    Mismatch,
+    /// The key was used with the same request: its response is to be replayed.
+    Completed(IdempotencyKey),
+}
+
+impl IdempotencyKey {
+    /// Returns the principal of the keys used by the authorized user.
+    pub fn user_principal(user_id: i32) -> String {
+        format!("user:{user_id}")
+    }
+
+    /// Claim the key for the request with the given fingerprint, unless it is
+    /// used already (and not expired).
+    ///
+    /// The claim is leased for `lock_timeout`: if the request is still in
+    /// progress after that, it is assumed to be abandoned, and the key is
+    /// claimed again by the retry.
+    /// Executes a database query, so it must be wrapped with actix' `web::block`.
+    pub fn claim(
+        principal: &str,
+        key: &str,
+        fingerprint: &str,
+        ttl: chrono::Duration,
+        lock_timeout: chrono::Duration,
+        db: web::Data<DbPool>,
+    ) -> Result<IdempotencyClaim, ApiError> {
+        with_retries(|| {
+            let mut conn = db.get()?;
+            conn.transaction(|conn| {
+                let now = chrono::Utc::now().naive_utc();
+                // Stored with the microsecond precision, so it is compared
+                // exactly afterwards
+                let lease = (now + lock_timeout).trunc_subsecs(6);
+                let target = idempotency_keys::table
+                    .filter(idempotency_keys::principal.eq(principal))
+                    .filter(idempotency_keys::idempotency_key.eq(key));
+                let _ = diesel::delete(target.filter(idempotency_keys::expires_at.le(now)))
+                    .execute(conn)?;
+
+                let claimed = diesel::insert_into(idempotency_keys::table)
+                    .values((
+                        idempotency_keys::principal.eq(principal),
+                        idempotency_keys::idempotency_key.eq(key),
+                        idempotency_keys::fingerprint.eq(fingerprint),
+                        idempotency_keys::expires_at.eq(now + ttl),
+                        idempotency_keys::locked_until.eq(lease),
+                    ))
+                    .on_conflict_do_nothing()
+                    .execute(conn)?;
+                if claimed > 0 {
+                    return Ok(IdempotencyClaim::Claimed(lease));
+                }
+
+                let existing = target.first::<IdempotencyKey>(conn)?;
+                if existing.fingerprint != fingerprint {
+                    return Ok(IdempotencyClaim::Mismatch);
+                }
+                if existing.status.is_some() {
+                    return Ok(IdempotencyClaim::Completed(existing));
+                }
+
+                // Concurrent retries are serialized by the row lock, so only
+                // one of them takes the abandoned key over
+                let taken_over = diesel::update(
+                    target
+                        .filter(idempotency_keys::status.is_null())
+                        .filter(idempotency_keys::locked_until.le(now)),
+                )
+                .set((
+                    idempotency_keys::expires_at.eq(now + ttl),
+                    idempotency_keys::locked_until.eq(lease),
+                ))
+                .execute(conn)?;
+                Ok(match taken_over {
+                    0 => IdempotencyClaim::InProgress,
+                    _ => IdempotencyClaim::Claimed(lease),
+                })
+            })
+        })
+    }
+
+    /// Store the response of the request the key was claimed for, unless
+    /// the key was taken over meanwhile (its `lease` is not held anymore).
+    /// Returns `false` if the lease was lost.
+    /// Executes a database query, so it must be wrapped with actix' `web::block`.
+    pub fn complete(
+        principal: &str,
+        key: &str,
+        lease: chrono::NaiveDateTime,
+        status: i16,
+        headers: &str,
+        body: &[u8],
+        db: web::Data<DbPool>,
+    ) -> Result<bool, ApiError> {
+        with_retries(|| {
+            let mut conn = db.get()?;
+            let completed = diesel::update(Self::leased(principal, key, lease))
+                .set((
+                    idempotency_keys::status.eq(status),
+                    idempotency_keys::headers.eq(headers),
+                    idempotency_keys::body.eq(body),
+                    idempotency_keys::locked_until.eq(None::<chrono::NaiveDateTime>),
+                ))
+                .execute(&mut conn)?;
+
+            Ok(completed > 0)
+        })
+    }
+
+    /// Release the key, so the request may be retried (for example, after a
+    /// server-side failure), unless the key was taken over meanwhile (its
+    /// `lease` is not held anymore). Returns `false` if the lease was lost.
+    /// Executes a database query, so it must be wrapped with actix' `web::block`.
+    pub fn release(
+        principal: &str,
+        key: &str,
+        lease: chrono::NaiveDateTime,
+        db: web::Data<DbPool>,
+    ) -> Result<bool, ApiError> {
+        with_retries(|| {
+            let mut conn = db.get()?;
+            let released =
+                diesel::delete(Self::leased(principal, key, lease)).execute(&mut conn)?;
+
+            Ok(released > 0)
+        })
+    }
+
+    /// Returns the query selecting the key in progress, if it is still held
+    /// with the given lease.
+    fn leased<'a>(principal: &'a str, key: &'a str, lease: chrono::NaiveDateTime) -> LeasedKey<'a> {
+        idempotency_keys::table.find((principal, key)).filter(
+            idempotency_keys::status
+                .is_null()
+                .and(idempotency_keys::locked_until.eq(lease)),
+        )
+    }
+
+    /// Permanently remove the keys expired before `expired_before`.
+    /// Returns the number of removed keys.
+    /// Executes a database query, so it must be wrapped with actix' `web::block`.
+    pub fn purge_expired(
+        expired_before: chrono::NaiveDateTime,
+        db: web::Data<DbPool>,
+    ) -> Result<usize, ApiError> {
+        with_retries(|| {
+            let mut conn = db.get()?;
+            let purged = diesel::delete(
+                idempotency_keys::table.filter(idempotency_keys::expires_at.le(expired_before)),
+            )
+            .execute(&mut conn)?;
 
-        Ok(inserted_user)
+            Ok(purged)
+        })
     }
 }
@@ -0,0 +1,112 @@
+//!
+//! Module contains the opaque list cursors encoding.
+//!
+//! A cursor is a JSON payload, signed with HMAC-SHA256, so clients cannot
+//! forge or alter it. Both parts are base64url-encoded and joined with a dot:
+//! `<payload>.<signature>`.
+
+use actix_web::http::header::HeaderValue;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::Sha256;
+use validator::{ValidationError, ValidationErrors};
+
+use crate::config::PaginationConfig;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Paging direction, relative to the cursor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// Records after the cursor (`after` parameter).
+    After,
+    /// Records before the cursor (`before` parameter).
+    Before,
+}
+
+///
+/// Request types paginated with `after`/`before` cursors.
+pub trait CursorPaginated: Serialize {
+    /// Returns a copy of the request pointing to the page adjacent to the cursor.
+    fn with_cursor(&self, direction: Direction, cursor: &str) -> Self;
+}
+
+///
+/// Builds the RFC 8288 `Link` header value with `next` and `prev` pages links.
+///
+/// Returns `None` if there are no adjacent pages.
+pub fn link_header<Q: CursorPaginated>(
+    path: &str,
+    query: &Q,
+    next_cursor: Option<&str>,
+    prev_cursor: Option<&str>,
+) -> Option<HeaderValue> {
+    let links: Vec<String> = [
+        (Direction::After, next_cursor, "next"),
+        (Direction::Before, prev_cursor, "prev"),
+    ]
+    .into_iter()
+    .filter_map(|(direction, cursor, rel)| {
+        let query = query.with_cursor(direction, cursor?);
+        let query_string = serde_urlencoded::to_string(&query).ok()?;
+        Some(format!("<{path}?{query_string}>; rel=\"{rel}\""))
+    })
+    .collect();
+
+    match links.is_empty() {
+        true => None,
+        false => HeaderValue::from_str(&links.join(", ")).ok(),
+    }
+}
+
+///
+/// Checks the requested page size against the configured maximum.
+///
+/// The lower bound is validated declaratively by the request types.
+pub fn validate_limit(limit: Option<i32>, cfg: &PaginationConfig) -> Result<(), ValidationErrors> {
+    match limit {
+        Some(limit) if limit > cfg.max_page_size => {
+            let mut error = ValidationError::new("range");
+            error.add_param("min".into(), &1);
+            error.add_param("max".into(), &cfg.max_page_size);
+            let mut errors = ValidationErrors::new();
+            errors.add("limit", error);
+            Err(errors)
+        }
+        _ => Ok(()),
+    }
+}
+
+///
+/// Encodes and signs the cursor payload.
+pub fn encode_cursor<T: Serialize>(payload: &T, secret: &[u8]) -> String {
+    let payload = serde_json::to_vec(payload).expect("Cursor payload is serializable");
+    let signature = sign(&payload, secret).finalize().into_bytes();
+
+    format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(payload),
+        URL_SAFE_NO_PAD.encode(signature)
+    )
+}
+
+///
+/// Verifies the cursor signature and decodes its payload.
+///
+/// Returns `None` if the cursor is malformed or was tampered with.
+pub fn decode_cursor<T: DeserializeOwned>(cursor: &str, secret: &[u8]) -> Option<T> {
+    let (payload, signature) = cursor.split_once('.')?;
+    let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+    let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+
+    sign(&payload, secret).verify_slice(&signature).ok()?;
+    serde_json::from_slice(&payload).ok()
+}
+
+fn sign(payload: &[u8], secret: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(payload);
+    mac
+}
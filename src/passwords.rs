@@ -0,0 +1,220 @@
+//!
+//! Module containing password hashing and verification functions.
+//!
+//! New passwords are hashed with Argon2id, using the configured parameters
+//! (see [PasswordsConfig]). Users imported from other systems may come with
+//! legacy hashes (bcrypt, scrypt or PBKDF2): those are verified with the
+//! matching algorithm, and reported as outdated, so they are re-hashed with
+//! Argon2id on the next successful login.
+//!
+//! Hashes are expected in PHC string format, except for bcrypt, which uses
+//! its own modular crypt format (`$2b$<cost>$<salt><hash>`).
+
+use std::str::FromStr;
+
+use argon2::password_hash::{rand_core::OsRng, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Argon2, PasswordHash};
+
+use crate::config::PasswordsConfig;
+use crate::errors::ApiError;
+
+/// Password hashing scheme, detected by the hash prefix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashScheme {
+    /// Argon2 (`$argon2id$`, `$argon2i$` or `$argon2d$`), the current one.
+    Argon2,
+    /// bcrypt (`$2a$`, `$2b$`, `$2x$` or `$2y$`).
+    Bcrypt,
+    /// scrypt (`$scrypt$`).
+    Scrypt,
+    /// PBKDF2 (`$pbkdf2$`, `$pbkdf2-sha256$` or `$pbkdf2-sha512$`).
+    Pbkdf2,
+}
+
+impl HashScheme {
+    /// Legacy schemes, in the order they are reported.
+    pub const LEGACY: [Self; 3] = [Self::Bcrypt, Self::Scrypt, Self::Pbkdf2];
+
+    /// Detects the scheme of the hash, if it is supported.
+    pub fn detect(hash: &str) -> Option<Self> {
+        let ident = hash.strip_prefix('$')?.split('$').next()?;
+        Self::from_ident(ident)
+    }
+
+    /// Maps the hash identifier (the part between the first two `$`) to the
+    /// scheme.
+    pub fn from_ident(ident: &str) -> Option<Self> {
+        match ident {
+            "argon2id" | "argon2i" | "argon2d" => Some(Self::Argon2),
+            "2a" | "2b" | "2x" | "2y" => Some(Self::Bcrypt),
+            "scrypt" => Some(Self::Scrypt),
+            "pbkdf2" | "pbkdf2-sha256" | "pbkdf2-sha512" => Some(Self::Pbkdf2),
+            _ => None,
+        }
+    }
+
+    /// Scheme name, as reported in metrics.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Argon2 => "argon2",
+            Self::Bcrypt => "bcrypt",
+            Self::Scrypt => "scrypt",
+            Self::Pbkdf2 => "pbkdf2",
+        }
+    }
+}
+
+/// Password verification outcome.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verification {
+    /// The password does not match (or the hash is malformed).
+    Invalid,
+    /// The password matches, and the hash is up to date.
+    Valid,
+    /// The password matches, but the hash should be replaced with a new one
+    /// (see [hash]): it uses a legacy scheme, or outdated Argon2 parameters.
+    Outdated,
+}
+
+/// Returns the Argon2 hasher with the configured parameters.
+fn argon2(cfg: &PasswordsConfig) -> Result<Argon2<'static>, ApiError> {
+    Ok(Argon2::new(
+        argon2::Algorithm::Argon2id,
+        argon2::Version::V0x13,
+        cfg.argon2_params()
+            .map_err(argon2::password_hash::Error::from)?,
+    ))
+}
+
+///
+/// Hashes the password with Argon2id and a random salt, in PHC string format.
+///
+/// CPU-intensive, so it should be wrapped with actix' `web::block` when used
+/// for many passwords at once.
+pub fn hash(password: &str, cfg: &PasswordsConfig) -> Result<String, ApiError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(argon2(cfg)?
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+///
+/// Verifies the password against the hash of any supported scheme.
+///
+/// CPU-intensive, so it should be wrapped with actix' `web::block`.
+pub fn verify(password: &str, hash: &str, cfg: &PasswordsConfig) -> Verification {
+    let outdated = match HashScheme::detect(hash) {
+        Some(HashScheme::Argon2) => match verify_argon2(password, hash, cfg) {
+            Some(outdated) => outdated,
+            None => return Verification::Invalid,
+        },
+        Some(HashScheme::Bcrypt) => match bcrypt::verify(password, hash) {
+            Ok(true) => true,
+            _ => return Verification::Invalid,
+        },
+        Some(HashScheme::Scrypt) => match PasswordHash::new(hash) {
+            Ok(parsed)
+                if scrypt::Scrypt
+                    .verify_password(password.as_bytes(), &parsed)
+                    .is_ok() =>
+            {
+                true
+            }
+            _ => return Verification::Invalid,
+        },
+        Some(HashScheme::Pbkdf2) => match PasswordHash::new(hash) {
+            Ok(parsed)
+                if pbkdf2::Pbkdf2
+                    .verify_password(password.as_bytes(), &parsed)
+                    .is_ok() =>
+            {
+                true
+            }
+            _ => return Verification::Invalid,
+        },
+        None => return Verification::Invalid,
+    };
+
+    match outdated {
+        true => Verification::Outdated,
+        false => Verification::Valid,
+    }
+}
+
+/// Verifies the password against the Argon2 hash.
+///
+/// Returns whether the hash is outdated (that is, not made with the
+/// configured algorithm and parameters), or `None` if the password does not
+/// match.
+fn verify_argon2(password: &str, hash: &str, cfg: &PasswordsConfig) -> Option<bool> {
+    let parsed = PasswordHash::new(hash).ok()?;
+    Argon2::default()
+        .verify_password(password.as_bytes(), &parsed)
+        .ok()?;
+
+    let params = argon2::Params::try_from(&parsed).ok()?;
+    let outdated = parsed.algorithm != argon2::Algorithm::Argon2id.ident()
+        || parsed.version != Some(argon2::Version::V0x13.into())
+        || params.m_cost() != cfg.argon2_memory_kib
+        || params.t_cost() != cfg.argon2_iterations
+        || params.p_cost() != cfg.argon2_parallelism;
+    Some(outdated)
+}
+
+///
+/// Checks whether the hash is well-formed and uses a supported scheme, so
+/// that it can be verified on login.
+///
+/// Returns `None` if the scheme is not supported, and `Some(false)` if the
+/// hash is malformed.
+pub fn is_well_formed(hash: &str) -> Option<bool> {
+    let well_formed = match HashScheme::detect(hash)? {
+        HashScheme::Argon2 => PasswordHash::new(hash).is_ok_and(|parsed| {
+            argon2::Algorithm::try_from(parsed.algorithm).is_ok()
+                && argon2::Params::try_from(&parsed).is_ok()
+                && parsed.hash.is_some()
+        }),
+        HashScheme::Bcrypt => bcrypt::HashParts::from_str(hash)
+            .is_ok_and(|parts| (4..=31).contains(&parts.get_cost())),
+        HashScheme::Scrypt => PasswordHash::new(hash)
+            .is_ok_and(|parsed| scrypt::Params::try_from(&parsed).is_ok() && parsed.hash.is_some()),
+        HashScheme::Pbkdf2 => PasswordHash::new(hash).is_ok_and(|parsed| {
+            pbkdf2::Algorithm::try_from(parsed.algorithm).is_ok()
+                && pbkdf2::Params::try_from(&parsed).is_ok()
+                && parsed.hash.is_some()
+        }),
+    };
+    Some(well_formed)
+}
+
+///
+/// Checks whether the cost parameters of the well-formed hash (see
+/// [is_well_formed]) do not exceed the configured maximums, so verifying it
+/// on login does not take too much time or memory.
+pub fn is_within_limits(hash: &str, cfg: &PasswordsConfig) -> bool {
+    match HashScheme::detect(hash) {
+        Some(HashScheme::Argon2) => PasswordHash::new(hash)
+            .ok()
+            .and_then(|parsed| argon2::Params::try_from(&parsed).ok())
+            .is_some_and(|params| {
+                params.m_cost() <= cfg.max_argon2_memory_kib
+                    && params.t_cost() <= cfg.max_argon2_iterations
+                    && params.p_cost() <= cfg.max_argon2_parallelism
+            }),
+        Some(HashScheme::Bcrypt) => bcrypt::HashParts::from_str(hash)
+            .is_ok_and(|parts| parts.get_cost() <= cfg.max_bcrypt_cost),
+        Some(HashScheme::Scrypt) => PasswordHash::new(hash)
+            .ok()
+            .and_then(|parsed| scrypt::Params::try_from(&parsed).ok())
+            .is_some_and(|params| {
+                params.log_n() <= cfg.max_scrypt_log_n
+                    && params.r() <= cfg.max_scrypt_r
+                    && params.p() <= cfg.max_scrypt_p
+            }),
+        Some(HashScheme::Pbkdf2) => PasswordHash::new(hash)
+            .ok()
+            .and_then(|parsed| pbkdf2::Params::try_from(&parsed).ok())
+            .is_some_and(|params| params.rounds <= cfg.max_pbkdf2_rounds),
+        None => false,
+    }
+}
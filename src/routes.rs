@@ -0,0 +1,193 @@
+//!
+//! Module containing the REST API routing table.
+//!
+//! Shared by the binary target and by integration tests, so both serve the
+//! exact same set of endpoints.
+
+use actix_web::{middleware::ErrorHandlers, web};
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::config::ServerConfig;
+use crate::middleware::api_version::{version_prefix, ApiVersionMiddleware, API_VERSIONS};
+use crate::middleware::{
+    idempotency::IdempotencyMiddleware, jwt::JwtMiddleware, request_id::RequestIdMiddleware,
+};
+use crate::{errors, graphql, handlers};
+
+/// Maximum size of the request bodies (except for the streamed ones), in bytes.
+const BODY_LIMIT: usize = 4096;
+
+///
+/// Returns a function configuring the routes of the metrics listener (see
+/// [crate::config::MetricsConfig]), served apart from the API, as scrapes are
+/// not authorized.
+///
+/// Usage:
+/// App::new().configure(routes::configure_metrics())
+pub fn configure_metrics() -> impl Fn(&mut web::ServiceConfig) {
+    |app: &mut web::ServiceConfig| {
+        let _ = app.service(
+            web::scope("")
+                .wrap(ErrorHandlers::new().default_handler(errors::render_problem))
+                .wrap(RequestIdMiddleware)
+                .default_service(web::to(errors::not_found))
+                .service(
+                    web::resource("/metrics").route(web::get().to(handlers::metrics::metrics)),
+                ),
+        );
+    }
+}
+
+///
+/// Returns a function configuring the application routes and extractors.
+///
+/// The API routes are served under the version prefix (`/v1/...`), and under
+/// the unversioned paths, negotiating the version with the `Api-Version`
+/// header (see [crate::middleware::api_version]).
+///
+/// Usage:
+/// App::new().configure(routes::configure(cfg))
+pub fn configure(cfg: &'static ServerConfig) -> impl Fn(&mut web::ServiceConfig) {
+    move |app: &mut web::ServiceConfig| {
+        // Error responses built by actix itself are rendered as problem
+        // details as well. Every request is identified first, so the error
+        // responses carry the request id
+        let mut root = web::scope("")
+            .wrap(ErrorHandlers::new().default_handler(errors::render_problem))
+            .wrap(RequestIdMiddleware)
+            .default_service(web::to(errors::not_found))
+            .service(
+                web::resource("/oauth/introspect")
+                    .route(web::post().to(handlers::oauth::introspect)),
+            )
+            .service(web::resource("/openapi.json").route(web::get().to(handlers::openapi::spec)));
+        if cfg.http.docs_ui {
+            root = root.service(
+                SwaggerUi::new("/docs/{_:.*}")
+                    .config(utoipa_swagger_ui::Config::from("/openapi.json")),
+            );
+        }
+        // Every version is served under its own prefix, so clients may
+        // migrate at their own pace
+        root = root.service(
+            web::scope(&version_prefix(1))
+                .wrap(ApiVersionMiddleware::versioned(1))
+                .default_service(web::to(errors::not_found))
+                .configure(api_v1(cfg)),
+        );
+        // The unversioned paths are served by the oldest version, until the
+        // requests not specifying the version are sunset
+        let unversioned = ApiVersionMiddleware::unversioned(API_VERSIONS[0], &cfg.versioning);
+        root = root.service(
+            web::scope("")
+                .wrap(unversioned)
+                .default_service(web::to(errors::not_found))
+                .configure(api_v1(cfg)),
+        );
+
+        let _ = app
+            .app_data(
+                web::JsonConfig::default()
+                    .limit(BODY_LIMIT)
+                    .content_type(|mime| mime.essence_str() == "application/merge-patch+json")
+                    .error_handler(errors::json_error_handler),
+            )
+            .app_data(
+                web::FormConfig::default()
+                    .limit(BODY_LIMIT)
+                    .error_handler(errors::form_error_handler),
+            )
+            .app_data(web::PayloadConfig::default().limit(BODY_LIMIT))
+            .app_data(web::QueryConfig::default().error_handler(errors::query_error_handler))
+            .app_data(web::Data::new(graphql::schema(&cfg.graphql)))
+            .app_data(web::PathConfig::default().error_handler(errors::path_error_handler))
+            .service(root);
+    }
+}
+
+/// Returns a function configuring the version 1 API routes, relative to the
+/// version prefix.
+fn api_v1(cfg: &'static ServerConfig) -> impl Fn(&mut web::ServiceConfig) {
+    move |api: &mut web::ServiceConfig| {
+        let jwt = JwtMiddleware {
+            jwt_config: &cfg.jwt,
+            optional: false,
+        };
+        // Serves the anonymous clients as well (to register)
+        let jwt_optional = JwtMiddleware {
+            optional: true,
+            ..jwt
+        };
+        // Wrapped by the JWT middleware where the authorization is required
+        // (or optional), so the idempotency keys are scoped to the authorized
+        // user. The import is not covered: it streams its response and
+        // commits in batches, so it cannot be replayed as a whole. The token
+        // creation is, but its response (a credential) is never stored: only
+        // the concurrent duplicates are rejected
+        let idempotency = IdempotencyMiddleware {
+            idempotency_config: &cfg.idempotency,
+        };
+
+        let _ = api
+            .service(
+                web::resource("/user")
+                    .wrap(idempotency)
+                    .route(web::post().to(handlers::user::register)),
+            )
+            .service(
+                web::resource("/user/me")
+                    .wrap(jwt)
+                    .route(web::get().to(handlers::user::me))
+                    .route(web::patch().to(handlers::user::update_me))
+                    .route(web::delete().to(handlers::user::delete_me)),
+            )
+            .service(
+                web::resource("/user/me/export")
+                    .wrap(jwt)
+                    .route(web::get().to(handlers::user::export_me)),
+            )
+            .service(
+                web::resource("/user/{id}")
+                    .wrap(jwt)
+                    .route(web::get().to(handlers::user::get))
+                    .route(web::delete().to(handlers::user::delete)),
+            )
+            .service(
+                web::resource("/user/{id}/restore")
+                    .wrap(idempotency)
+                    .wrap(jwt)
+                    .route(web::post().to(handlers::user::restore)),
+            )
+            .service(
+                web::resource("/auth/token")
+                    .wrap(idempotency)
+                    .route(web::post().to(handlers::auth::token)),
+            )
+            .service(
+                web::resource("/users")
+                    .wrap(jwt)
+                    .route(web::get().to(handlers::users::list)),
+            )
+            .service(
+                web::resource("/users/export")
+                    .wrap(jwt)
+                    .route(web::get().to(handlers::users_export::export)),
+            )
+            .service(
+                web::resource("/users/import")
+                    .wrap(jwt)
+                    .route(web::post().to(handlers::users_import::import)),
+            )
+            .service(
+                web::resource("/graphql")
+                    .wrap(idempotency)
+                    .wrap(jwt_optional)
+                    .route(web::post().to(handlers::graphql::graphql)),
+            )
+            .service(
+                web::resource("/users/search")
+                    .wrap(jwt)
+                    .route(web::get().to(handlers::search::search)),
+            );
+    }
+}
@@ -1,4 +1,27 @@
 // @generated automatically by Diesel CLI.
+
+diesel::table! {
+    idempotency_keys (principal, idempotency_key) {
+        principal -> Text,
+        idempotency_key -> Text,
+        fingerprint -> Text,
+        status -> Nullable<Int2>,
+        headers -> Text,
+        body -> Bytea,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        locked_until -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    user_roles (user_id, role) {
+        user_id -> Int4,
+        role -> Text,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     users (id) {
         id -> Int4,
@@ -7,5 +30,11 @@ diesel::table! {
         hashed_password -> Text,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        deleted_at -> Nullable<Timestamp>,
+        listed -> Bool,
     }
 }
+
+diesel::joinable!(user_roles -> users (user_id));
+
+diesel::allow_tables_to_appear_in_same_query!(idempotency_keys, user_roles, users,);
@@ -0,0 +1,79 @@
+//!
+//! Module contains request validation rules shared by request types.
+//!
+//! Request types declare their rules with `#[derive(validator::Validate)]`;
+//! failed validations are rendered as 422 responses listing every failing
+//! field along with a machine-readable code (see [crate::errors::ApiError]).
+
+use validator::{ValidationError, ValidationErrors};
+
+use crate::config::PasswordsConfig;
+
+/// Maximum length of the user name, in characters.
+pub const NAME_MAX_LENGTH: u64 = 128;
+/// Minimum length of the user password, in characters.
+pub const PASSWORD_MIN_LENGTH: u64 = 8;
+/// Maximum length of the user password, in characters.
+///
+/// Limits the amount of work spent on hashing.
+pub const PASSWORD_MAX_LENGTH: u64 = 1024;
+
+///
+/// Validates the user name Unicode rules.
+///
+/// The name must contain at least one non-whitespace character, and must not
+/// contain control characters or invisible formatting characters (such as
+/// zero-width spaces and bidirectional overrides), which may be used to spoof
+/// other users names.
+pub fn validate_name(name: &str) -> Result<(), ValidationError> {
+    if name.trim().is_empty() {
+        return Err(ValidationError::new("blank"));
+    }
+    if name.chars().any(|c| c.is_control() || is_format_char(c)) {
+        return Err(ValidationError::new("invalid_characters"));
+    }
+
+    Ok(())
+}
+
+///
+/// Checks the pre-hashed password.
+///
+/// The hash must use one of the schemes supported on login (see
+/// [crate::passwords]): Argon2, scrypt or PBKDF2 in PHC string format (for
+/// example, `$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>`), or bcrypt. Its
+/// cost parameters must not exceed the configured maximums (see
+/// [PasswordsConfig]).
+pub fn validate_password_hash(hash: &str, cfg: &PasswordsConfig) -> Result<(), ValidationError> {
+    match crate::passwords::is_well_formed(hash) {
+        Some(true) if crate::passwords::is_within_limits(hash, cfg) => Ok(()),
+        Some(true) => Err(ValidationError::new("hash_too_costly")),
+        Some(false) => Err(ValidationError::new("invalid_hash")),
+        None => Err(ValidationError::new("unsupported_hash")),
+    }
+}
+
+/// Checks for the invisible formatting characters (Unicode `Cf` category
+/// ranges commonly used for spoofing).
+fn is_format_char(c: char) -> bool {
+    matches!(
+        c,
+        '\u{00AD}'
+            | '\u{061C}'
+            | '\u{180E}'
+            | '\u{200B}'..='\u{200F}'
+            | '\u{202A}'..='\u{202E}'
+            | '\u{2060}'..='\u{2064}'
+            | '\u{2066}'..='\u{206F}'
+            | '\u{FEFF}'
+    )
+}
+
+///
+/// Builds validation errors for a single field failing a check that cannot
+/// be expressed declaratively (for example, one depending on configuration).
+pub fn field_error(field: &'static str, code: &'static str) -> ValidationErrors {
+    let mut errors = ValidationErrors::new();
+    errors.add(field, ValidationError::new(code));
+    errors
+}
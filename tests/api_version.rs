@@ -0,0 +1,102 @@
+mod common;
+
+use actix_web::{http, test};
+use na::errors::{ErrorCode, ProblemDetails};
+
+/// Checks if the API is served under the version prefix, without the
+/// deprecation headers.
+#[actix_web::test]
+async fn versioned_paths() {
+    let app = common::setup_server().await;
+    let email = common::random_email();
+    let password = common::random_string(16);
+    let user = common::register_user(&app, &email, &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+
+    for (header, uri) in [
+        (None, "/v1/user/me"),
+        // The path version takes precedence over the header
+        (Some("2"), "/v1/user/me"),
+        (Some("1"), "/user/me"),
+    ] {
+        let mut req = test::TestRequest::get()
+            .uri(uri)
+            .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)));
+        if let Some(header) = header {
+            req = req.append_header(("api-version", header));
+        }
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(200, resp.status().as_u16(), "{uri} {header:?}");
+        assert_eq!("1", resp.headers().get("api-version").unwrap());
+        assert!(resp.headers().get("deprecation").is_none());
+        assert!(resp.headers().get("sunset").is_none());
+        assert!(resp.headers().get(http::header::LINK).is_none());
+        let body = test::read_body(resp).await;
+        let payload: na::handlers::OutputUser = serde_json::from_slice(&body).unwrap();
+        assert_eq!(user.id, payload.id);
+    }
+
+    let req = test::TestRequest::get()
+        .uri("/v1/no/such/route")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(404, resp.status().as_u16());
+}
+
+/// Checks if the requests to the unversioned paths which do not specify the
+/// version are served with the deprecation headers.
+#[actix_web::test]
+async fn unversioned_paths_deprecated() {
+    let app = common::setup_server().await;
+    let email = common::random_email();
+    let password = common::random_string(16);
+    let _ = common::register_user(&app, &email, &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+
+    let req = test::TestRequest::get()
+        .uri("/user/me")
+        .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(200, resp.status().as_u16());
+    let headers = resp.headers();
+    assert_eq!("1", headers.get("api-version").unwrap());
+    // 2026-10-19T00:00:00Z and 2027-10-19T00:00:00Z (see config/default.toml)
+    assert_eq!("@1792368000", headers.get("deprecation").unwrap());
+    assert_eq!(
+        "Tue, 19 Oct 2027 00:00:00 GMT",
+        headers.get("sunset").unwrap()
+    );
+    assert_eq!(
+        "</v1/user/me>; rel=\"successor-version\"",
+        headers.get(http::header::LINK).unwrap()
+    );
+
+    // Service endpoints are not versioned
+    let req = test::TestRequest::get().uri("/openapi.json").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(200, resp.status().as_u16());
+    assert!(resp.headers().get("api-version").is_none());
+    assert!(resp.headers().get("deprecation").is_none());
+}
+
+/// Checks if the requests to the unversioned paths specifying an unsupported
+/// version are rejected with 406 Not Acceptable.
+#[actix_web::test]
+async fn unsupported_version() {
+    let app = common::setup_server().await;
+
+    for version in ["2", "0", "v1", ""] {
+        let req = test::TestRequest::post()
+            .uri("/auth/token")
+            .append_header(("api-version", version))
+            .set_json(serde_json::json!({"email": "nobody@example.org", "password": "secret"}))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(406, resp.status().as_u16(), "{version}");
+        let body = test::read_body(resp).await;
+        let problem: ProblemDetails = serde_json::from_slice(&body).unwrap();
+        assert_eq!(ErrorCode::NotAcceptable, problem.code);
+        assert_eq!(Some("/auth/token".to_string()), problem.instance);
+    }
+}
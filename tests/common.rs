@@ -1,49 +1,115 @@
-use actix_web::{dev::ServiceResponse, error::InternalError, test, web, App, HttpResponse};
+use actix_web::{dev::ServiceResponse, test, web, App};
 use diesel::{r2d2::ConnectionManager, PgConnection};
-use na::{config::ServerConfig, errors, handlers, middleware::jwt::JwtMiddleware, DbPool};
+use na::handlers::{
+    auth::{TokenCreateRequest, TokenCreateResponse},
+    user::InputUser,
+    OutputUser,
+};
+use na::{config::ServerConfig, routes, DbPool};
 
 pub async fn setup_server() -> impl actix_web::dev::Service<
     actix_http::Request,
     Response = ServiceResponse,
     Error = actix_web::Error,
 > {
-    let cfg = ServerConfig::new_leaked();
-    let manager = ConnectionManager::<PgConnection>::new(&cfg.database.url);
-    let db_pool: DbPool = r2d2::Pool::builder()
-        .build(manager)
-        .expect("Failed to create pool.");
+    setup_server_with(ServerConfig::new_leaked()).await
+}
+
+/// Same as [setup_server], but with a custom server configuration.
+pub async fn setup_server_with(
+    cfg: &'static ServerConfig,
+) -> impl actix_web::dev::Service<
+    actix_http::Request,
+    Response = ServiceResponse,
+    Error = actix_web::Error,
+> {
+    let db_pool = db_pool(cfg);
 
     test::init_service(
         App::new()
             .app_data(web::Data::new(db_pool.clone()))
             .app_data(web::Data::new(cfg))
-            .app_data(
-                web::JsonConfig::default()
-                    .limit(4096)
-                    .error_handler(|err, _req| {
-                        let error_reason = err.to_string();
-                        InternalError::from_response(
-                            err,
-                            HttpResponse::BadRequest().json(errors::ErrorPayload {
-                                reason: error_reason.as_str(),
-                            }),
-                        )
-                        .into()
-                    }),
-            )
-            .service(web::resource("/user").route(web::post().to(handlers::user::register)))
-            .service(web::resource("/auth/token").route(web::post().to(handlers::auth::token)))
-            .service(
-                web::resource("/users")
-                    .wrap(JwtMiddleware {
-                        jwt_config: &cfg.jwt,
-                    })
-                    .route(web::get().to(handlers::users::list)),
-            ),
+            .configure(routes::configure(cfg)),
     )
     .await
 }
 
+/// Initializes the metrics listener application (see
+/// routes::configure_metrics).
+#[allow(dead_code)]
+pub async fn setup_metrics_server() -> impl actix_web::dev::Service<
+    actix_http::Request,
+    Response = ServiceResponse,
+    Error = actix_web::Error,
+> {
+    let db_pool = db_pool(ServerConfig::new_leaked());
+
+    test::init_service(
+        App::new()
+            .app_data(web::Data::new(db_pool))
+            .configure(routes::configure_metrics()),
+    )
+    .await
+}
+
+/// Creates a database pool, to be used by the server or directly by tests.
+pub fn db_pool(cfg: &ServerConfig) -> DbPool {
+    let manager = ConnectionManager::<PgConnection>::new(&cfg.database.url);
+    r2d2::Pool::builder()
+        .build(manager)
+        .expect("Failed to create pool.")
+}
+
+/// Registers a new user with the given credentials and returns it.
+#[allow(dead_code)]
+pub async fn register_user(
+    app: &impl actix_web::dev::Service<
+        actix_http::Request,
+        Response = ServiceResponse,
+        Error = actix_web::Error,
+    >,
+    email: &str,
+    password: &str,
+) -> OutputUser {
+    let req = test::TestRequest::post()
+        .uri("/user")
+        .set_json(InputUser {
+            name: random_string(16),
+            email: email.to_string(),
+            password: password.to_string(),
+        })
+        .to_request();
+    let resp = test::call_service(app, req).await;
+    assert_eq!(201, resp.status().as_u16());
+    let body = test::read_body(resp).await;
+    serde_json::from_slice(&body).unwrap()
+}
+
+/// Creates an auth token for the given credentials.
+#[allow(dead_code)]
+pub async fn create_token(
+    app: &impl actix_web::dev::Service<
+        actix_http::Request,
+        Response = ServiceResponse,
+        Error = actix_web::Error,
+    >,
+    email: &str,
+    password: &str,
+) -> String {
+    let req = test::TestRequest::post()
+        .uri("/auth/token")
+        .set_json(TokenCreateRequest {
+            email: email.to_string(),
+            password: password.to_string(),
+        })
+        .to_request();
+    let resp = test::call_service(app, req).await;
+    assert_eq!(201, resp.status().as_u16());
+    let body = test::read_body(resp).await;
+    let token_create_response: TokenCreateResponse = serde_json::from_slice(&body).unwrap();
+    token_create_response.token
+}
+
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 
@@ -53,3 +119,9 @@ pub fn random_string(length: usize) -> String {
         .map(|_| rng.sample(Alphanumeric) as char)
         .collect()
 }
+
+/// Generates a random (but syntactically valid) email address.
+#[allow(dead_code)]
+pub fn random_email() -> String {
+    format!("{}@example.org", random_string(16).to_lowercase())
+}
@@ -0,0 +1,255 @@
+mod common;
+
+use actix_web::{dev::ServiceResponse, http, test, web::Bytes};
+use na::{
+    errors::{ErrorCode, ProblemDetails},
+    handlers::{
+        auth::{TokenCreateRequest, TokenCreateResponse},
+        user::{InputUser, UserPatch},
+        users::ListResponse,
+        OutputUser,
+    },
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Media types of the supported encodings.
+const MEDIA_TYPES: [&str; 3] = [
+    "application/json",
+    "application/msgpack",
+    "application/cbor",
+];
+
+/// Encodes the value with the encoding of the given media type.
+fn encode(media_type: &str, value: &impl Serialize) -> Vec<u8> {
+    match media_type {
+        "application/json" => serde_json::to_vec(value).unwrap(),
+        "application/msgpack" => rmp_serde::to_vec_named(value).unwrap(),
+        "application/cbor" => {
+            let mut buffer = vec![];
+            ciborium::into_writer(value, &mut buffer).unwrap();
+            buffer
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Decodes the value with the encoding of the given media type.
+fn decode<T: DeserializeOwned>(media_type: &str, body: &[u8]) -> T {
+    match media_type {
+        "application/json" => serde_json::from_slice(body).unwrap(),
+        "application/msgpack" => rmp_serde::from_slice(body).unwrap(),
+        "application/cbor" => ciborium::from_reader(body).unwrap(),
+        _ => unreachable!(),
+    }
+}
+
+/// Returns the `Content-Type` response header value.
+fn content_type(resp: &ServiceResponse) -> String {
+    resp.headers()
+        .get(http::header::CONTENT_TYPE)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+/// Checks if the user can be registered, authorized and updated with the
+/// bodies of every encoding, and the responses are encoded the same way.
+#[actix_web::test]
+async fn round_trip() {
+    let app = common::setup_server().await;
+
+    for media_type in MEDIA_TYPES {
+        let email = common::random_email();
+        let password = common::random_string(16);
+        let name = common::random_string(16);
+
+        let req = test::TestRequest::post()
+            .uri("/v1/user")
+            .insert_header((http::header::CONTENT_TYPE, media_type))
+            .insert_header((http::header::ACCEPT, media_type))
+            .set_payload(encode(
+                media_type,
+                &InputUser {
+                    name: name.clone(),
+                    email: email.clone(),
+                    password: password.clone(),
+                },
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(201, resp.status().as_u16());
+        assert_eq!(media_type, content_type(&resp));
+        let user: OutputUser = decode(media_type, &test::read_body(resp).await);
+        assert_eq!(email, user.email);
+        assert_eq!(name, user.name);
+        assert_eq!(Some(true), user.listed);
+
+        let req = test::TestRequest::post()
+            .uri("/v1/auth/token")
+            .insert_header((http::header::CONTENT_TYPE, media_type))
+            .insert_header((http::header::ACCEPT, media_type))
+            .set_payload(encode(
+                media_type,
+                &TokenCreateRequest {
+                    email: email.clone(),
+                    password: password.clone(),
+                },
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(201, resp.status().as_u16());
+        assert_eq!(media_type, content_type(&resp));
+        let token: TokenCreateResponse = decode(media_type, &test::read_body(resp).await);
+
+        let req = test::TestRequest::get()
+            .uri("/v1/user/me")
+            .insert_header((
+                http::header::AUTHORIZATION,
+                format!("Bearer {}", token.token),
+            ))
+            .insert_header((http::header::ACCEPT, media_type))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(200, resp.status().as_u16());
+        let etag = resp.headers().get(http::header::ETAG).unwrap().clone();
+
+        let req = test::TestRequest::patch()
+            .uri("/v1/user/me")
+            .insert_header((
+                http::header::AUTHORIZATION,
+                format!("Bearer {}", token.token),
+            ))
+            .insert_header((http::header::IF_MATCH, etag))
+            .insert_header((http::header::CONTENT_TYPE, media_type))
+            .insert_header((http::header::ACCEPT, media_type))
+            .set_payload(encode(
+                media_type,
+                &UserPatch {
+                    listed: Some(Some(false)),
+                    ..Default::default()
+                },
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(200, resp.status().as_u16());
+        assert_eq!(media_type, content_type(&resp));
+        let user: OutputUser = decode(media_type, &test::read_body(resp).await);
+        assert_eq!(Some(false), user.listed);
+
+        let req = test::TestRequest::get()
+            .uri("/v1/users?limit=1")
+            .insert_header((
+                http::header::AUTHORIZATION,
+                format!("Bearer {}", token.token),
+            ))
+            .insert_header((http::header::ACCEPT, media_type))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(200, resp.status().as_u16());
+        assert_eq!(media_type, content_type(&resp));
+        let list: ListResponse = decode(media_type, &test::read_body(resp).await);
+        assert!(list.users.len() <= 1);
+    }
+}
+
+/// Checks if the errors are rendered as problem details in the negotiated
+/// encoding, including the ones rendered by actix itself.
+#[actix_web::test]
+async fn problems_negotiated() {
+    let app = common::setup_server().await;
+
+    for (media_type, problem_type) in [
+        ("application/json", "application/problem+json"),
+        ("application/msgpack", "application/problem+msgpack"),
+        ("application/cbor", "application/problem+cbor"),
+    ] {
+        let req = test::TestRequest::post()
+            .uri("/v1/user")
+            .insert_header((http::header::CONTENT_TYPE, media_type))
+            .insert_header((http::header::ACCEPT, media_type))
+            .set_payload(encode(
+                media_type,
+                &InputUser {
+                    name: common::random_string(16),
+                    email: "john.example.org".to_string(),
+                    password: common::random_string(16),
+                },
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(422, resp.status().as_u16());
+        assert_eq!(problem_type, content_type(&resp));
+        let problem: ProblemDetails = decode(media_type, &test::read_body(resp).await);
+        assert_eq!(ErrorCode::ValidationFailed, problem.code);
+        assert_eq!("email", problem.errors[0].field);
+
+        let req = test::TestRequest::put()
+            .uri("/v1/user")
+            .insert_header((http::header::ACCEPT, media_type))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(405, resp.status().as_u16());
+        assert_eq!(problem_type, content_type(&resp));
+        let problem: ProblemDetails = decode(media_type, &test::read_body(resp).await);
+        assert_eq!(ErrorCode::MethodNotAllowed, problem.code);
+    }
+}
+
+/// Checks if service responds with 400 Bad Request when the binary body
+/// cannot be decoded, and with 415 Unsupported Media Type when the body
+/// encoding is not supported.
+#[actix_web::test]
+async fn body_rejected() {
+    let app = common::setup_server().await;
+
+    for media_type in ["application/msgpack", "application/cbor"] {
+        let req = test::TestRequest::post()
+            .uri("/v1/auth/token")
+            .insert_header((http::header::CONTENT_TYPE, media_type))
+            .set_payload(Bytes::from_static(b"\xc1\xff\x00garbage"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(400, resp.status().as_u16());
+        assert_eq!("application/problem+json", content_type(&resp));
+        let problem: ProblemDetails = test::read_body_json(resp).await;
+        assert_eq!(ErrorCode::MalformedRequest, problem.code);
+    }
+
+    let req = test::TestRequest::post()
+        .uri("/v1/auth/token")
+        .insert_header((http::header::CONTENT_TYPE, "application/xml"))
+        .set_payload("<token/>")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(415, resp.status().as_u16());
+}
+
+/// Checks if the response is encoded with the most preferred supported
+/// encoding, falling back to JSON.
+#[actix_web::test]
+async fn accept_ranked() {
+    let app = common::setup_server().await;
+
+    for (accept, expected) in [
+        (None, "application/problem+json"),
+        (Some("text/html"), "application/problem+json"),
+        (Some("*/*"), "application/problem+json"),
+        (
+            Some("application/json;q=0.5, application/cbor"),
+            "application/problem+cbor",
+        ),
+        (
+            Some("application/msgpack;q=0.9, */*;q=0.1"),
+            "application/problem+msgpack",
+        ),
+    ] {
+        let mut req = test::TestRequest::get().uri("/v1/user/me");
+        if let Some(accept) = accept {
+            req = req.insert_header((http::header::ACCEPT, accept));
+        }
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(401, resp.status().as_u16());
+        assert_eq!(expected, content_type(&resp));
+    }
+}
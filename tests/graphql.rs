@@ -0,0 +1,233 @@
+mod common;
+
+use actix_web::{dev::ServiceResponse, http, test, web};
+use na::{config::ServerConfig, models};
+use serde_json::{json, Value};
+
+/// Sends the GraphQL request (with the token, if any) and returns the
+/// response.
+async fn execute(
+    app: &impl actix_web::dev::Service<
+        actix_http::Request,
+        Response = ServiceResponse,
+        Error = actix_web::Error,
+    >,
+    token: Option<&str>,
+    query: &str,
+    variables: Value,
+) -> Value {
+    let mut req = test::TestRequest::post()
+        .uri("/v1/graphql")
+        .set_json(json!({ "query": query, "variables": variables }));
+    if let Some(token) = token {
+        req = req.insert_header((http::header::AUTHORIZATION, format!("Bearer {}", token)));
+    }
+    let resp = test::call_service(app, req.to_request()).await;
+    assert_eq!(200, resp.status().as_u16());
+    test::read_body_json(resp).await
+}
+
+/// Returns the error code of the first error of the response.
+fn error_code(response: &Value) -> &str {
+    response["errors"][0]["extensions"]["code"]
+        .as_str()
+        .unwrap_or_default()
+}
+
+/// Checks if the anonymous client can register, and the user can get and
+/// update the own profile.
+#[actix_web::test]
+async fn register_and_update() {
+    let app = common::setup_server().await;
+    let email = common::random_email();
+    let password = common::random_string(16);
+    let name = common::random_string(16);
+
+    let response = execute(
+        &app,
+        None,
+        "mutation ($input: RegisterInput!) { register(input: $input) { id email name listed } }",
+        json!({ "input": { "name": name, "email": email, "password": password } }),
+    )
+    .await;
+    assert!(response.get("errors").is_none(), "{}", response);
+    let user = &response["data"]["register"];
+    assert_eq!(email, user["email"]);
+    assert_eq!(name, user["name"]);
+    assert_eq!(true, user["listed"]);
+
+    let token = common::create_token(&app, &email, &password).await;
+    let response = execute(&app, Some(&token), "{ me { id email roles } }", Value::Null).await;
+    assert_eq!(user["id"], response["data"]["me"]["id"]);
+    assert_eq!(email, response["data"]["me"]["email"]);
+    assert_eq!(json!([]), response["data"]["me"]["roles"]);
+
+    let response = execute(
+        &app,
+        Some(&token),
+        "mutation { updateProfile(input: { listed: false }) { name listed } }",
+        Value::Null,
+    )
+    .await;
+    assert_eq!(name, response["data"]["updateProfile"]["name"]);
+    assert_eq!(false, response["data"]["updateProfile"]["listed"]);
+}
+
+/// Checks if the errors carry the error code (and the failed validations)
+/// in their extensions.
+#[actix_web::test]
+async fn errors_extended() {
+    let app = common::setup_server().await;
+
+    let response = execute(&app, None, "{ me { id } }", Value::Null).await;
+    assert_eq!("unauthorized", error_code(&response));
+    assert_eq!(401, response["errors"][0]["extensions"]["status"]);
+
+    let response = execute(
+        &app,
+        None,
+        "mutation { register(input: { name: \"john\", email: \"john.example.org\", password: \"password123\" }) { id } }",
+        Value::Null,
+    )
+    .await;
+    assert_eq!("validation_failed", error_code(&response));
+    assert_eq!(
+        "email",
+        response["errors"][0]["extensions"]["errors"][0]["field"]
+    );
+
+    let email = common::random_email();
+    let password = common::random_string(16);
+    let _ = common::register_user(&app, &email, &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+    let response = execute(
+        &app,
+        Some(&token),
+        "{ users { edges { cursor } } }",
+        Value::Null,
+    )
+    .await;
+    assert_eq!("validation_failed", error_code(&response));
+
+    let response = execute(&app, Some(&token), "{ user(id: -1) { id } }", Value::Null).await;
+    assert!(response.get("errors").is_none(), "{}", response);
+    assert_eq!(Value::Null, response["data"]["user"]);
+}
+
+/// Checks if the roles are rendered for the user itself and admins only.
+#[actix_web::test]
+async fn roles_restricted() {
+    let app = common::setup_server().await;
+    let db = web::Data::new(common::db_pool(ServerConfig::new_leaked()));
+    let email = common::random_email();
+    let password = common::random_string(16);
+
+    let response = execute(
+        &app,
+        None,
+        "mutation ($input: RegisterInput!) { register(input: $input) { id roles } }",
+        json!({ "input": { "name": "john", "email": email, "password": password } }),
+    )
+    .await;
+    assert!(response.get("errors").is_none(), "{}", response);
+    assert_eq!(json!([]), response["data"]["register"]["roles"]);
+
+    let admin_email = common::random_email();
+    let admin = common::register_user(&app, &admin_email, &password).await;
+    models::User::grant_role(admin.id, models::ROLE_ADMIN, db).unwrap();
+    let token = common::create_token(&app, &email, &password).await;
+    let admin_token = common::create_token(&app, &admin_email, &password).await;
+
+    let query = "query ($id: Int!) { user(id: $id) { roles } }";
+    let response = execute(&app, Some(&token), query, json!({ "id": admin.id })).await;
+    assert_eq!("forbidden", error_code(&response));
+
+    let response = execute(
+        &app,
+        Some(&token),
+        "{ users(first: 50, sort: ID_DESC) { nodes { id roles } } }",
+        Value::Null,
+    )
+    .await;
+    assert_eq!("forbidden", error_code(&response));
+
+    let response = execute(&app, Some(&admin_token), query, json!({ "id": admin.id })).await;
+    assert!(response.get("errors").is_none(), "{}", response);
+    assert_eq!(json!(["admin"]), response["data"]["user"]["roles"]);
+}
+
+/// Checks if the users connection may be paginated forward with cursors,
+/// along with the roles (for admins) and the total.
+#[actix_web::test]
+async fn users_paginated() {
+    let app = common::setup_server().await;
+    let db = web::Data::new(common::db_pool(ServerConfig::new_leaked()));
+    let email = common::random_email();
+    let password = common::random_string(16);
+    let admin = common::register_user(&app, &email, &password).await;
+    models::User::grant_role(admin.id, models::ROLE_ADMIN, db).unwrap();
+    let _ = common::register_user(&app, &common::random_email(), &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+
+    let query = "query ($after: String) {
+        users(first: 1, after: $after, sort: ID_ASC) {
+            edges { cursor node { id name roles } }
+            pageInfo { hasNextPage hasPreviousPage endCursor }
+            total { count estimated }
+        }
+    }";
+    let first = execute(&app, Some(&token), query, json!({ "after": null })).await;
+    assert!(first.get("errors").is_none(), "{}", first);
+    let users = &first["data"]["users"];
+    assert_eq!(1, users["edges"].as_array().unwrap().len());
+    assert!(users["edges"][0]["node"]["roles"].is_array());
+    assert_eq!(true, users["pageInfo"]["hasNextPage"]);
+    assert!(users["total"]["count"].as_i64().unwrap() >= 2);
+
+    let end_cursor = users["pageInfo"]["endCursor"].clone();
+    assert_eq!(users["edges"][0]["cursor"], end_cursor);
+    let second = execute(&app, Some(&token), query, json!({ "after": end_cursor })).await;
+    assert!(second.get("errors").is_none(), "{}", second);
+    let next = &second["data"]["users"];
+    assert_eq!(true, next["pageInfo"]["hasPreviousPage"]);
+    assert!(
+        next["edges"][0]["node"]["id"].as_i64().unwrap()
+            > users["edges"][0]["node"]["id"].as_i64().unwrap()
+    );
+}
+
+/// Checks if the queries exceeding the complexity or depth limits are
+/// rejected.
+#[actix_web::test]
+async fn limits_enforced() {
+    let app = common::setup_server().await;
+    let email = common::random_email();
+    let password = common::random_string(16);
+    let _ = common::register_user(&app, &email, &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+
+    let response = execute(
+        &app,
+        Some(&token),
+        "{ users(first: 500) { edges { cursor node { id email name createdAt listed roles } } } }",
+        Value::Null,
+    )
+    .await;
+    assert!(response["errors"][0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("complex"));
+    assert_eq!(Value::Null, response["data"]);
+
+    let response = execute(
+        &app,
+        Some(&token),
+        "{ __schema { types { fields { type { ofType { ofType { ofType { ofType { name } } } } } } } } }",
+        Value::Null,
+    )
+    .await;
+    assert!(response["errors"][0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("nested"));
+}
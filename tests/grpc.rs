@@ -0,0 +1,347 @@
+mod common;
+
+use actix_web::web;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use na::grpc::{
+    self,
+    proto::{self, auth_client::AuthClient, users_client::UsersClient},
+};
+use na::{config::ServerConfig, models};
+use tonic::{transport::Channel, Code, Request, Status};
+
+/// Starts the gRPC server on a random port, and returns the clients
+/// connected to it.
+async fn setup_clients() -> (AuthClient<Channel>, UsersClient<Channel>) {
+    let cfg = ServerConfig::new_leaked();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let db = web::Data::new(common::db_pool(cfg));
+    actix_rt::spawn(grpc::serve(listener, db, cfg));
+
+    let channel = Channel::from_shared(format!("http://{addr}"))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    (AuthClient::new(channel.clone()), UsersClient::new(channel))
+}
+
+/// Returns the request carrying the bearer token.
+fn authorized<T>(token: &str, message: T) -> Request<T> {
+    let mut request = Request::new(message);
+    let _ = request
+        .metadata_mut()
+        .insert("authorization", format!("Bearer {token}").parse().unwrap());
+    request
+}
+
+/// Returns the request carrying the credentials of the client configured in
+/// `config/default.toml`.
+fn client_authorized<T>(message: T) -> Request<T> {
+    let mut request = Request::new(message);
+    let _ = request.metadata_mut().insert(
+        "authorization",
+        format!("Basic {}", STANDARD.encode("dev:dev"))
+            .parse()
+            .unwrap(),
+    );
+    request
+}
+
+/// Returns the error code metadata of the failed call.
+fn error_code(status: &Status) -> &str {
+    status
+        .metadata()
+        .get(grpc::ERROR_CODE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+}
+
+/// Registers a random user, and returns it along with its access token.
+async fn register(
+    auth: &mut AuthClient<Channel>,
+    users: &mut UsersClient<Channel>,
+) -> (proto::User, String) {
+    let email = common::random_email();
+    let password = common::random_string(16);
+    let user = users
+        .register(proto::RegisterRequest {
+            name: common::random_string(16),
+            email: email.clone(),
+            password: password.clone(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let token = auth
+        .create_token(proto::CreateTokenRequest { email, password })
+        .await
+        .unwrap()
+        .into_inner()
+        .token;
+    (user, token)
+}
+
+/// Checks if the user can be registered and authorized, and its token is
+/// accepted by the REST API as well.
+#[actix_web::test]
+async fn register_and_authorize() {
+    let (mut auth, mut users) = setup_clients().await;
+    let (user, token) = register(&mut auth, &mut users).await;
+    assert_eq!(Some(true), user.listed);
+
+    let validated = auth
+        .validate_token(client_authorized(proto::ValidateTokenRequest {
+            token: token.clone(),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(validated.valid);
+    assert_eq!(user.id, validated.user_id);
+    assert_eq!(user.email, validated.email);
+    assert!(validated.roles.is_empty());
+    assert!(validated.expires_at > chrono::Utc::now().timestamp());
+
+    let me = users
+        .get_me(authorized(&token, proto::GetMeRequest {}))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(user, me);
+
+    // Other users see the masked email
+    let (_, other_token) = register(&mut auth, &mut users).await;
+    let other = users
+        .get_user(authorized(
+            &other_token,
+            proto::GetUserRequest { id: user.id },
+        ))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(user.name, other.name);
+    assert_ne!(user.email, other.email);
+    assert_eq!(None, other.listed);
+
+    let app = common::setup_server().await;
+    let req = actix_web::test::TestRequest::get()
+        .uri("/v1/user/me")
+        .insert_header(("Authorization", format!("Bearer {token}")))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(200, resp.status().as_u16());
+}
+
+/// Checks if the invalid tokens are reported as such, and the calls
+/// requiring the authorization are rejected without a valid one.
+#[actix_web::test]
+async fn unauthenticated() {
+    let (mut auth, mut users) = setup_clients().await;
+
+    let validated = auth
+        .validate_token(client_authorized(proto::ValidateTokenRequest {
+            token: "invalid".to_string(),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(proto::ValidateTokenResponse::default(), validated);
+
+    // Tokens are validated for the authenticated clients only
+    let (_, token) = register(&mut auth, &mut users).await;
+    let mut wrong_secret = Request::new(proto::ValidateTokenRequest {
+        token: token.clone(),
+    });
+    let _ = wrong_secret.metadata_mut().insert(
+        "authorization",
+        format!("Basic {}", STANDARD.encode("dev:wrong"))
+            .parse()
+            .unwrap(),
+    );
+    for request in [
+        Request::new(proto::ValidateTokenRequest {
+            token: token.clone(),
+        }),
+        authorized(
+            &token,
+            proto::ValidateTokenRequest {
+                token: token.clone(),
+            },
+        ),
+        wrong_secret,
+    ] {
+        let status = auth.validate_token(request).await.unwrap_err();
+        assert_eq!(Code::Unauthenticated, status.code());
+        assert_eq!("invalid_client", error_code(&status));
+    }
+
+    let status = users
+        .get_me(Request::new(proto::GetMeRequest {}))
+        .await
+        .unwrap_err();
+    assert_eq!(Code::Unauthenticated, status.code());
+    assert_eq!("unauthorized", error_code(&status));
+
+    let status = users
+        .list_users(authorized("invalid", proto::ListUsersRequest::default()))
+        .await
+        .unwrap_err();
+    assert_eq!(Code::Unauthenticated, status.code());
+}
+
+/// Checks if the API errors are mapped to the gRPC status codes, along with
+/// the error code metadata.
+#[actix_web::test]
+async fn errors_mapped() {
+    let (mut auth, mut users) = setup_clients().await;
+    let (user, token) = register(&mut auth, &mut users).await;
+
+    let status = users
+        .register(proto::RegisterRequest {
+            name: common::random_string(16),
+            email: "john.example.org".to_string(),
+            password: common::random_string(16),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(Code::InvalidArgument, status.code());
+    assert_eq!("validation_failed", error_code(&status));
+    assert!(status.message().contains("email"));
+
+    let status = users
+        .register(proto::RegisterRequest {
+            name: common::random_string(16),
+            email: user.email.clone(),
+            password: common::random_string(16),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(Code::AlreadyExists, status.code());
+    assert_eq!("conflict", error_code(&status));
+
+    let status = auth
+        .create_token(proto::CreateTokenRequest {
+            email: user.email.clone(),
+            password: common::random_string(16),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(Code::Unauthenticated, status.code());
+    assert_eq!("invalid_credentials", error_code(&status));
+
+    let status = users
+        .get_user(authorized(&token, proto::GetUserRequest { id: -1 }))
+        .await
+        .unwrap_err();
+    assert_eq!(Code::NotFound, status.code());
+
+    let status = users
+        .list_users(authorized(
+            &token,
+            proto::ListUsersRequest {
+                email_prefix: Some("j".to_string()),
+                ..Default::default()
+            },
+        ))
+        .await
+        .unwrap_err();
+    assert_eq!(Code::PermissionDenied, status.code());
+    assert_eq!("forbidden", error_code(&status));
+}
+
+/// Checks if the users list may be paginated with cursors, along with the
+/// roles (for admins) and the total.
+#[actix_web::test]
+async fn list_paginated() {
+    let (mut auth, mut users) = setup_clients().await;
+    let _ = register(&mut auth, &mut users).await;
+    let (user, token) = register(&mut auth, &mut users).await;
+
+    let request = proto::ListUsersRequest {
+        limit: Some(1),
+        include_total: true,
+        include_roles: true,
+        ..Default::default()
+    };
+    let status = users
+        .list_users(authorized(&token, request.clone()))
+        .await
+        .unwrap_err();
+    assert_eq!(Code::PermissionDenied, status.code());
+
+    let db = web::Data::new(common::db_pool(ServerConfig::new_leaked()));
+    models::User::grant_role(user.id, models::ROLE_ADMIN, db).unwrap();
+    let first = users
+        .list_users(authorized(&token, request.clone()))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(1, first.users.len());
+    assert!(first.total.unwrap().count >= 2);
+    assert!(first.next_cursor.is_some());
+    assert_eq!(None, first.prev_cursor);
+
+    let second = users
+        .list_users(authorized(
+            &token,
+            proto::ListUsersRequest {
+                after: first.next_cursor,
+                ..request
+            },
+        ))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(1, second.users.len());
+    assert!(second.users[0].id > first.users[0].id);
+    assert!(second.prev_cursor.is_some());
+}
+
+/// Checks if the users list may be filtered by the creation datetime.
+#[actix_web::test]
+async fn list_created_filtered() {
+    let (mut auth, mut users) = setup_clients().await;
+    let (user, token) = register(&mut auth, &mut users).await;
+
+    let listed = users
+        .list_users(authorized(
+            &token,
+            proto::ListUsersRequest {
+                created_after: Some(user.created_at.clone()),
+                ..Default::default()
+            },
+        ))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(listed.users.iter().all(|listed| listed.id != user.id));
+
+    let listed = users
+        .list_users(authorized(
+            &token,
+            proto::ListUsersRequest {
+                created_before: Some(user.created_at.clone()),
+                sort: proto::UserSort::IdDesc.into(),
+                ..Default::default()
+            },
+        ))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(listed.users.iter().all(|listed| listed.id < user.id));
+
+    let status = users
+        .list_users(authorized(
+            &token,
+            proto::ListUsersRequest {
+                created_after: Some("yesterday".to_string()),
+                ..Default::default()
+            },
+        ))
+        .await
+        .unwrap_err();
+    assert_eq!(Code::InvalidArgument, status.code());
+    assert_eq!("malformed_request", error_code(&status));
+}
@@ -0,0 +1,267 @@
+mod common;
+
+use actix_web::{dev::ServiceResponse, test, web};
+use na::{
+    config::ServerConfig,
+    errors::{ErrorCode, ProblemDetails},
+    handlers::{auth::TokenCreateRequest, user::InputUser},
+    jobs,
+    models::{IdempotencyClaim, IdempotencyKey},
+};
+
+/// Returns the request registering the user, sent with the idempotency key.
+fn register_request(key: &str, user: &InputUser) -> actix_http::Request {
+    test::TestRequest::post()
+        .uri("/user")
+        .append_header(("Idempotency-Key", key))
+        .set_json(user)
+        .to_request()
+}
+
+/// Returns `true` if the response is a replayed one.
+fn is_replayed(resp: &ServiceResponse) -> bool {
+    resp.headers()
+        .get("idempotent-replayed")
+        .map(|value| value == "true")
+        .unwrap_or(false)
+}
+
+/// Returns a random user to be registered.
+fn random_user() -> InputUser {
+    InputUser {
+        name: common::random_string(16),
+        email: common::random_email(),
+        password: common::random_string(16),
+    }
+}
+
+/// Checks if the registration retried with the same idempotency key is
+/// handled once, and the original response is replayed.
+#[actix_web::test]
+async fn register_replayed() {
+    let app = common::setup_server().await;
+    let key = common::random_string(32);
+    let user = random_user();
+
+    let resp = test::call_service(&app, register_request(&key, &user)).await;
+    assert_eq!(201, resp.status().as_u16());
+    assert!(!is_replayed(&resp));
+    let location = resp.headers().get("location").cloned();
+    let body = test::read_body(resp).await;
+
+    let resp = test::call_service(&app, register_request(&key, &user)).await;
+    assert_eq!(201, resp.status().as_u16());
+    assert!(is_replayed(&resp));
+    assert_eq!(location, resp.headers().get("location").cloned());
+    assert_eq!(body, test::read_body(resp).await);
+
+    // Without the key, the request is handled again
+    let req = test::TestRequest::post()
+        .uri("/user")
+        .set_json(&user)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(409, resp.status().as_u16());
+    assert!(!is_replayed(&resp));
+}
+
+/// Checks if the client errors are replayed as well.
+#[actix_web::test]
+async fn client_error_replayed() {
+    let app = common::setup_server().await;
+    let key = common::random_string(32);
+    let user = InputUser {
+        email: "john.example.org".to_string(),
+        ..random_user()
+    };
+
+    for replayed in [false, true] {
+        let resp = test::call_service(&app, register_request(&key, &user)).await;
+        assert_eq!(422, resp.status().as_u16());
+        assert_eq!(replayed, is_replayed(&resp));
+        let body = test::read_body(resp).await;
+        let payload: ProblemDetails = serde_json::from_slice(&body).unwrap();
+        assert_eq!(ErrorCode::ValidationFailed, payload.code);
+    }
+}
+
+/// Checks if the token request is not replayed when sent with the
+/// idempotency key: its response is a credential, which is never stored, so
+/// the key is released and the retry is handled again.
+#[actix_web::test]
+async fn token_not_replayed() {
+    let app = common::setup_server().await;
+    let email = common::random_email();
+    let password = common::random_string(16);
+    let _ = common::register_user(&app, &email, &password).await;
+    let key = common::random_string(32);
+
+    for _ in 0..2 {
+        let req = test::TestRequest::post()
+            .uri("/v1/auth/token")
+            .append_header(("Idempotency-Key", key.as_str()))
+            .set_json(TokenCreateRequest {
+                email: email.clone(),
+                password: password.clone(),
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(201, resp.status().as_u16());
+        assert!(!is_replayed(&resp));
+        assert_eq!("no-store", resp.headers().get("cache-control").unwrap());
+    }
+}
+
+/// Checks if the GraphQL registration retried with the same idempotency key
+/// is handled once, and the original response is replayed.
+#[actix_web::test]
+async fn graphql_register_replayed() {
+    let app = common::setup_server().await;
+    let key = common::random_string(32);
+    let user = random_user();
+    let request = serde_json::json!({
+        "query": "mutation ($input: RegisterInput!) { register(input: $input) { id } }",
+        "variables": { "input": user },
+    });
+
+    let mut ids = vec![];
+    for replayed in [false, true] {
+        let req = test::TestRequest::post()
+            .uri("/v1/graphql")
+            .append_header(("Idempotency-Key", key.as_str()))
+            .set_json(&request)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(200, resp.status().as_u16());
+        assert_eq!(replayed, is_replayed(&resp));
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body.get("errors").is_none(), "{}", body);
+        ids.push(body["data"]["register"]["id"].clone());
+    }
+    assert_eq!(ids[0], ids[1]);
+}
+
+/// Checks if service responds with 422 Unprocessable Entity when the
+/// idempotency key is reused with a different request.
+#[actix_web::test]
+async fn key_reused() {
+    let app = common::setup_server().await;
+    let key = common::random_string(32);
+
+    let resp = test::call_service(&app, register_request(&key, &random_user())).await;
+    assert_eq!(201, resp.status().as_u16());
+
+    let resp = test::call_service(&app, register_request(&key, &random_user())).await;
+    assert_eq!(422, resp.status().as_u16());
+    let body = test::read_body(resp).await;
+    let payload: ProblemDetails = serde_json::from_slice(&body).unwrap();
+    assert_eq!(ErrorCode::IdempotencyKeyReused, payload.code);
+}
+
+/// Checks if service responds with 400 Bad Request when the idempotency key
+/// is malformed.
+#[actix_web::test]
+async fn key_invalid() {
+    let app = common::setup_server().await;
+
+    for key in ["", "with spaces", &"a".repeat(256)] {
+        let resp = test::call_service(&app, register_request(key, &random_user())).await;
+        assert_eq!(400, resp.status().as_u16());
+        let body = test::read_body(resp).await;
+        let payload: ProblemDetails = serde_json::from_slice(&body).unwrap();
+        assert_eq!(ErrorCode::MalformedRequest, payload.code);
+    }
+}
+
+/// Checks if the expired keys are not replayed, and are purged by the job.
+#[actix_web::test]
+async fn key_expired() {
+    let mut cfg = ServerConfig::new_leaked().clone();
+    cfg.idempotency.ttl_secs = 0;
+    let cfg: &'static ServerConfig = Box::leak(Box::new(cfg));
+    let app = common::setup_server_with(cfg).await;
+    let key = common::random_string(32);
+    let user = random_user();
+
+    let resp = test::call_service(&app, register_request(&key, &user)).await;
+    assert_eq!(201, resp.status().as_u16());
+
+    let resp = test::call_service(&app, register_request(&key, &user)).await;
+    assert_eq!(409, resp.status().as_u16());
+    assert!(!is_replayed(&resp));
+
+    let db = web::Data::new(common::db_pool(cfg));
+    let purged = jobs::idempotency::purge(db).await.unwrap();
+    assert!(purged >= 1);
+}
+
+/// Checks if the key of a request abandoned in progress is taken over by a
+/// retry once its lease expires, but not before.
+#[actix_web::test]
+async fn abandoned_key_taken_over() {
+    let cfg = ServerConfig::new_leaked();
+    let db = web::Data::new(common::db_pool(cfg));
+    let key = common::random_string(32);
+    let ttl = cfg.idempotency.ttl();
+    let claim = |fingerprint: &str, lock_timeout: i64| {
+        IdempotencyKey::claim(
+            "anonymous",
+            &key,
+            fingerprint,
+            ttl,
+            chrono::Duration::seconds(lock_timeout),
+            db.clone(),
+        )
+        .unwrap()
+    };
+
+    // The lease of the first claim expires immediately
+    assert!(matches!(claim("a", 0), IdempotencyClaim::Claimed(_)));
+    assert!(matches!(claim("a", 3600), IdempotencyClaim::Claimed(_)));
+    assert!(matches!(claim("a", 3600), IdempotencyClaim::InProgress));
+    assert!(matches!(claim("b", 3600), IdempotencyClaim::Mismatch));
+}
+
+/// Checks if the request the key was taken over from can neither release the
+/// key nor store its response, while the retry holding the key can.
+#[actix_web::test]
+async fn stale_owner_fenced_off() {
+    let cfg = ServerConfig::new_leaked();
+    let db = web::Data::new(common::db_pool(cfg));
+    let key = common::random_string(32);
+    let ttl = cfg.idempotency.ttl();
+    let claim = |lock_timeout: i64| {
+        IdempotencyKey::claim(
+            "anonymous",
+            &key,
+            "a",
+            ttl,
+            chrono::Duration::seconds(lock_timeout),
+            db.clone(),
+        )
+        .unwrap()
+    };
+
+    let IdempotencyClaim::Claimed(stale_lease) = claim(0) else {
+        panic!("key not claimed");
+    };
+    let IdempotencyClaim::Claimed(lease) = claim(3600) else {
+        panic!("key not taken over");
+    };
+    assert_ne!(stale_lease, lease);
+
+    assert!(!IdempotencyKey::release("anonymous", &key, stale_lease, db.clone()).unwrap());
+    assert!(matches!(claim(3600), IdempotencyClaim::InProgress));
+    assert!(
+        !IdempotencyKey::complete("anonymous", &key, stale_lease, 500, "[]", b"", db.clone())
+            .unwrap()
+    );
+    assert!(
+        IdempotencyKey::complete("anonymous", &key, lease, 201, "[]", b"{}", db.clone()).unwrap()
+    );
+    match claim(3600) {
+        IdempotencyClaim::Completed(record) => assert_eq!(Some(201), record.status),
+        other => panic!("unexpected claim: {other:?}"),
+    }
+    assert!(!IdempotencyKey::release("anonymous", &key, lease, db).unwrap());
+}
@@ -1,12 +1,15 @@
 mod common;
 
-use actix_web::{dev::ServiceResponse, http, test};
+use actix_web::{dev::ServiceResponse, http, test, web};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use na::config::ServerConfig;
 use na::handlers::{
     auth::{TokenCreateRequest, TokenCreateResponse},
     user::InputUser,
     users::ListResponse,
     OutputUser,
 };
+use na::models;
 use serial_test::serial;
 
 async fn create_random_user(
@@ -20,7 +23,7 @@ async fn create_random_user(
         .uri("/user")
         .set_json(InputUser {
             name: common::random_string(16),
-            email: common::random_string(16),
+            email: common::random_email(),
             password: common::random_string(16),
         })
         .to_request();
@@ -35,7 +38,7 @@ async fn create_random_user(
 #[serial]
 async fn list_users() {
     let app = common::setup_server().await;
-    let email = common::random_string(16);
+    let email = common::random_email();
     let password = common::random_string(16);
 
     // register new user
@@ -80,7 +83,7 @@ async fn list_users() {
 #[serial]
 async fn list_users_after() {
     let app = common::setup_server().await;
-    let email = common::random_string(16);
+    let email = common::random_email();
     let password = common::random_string(16);
 
     // register a new user
@@ -147,3 +150,503 @@ async fn list_users_after() {
     println!("{:?}", list_response);
     assert_eq!(3, list_response.users.len());
 }
+
+/// Checks if service responds with 422 Unprocessable Entity when the
+/// requested limit is out of bounds.
+#[actix_web::test]
+#[serial]
+async fn list_users_invalid_limit() {
+    let app = common::setup_server().await;
+    let email = common::random_email();
+    let password = common::random_string(16);
+
+    let _ = common::register_user(&app, &email, &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+
+    for limit in [0, -1, 101] {
+        let req = test::TestRequest::get()
+            .uri(format!("/users?limit={}", limit).as_str())
+            .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(422, resp.status().as_u16());
+    }
+}
+
+/// Query string datetime format.
+const DATETIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.6f";
+
+/// Lists users with the given query string, returning the response status and
+/// the listed users ids.
+async fn list_user_ids(
+    app: &impl actix_web::dev::Service<
+        actix_http::Request,
+        Response = ServiceResponse,
+        Error = actix_web::Error,
+    >,
+    token: &str,
+    query: &str,
+) -> (u16, Vec<i32>) {
+    let req = test::TestRequest::get()
+        .uri(format!("/users?{}", query).as_str())
+        .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(app, req).await;
+    let status = resp.status().as_u16();
+    if status != 200 {
+        return (status, vec![]);
+    }
+    let body = test::read_body(resp).await;
+    let list_response: ListResponse = serde_json::from_slice(&body).unwrap();
+    (status, list_response.users.iter().map(|u| u.id).collect())
+}
+
+/// Checks if user list may be filtered.
+#[actix_web::test]
+#[serial]
+async fn list_users_filters() {
+    let app = common::setup_server().await;
+    let db = web::Data::new(common::db_pool(ServerConfig::new_leaked()));
+    let email = common::random_email();
+    let password = common::random_string(16);
+
+    let self_user = common::register_user(&app, &email, &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+
+    // register users sharing a unique name prefix (containing LIKE wildcard)
+    let prefix = format!("{}_%", common::random_string(8));
+    let mut prefixed_users = vec![];
+    for _ in 1..=3 {
+        let req = test::TestRequest::post()
+            .uri("/user")
+            .set_json(InputUser {
+                name: format!("{}{}", prefix, common::random_string(8)),
+                email: common::random_email(),
+                password: common::random_string(16),
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(201, resp.status().as_u16());
+        let body = test::read_body(resp).await;
+        let user: OutputUser = serde_json::from_slice(&body).unwrap();
+        prefixed_users.push(user);
+    }
+    let prefixed_ids: Vec<i32> = prefixed_users.iter().map(|u| u.id).collect();
+
+    // name prefix is case-insensitive, wildcards are matched literally
+    let (status, ids) = list_user_ids(
+        &app,
+        &token,
+        &format!("name_prefix={}", prefix.to_uppercase().replace('%', "%25")),
+    )
+    .await;
+    assert_eq!(200, status);
+    assert_eq!(prefixed_ids, ids);
+
+    // emails are masked, so filtering by them is reserved for admins
+    let (status, _) = list_user_ids(
+        &app,
+        &token,
+        &format!("email_prefix={}", &prefixed_users[1].email[..10]),
+    )
+    .await;
+    assert_eq!(403, status);
+
+    // creation datetime range
+    let (status, ids) = list_user_ids(
+        &app,
+        &token,
+        &format!(
+            "after={}&created_after={}&created_before={}",
+            self_user.id,
+            prefixed_users[0].created_at.format(DATETIME_FORMAT),
+            prefixed_users[2].created_at.format(DATETIME_FORMAT)
+        ),
+    )
+    .await;
+    assert_eq!(200, status);
+    assert_eq!(vec![prefixed_users[1].id], ids);
+
+    let (status, _) = list_user_ids(
+        &app,
+        &token,
+        &format!(
+            "created_after={}&created_before={}",
+            prefixed_users[2].created_at.format(DATETIME_FORMAT),
+            prefixed_users[0].created_at.format(DATETIME_FORMAT)
+        ),
+    )
+    .await;
+    assert_eq!(422, status);
+
+    // roles are filtered by admins only, so the privileged accounts are not
+    // disclosed
+    let role = common::random_string(16);
+    models::User::grant_role(prefixed_users[2].id, &role, db.clone()).unwrap();
+    let (status, _) = list_user_ids(&app, &token, &format!("role={}", role)).await;
+    assert_eq!(403, status);
+    let (status, _) = list_user_ids(&app, &token, "role=admin").await;
+    assert_eq!(403, status);
+
+    // deleted users may be listed by admins only
+    let (status, _) = list_user_ids(&app, &token, "status=deleted").await;
+    assert_eq!(403, status);
+
+    models::User::soft_delete(prefixed_users[0].id, db.clone()).unwrap();
+    models::User::grant_role(self_user.id, models::ROLE_ADMIN, db.clone()).unwrap();
+    let (status, ids) = list_user_ids(
+        &app,
+        &token,
+        &format!("status=deleted&after={}", self_user.id),
+    )
+    .await;
+    assert_eq!(200, status);
+    assert_eq!(vec![prefixed_users[0].id], ids);
+
+    let (status, ids) = list_user_ids(
+        &app,
+        &token,
+        &format!("name_prefix={}", prefix.replace('%', "%25")),
+    )
+    .await;
+    assert_eq!(200, status);
+    assert_eq!(prefixed_ids[1..].to_vec(), ids);
+
+    let (status, ids) = list_user_ids(
+        &app,
+        &token,
+        &format!("email_prefix={}", &prefixed_users[1].email[..10]),
+    )
+    .await;
+    assert_eq!(200, status);
+    assert_eq!(vec![prefixed_users[1].id], ids);
+
+    let (status, ids) = list_user_ids(&app, &token, &format!("role={}", role)).await;
+    assert_eq!(200, status);
+    assert_eq!(vec![prefixed_users[2].id], ids);
+}
+
+/// Lists users with the given query string, returning the response status,
+/// the `Link` header and the parsed response.
+async fn list_users_page(
+    app: &impl actix_web::dev::Service<
+        actix_http::Request,
+        Response = ServiceResponse,
+        Error = actix_web::Error,
+    >,
+    token: &str,
+    query: &str,
+) -> (u16, Option<String>, Option<ListResponse>) {
+    let req = test::TestRequest::get()
+        .uri(format!("/users?{}", query).as_str())
+        .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(app, req).await;
+    let status = resp.status().as_u16();
+    let link = resp
+        .headers()
+        .get(http::header::LINK)
+        .map(|link| link.to_str().unwrap().to_string());
+    let body = test::read_body(resp).await;
+    (status, link, serde_json::from_slice(&body).ok())
+}
+
+/// Checks if user list may be sorted and paged forwards and backwards with
+/// cursors.
+#[actix_web::test]
+#[serial]
+async fn list_users_cursors() {
+    let app = common::setup_server().await;
+    let email = common::random_email();
+    let password = common::random_string(16);
+
+    let _ = common::register_user(&app, &email, &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+
+    // register users, so the name order differs from the id order
+    let prefix = common::random_string(8).to_lowercase();
+    let mut named_users = vec![];
+    for suffix in ["c", "a", "e", "b", "d"] {
+        let req = test::TestRequest::post()
+            .uri("/user")
+            .set_json(InputUser {
+                name: format!("{}{}", prefix, suffix),
+                email: common::random_email(),
+                password: common::random_string(16),
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(201, resp.status().as_u16());
+        let body = test::read_body(resp).await;
+        let user: OutputUser = serde_json::from_slice(&body).unwrap();
+        named_users.push(user);
+    }
+    named_users.sort_by(|a, b| b.name.cmp(&a.name));
+    let expected_ids: Vec<i32> = named_users.iter().map(|u| u.id).collect();
+    let base_query = format!("limit=2&sort=-name&name_prefix={}", prefix);
+
+    // page forwards
+    let mut ids = vec![];
+    let mut query = base_query.clone();
+    let mut last_page;
+    loop {
+        let (status, link, page) = list_users_page(&app, &token, &query).await;
+        assert_eq!(200, status);
+        let page = page.unwrap();
+        ids.extend(page.users.iter().map(|u| u.id));
+        last_page = page;
+        match &last_page.next_cursor {
+            Some(next_cursor) => {
+                assert!(link.unwrap().contains("rel=\"next\""));
+                query = format!("{}&after={}", base_query, next_cursor);
+            }
+            None => break,
+        }
+    }
+    assert_eq!(expected_ids, ids);
+
+    // page backwards from the last page
+    let mut ids: Vec<i32> = last_page.users.iter().map(|u| u.id).collect();
+    while let Some(prev_cursor) = last_page.prev_cursor.clone() {
+        let query = format!("{}&before={}", base_query, prev_cursor);
+        let (status, link, page) = list_users_page(&app, &token, &query).await;
+        assert_eq!(200, status);
+        assert!(link.unwrap().contains("rel=\"next\""));
+        let page = page.unwrap();
+        ids.splice(0..0, page.users.iter().map(|u| u.id));
+        last_page = page;
+    }
+    assert_eq!(expected_ids, ids);
+
+    // invalid cursors are rejected
+    let (_, _, page) = list_users_page(&app, &token, &base_query).await;
+    let next_cursor = page.unwrap().next_cursor.unwrap();
+    let tampered_cursor = format!("{}x", next_cursor);
+    let keyless_cursor = na::pagination::encode_cursor(
+        &serde_json::json!({ "sort": "-name", "id": named_users[0].id }),
+        ServerConfig::new_leaked()
+            .pagination
+            .cursor_secret
+            .as_bytes(),
+    );
+    for query in [
+        format!("{}&after={}", base_query, tampered_cursor),
+        format!("{}&before={}", base_query, keyless_cursor),
+        format!("limit=2&sort=name&after={}", next_cursor),
+        format!(
+            "{}&after={}&before={}",
+            base_query, next_cursor, next_cursor
+        ),
+    ] {
+        let (status, _, _) = list_users_page(&app, &token, &query).await;
+        assert_eq!(422, status);
+    }
+}
+
+/// Checks if the total number of matching users may be included, exact for
+/// filtered lists and estimated for large unfiltered ones.
+#[actix_web::test]
+#[serial]
+async fn list_users_total() {
+    let mut cfg = ServerConfig::new_leaked().clone();
+    cfg.pagination.exact_count_threshold = 0;
+    let app = common::setup_server_with(Box::leak(Box::new(cfg))).await;
+    let db = common::db_pool(ServerConfig::new_leaked());
+    let email = common::random_email();
+    let password = common::random_string(16);
+
+    let self_user = common::register_user(&app, &email, &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+
+    let prefix = common::random_string(8).to_lowercase();
+    let mut prefixed_users = vec![];
+    for _ in 1..=3 {
+        let req = test::TestRequest::post()
+            .uri("/user")
+            .set_json(InputUser {
+                name: format!("{}{}", prefix, common::random_string(8)),
+                email: common::random_email(),
+                password: common::random_string(16),
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(201, resp.status().as_u16());
+        let user: OutputUser = test::read_body_json(resp).await;
+        prefixed_users.push(user);
+    }
+
+    let query = format!("limit=2&include_total=true&name_prefix={}", prefix);
+    let (status, _, page) = list_users_page(&app, &token, &query).await;
+    assert_eq!(200, status);
+    let page = page.unwrap();
+    assert_eq!(2, page.users.len());
+    let total = page.total.unwrap();
+    assert_eq!(3, total.count);
+    assert!(!total.estimated);
+
+    let (_, _, page) = list_users_page(&app, &token, &format!("name_prefix={}", prefix)).await;
+    assert!(page.unwrap().total.is_none());
+
+    let db = web::Data::new(db);
+    models::User::soft_delete(prefixed_users[0].id, db.clone()).unwrap();
+    let _ = diesel::sql_query("ANALYZE users")
+        .execute(&mut db.get().unwrap())
+        .unwrap();
+    // statistics don't account for unlisted users, so they are used for admins only
+    let (status, _, page) = list_users_page(&app, &token, "include_total=true").await;
+    assert_eq!(200, status);
+    assert!(!page.unwrap().total.unwrap().estimated);
+
+    models::User::grant_role(self_user.id, models::ROLE_ADMIN, db.clone()).unwrap();
+    let (status, _, page) = list_users_page(&app, &token, "include_total=true").await;
+    assert_eq!(200, status);
+    let total = page.unwrap().total.unwrap();
+    assert!(total.estimated);
+
+    // the deleted users are not counted, so the fresh statistics are exact
+    let active = na::schema::users::table
+        .filter(na::schema::users::deleted_at.is_null())
+        .count()
+        .get_result::<i64>(&mut db.get().unwrap())
+        .unwrap();
+    assert_eq!(active, total.count);
+}
+
+/// Checks if unlisted users are only listed for themselves and admins, and
+/// if other users' emails are masked.
+#[actix_web::test]
+#[serial]
+async fn list_users_unlisted() {
+    let app = common::setup_server().await;
+    let db = web::Data::new(common::db_pool(ServerConfig::new_leaked()));
+    let prefix = common::random_string(8).to_lowercase();
+
+    let mut users = vec![];
+    for _ in 1..=3 {
+        let email = common::random_email();
+        let password = common::random_string(16);
+        let req = test::TestRequest::post()
+            .uri("/user")
+            .set_json(InputUser {
+                name: format!("{}{}", prefix, common::random_string(8)),
+                email: email.clone(),
+                password: password.clone(),
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(201, resp.status().as_u16());
+        let body = test::read_body(resp).await;
+        let user: OutputUser = serde_json::from_slice(&body).unwrap();
+        assert_eq!(Some(true), user.listed);
+        let token = common::create_token(&app, &email, &password).await;
+        users.push((user, token));
+    }
+    let (unlisted, unlisted_token) = &users[0];
+    let (other, other_token) = &users[1];
+    let (admin, admin_token) = &users[2];
+    models::User::grant_role(admin.id, models::ROLE_ADMIN, db.clone()).unwrap();
+
+    // hide the first user from the list
+    let req = test::TestRequest::get()
+        .uri("/user/me")
+        .append_header((
+            http::header::AUTHORIZATION,
+            format!("Bearer {}", unlisted_token),
+        ))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    let etag = resp.headers().get(http::header::ETAG).unwrap().clone();
+    let req = test::TestRequest::patch()
+        .uri("/user/me")
+        .append_header((
+            http::header::AUTHORIZATION,
+            format!("Bearer {}", unlisted_token),
+        ))
+        .append_header((http::header::IF_MATCH, etag))
+        .set_json(serde_json::json!({ "listed": false }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(200, resp.status().as_u16());
+
+    let query = format!("name_prefix={}", prefix);
+    let (status, _, page) = list_users_page(&app, other_token, &query).await;
+    assert_eq!(200, status);
+    let page = page.unwrap();
+    let ids: Vec<i32> = page.users.iter().map(|u| u.id).collect();
+    assert_eq!(vec![other.id, admin.id], ids);
+    assert_eq!(Some(other.email.clone()), page.users[0].email);
+    assert_eq!(
+        Some(format!("{}***@example.org", &admin.email[..1])),
+        page.users[1].email
+    );
+    assert_eq!(None, page.users[1].listed);
+
+    for (token, expected_ids) in [
+        (unlisted_token, vec![unlisted.id, other.id, admin.id]),
+        (admin_token, vec![unlisted.id, other.id, admin.id]),
+    ] {
+        let (status, ids) = list_user_ids(&app, token, &query).await;
+        assert_eq!(200, status);
+        assert_eq!(expected_ids, ids);
+    }
+
+    let (_, _, page) = list_users_page(&app, admin_token, &query).await;
+    let page = page.unwrap();
+    assert_eq!(Some(unlisted.email.clone()), page.users[0].email);
+    assert_eq!(Some(false), page.users[0].listed);
+}
+
+/// Checks if users may be projected to the requested fields and extended with
+/// the related data.
+#[actix_web::test]
+#[serial]
+async fn list_users_fields() {
+    let app = common::setup_server().await;
+    let db = web::Data::new(common::db_pool(ServerConfig::new_leaked()));
+    let email = common::random_email();
+    let password = common::random_string(16);
+
+    let self_user = common::register_user(&app, &email, &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+    let role = common::random_string(16);
+    models::User::grant_role(self_user.id, &role, db.clone()).unwrap();
+
+    // the role filter is reserved for admins
+    let admin_email = common::random_email();
+    let admin = common::register_user(&app, &admin_email, &password).await;
+    models::User::grant_role(admin.id, models::ROLE_ADMIN, db.clone()).unwrap();
+    let admin_token = common::create_token(&app, &admin_email, &password).await;
+
+    let query = format!("role={}&fields=id,name&sort=-created_at&expand=roles", role);
+    let (status, _, page) = list_users_page(&app, &admin_token, &query).await;
+    assert_eq!(200, status);
+    let user = &page.unwrap().users[0];
+    assert_eq!(self_user.id, user.id);
+    assert_eq!(Some(self_user.name.clone()), user.name);
+    assert_eq!(None, user.email);
+    assert_eq!(None, user.created_at);
+    assert_eq!(None, user.listed);
+    assert_eq!(Some(vec![role.clone()]), user.roles);
+
+    let (status, _, page) = list_users_page(&app, &admin_token, &format!("role={}", role)).await;
+    assert_eq!(200, status);
+    let user = &page.unwrap().users[0];
+    assert_eq!(Some(self_user.email.clone()), user.email);
+    assert_eq!(Some(self_user.created_at), user.created_at);
+    assert_eq!(None, user.roles);
+
+    // roles of other users are listed to admins only
+    let (status, _, _) = list_users_page(&app, &token, "expand=roles").await;
+    assert_eq!(403, status);
+
+    for query in [
+        "fields=id,password",
+        "fields=",
+        "fields=id,,name",
+        "expand=identities",
+        "expand=roles,",
+    ] {
+        let (status, _, _) = list_users_page(&app, &token, query).await;
+        assert_eq!(422, status, "{}", query);
+    }
+}
@@ -0,0 +1,23 @@
+mod common;
+
+use actix_web::test;
+
+/// Checks if the metrics are served by the metrics listener only, and not
+/// along with the API.
+#[actix_web::test]
+async fn metrics_served_separately() {
+    let app = common::setup_server().await;
+    let req = test::TestRequest::get().uri("/metrics").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(404, resp.status().as_u16());
+
+    let metrics = common::setup_metrics_server().await;
+    let req = test::TestRequest::get().uri("/metrics").to_request();
+    let resp = test::call_service(&metrics, req).await;
+    assert_eq!(200, resp.status().as_u16());
+    assert!(resp.headers().get("x-request-id").is_some());
+    let body = test::read_body(resp).await;
+    assert!(std::str::from_utf8(&body)
+        .unwrap()
+        .contains("# TYPE na_legacy_password_hashes gauge"));
+}
@@ -0,0 +1,160 @@
+mod common;
+
+use actix_web::{dev::ServiceResponse, http, test, web};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use na::{
+    config::ServerConfig,
+    errors::{ErrorCode, ProblemDetails},
+    handlers::oauth::{IntrospectionResponse, TOKEN_CLIENT_ID},
+    models,
+};
+
+/// Returns the `Authorization` header value of the client configured in
+/// `config/default.toml`.
+fn client_basic() -> String {
+    format!("Basic {}", STANDARD.encode("dev:dev"))
+}
+
+/// Introspects the token, authenticating with the given `Authorization`
+/// header value (if any).
+async fn introspect(
+    app: &impl actix_web::dev::Service<
+        actix_http::Request,
+        Response = ServiceResponse,
+        Error = actix_web::Error,
+    >,
+    authorization: Option<&str>,
+    form: &[(&str, &str)],
+) -> ServiceResponse {
+    let mut req = test::TestRequest::post()
+        .uri("/oauth/introspect")
+        .set_form(form);
+    if let Some(authorization) = authorization {
+        req = req.insert_header((http::header::AUTHORIZATION, authorization));
+    }
+    test::call_service(app, req.to_request()).await
+}
+
+/// Checks if the active token is described, with the client authenticated
+/// either with the `Authorization` header or with the form fields.
+#[actix_web::test]
+async fn token_active() {
+    let app = common::setup_server().await;
+    let email = common::random_email();
+    let password = common::random_string(16);
+    let _ = common::register_user(&app, &email, &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+
+    let resp = introspect(&app, Some(&client_basic()), &[("token", &token)]).await;
+    assert_eq!(200, resp.status().as_u16());
+    assert_eq!(
+        "no-store",
+        resp.headers().get(http::header::CACHE_CONTROL).unwrap()
+    );
+    let description: IntrospectionResponse = test::read_body_json(resp).await;
+    assert!(description.active);
+    assert_eq!(Some(email.clone()), description.sub);
+    assert_eq!(None, description.scope);
+    assert!(description.exp.unwrap() as i64 > chrono::Utc::now().timestamp());
+    assert_eq!(Some(TOKEN_CLIENT_ID.to_string()), description.client_id);
+
+    let resp = introspect(
+        &app,
+        None,
+        &[
+            ("token", &token),
+            ("token_type_hint", "access_token"),
+            ("client_id", "dev"),
+            ("client_secret", "dev"),
+        ],
+    )
+    .await;
+    assert_eq!(200, resp.status().as_u16());
+    let description: IntrospectionResponse = test::read_body_json(resp).await;
+    assert!(description.active);
+    assert_eq!(Some(email), description.sub);
+}
+
+/// Checks if the roles granted to the token subject are listed as the scope.
+#[actix_web::test]
+async fn token_scope() {
+    let app = common::setup_server().await;
+    let email = common::random_email();
+    let password = common::random_string(16);
+    let user = common::register_user(&app, &email, &password).await;
+    let db = web::Data::new(common::db_pool(ServerConfig::new_leaked()));
+    models::User::grant_role(user.id, models::ROLE_ADMIN, db).unwrap();
+    let token = common::create_token(&app, &email, &password).await;
+
+    let resp = introspect(&app, Some(&client_basic()), &[("token", &token)]).await;
+    let description: IntrospectionResponse = test::read_body_json(resp).await;
+    assert!(description.active);
+    assert_eq!(Some(models::ROLE_ADMIN.to_string()), description.scope);
+}
+
+/// Checks if the invalid tokens, and the tokens of the deleted users, are
+/// described as inactive, with no other members.
+#[actix_web::test]
+async fn token_inactive() {
+    let app = common::setup_server().await;
+
+    let resp = introspect(&app, Some(&client_basic()), &[("token", "invalid")]).await;
+    assert_eq!(200, resp.status().as_u16());
+    let body = test::read_body(resp).await;
+    assert_eq!(&b"{\"active\":false}"[..], &body[..]);
+
+    let email = common::random_email();
+    let password = common::random_string(16);
+    let _ = common::register_user(&app, &email, &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+    let req = test::TestRequest::delete()
+        .uri("/user/me")
+        .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(204, resp.status().as_u16());
+
+    let resp = introspect(&app, Some(&client_basic()), &[("token", &token)]).await;
+    let description: IntrospectionResponse = test::read_body_json(resp).await;
+    assert!(!description.active);
+    assert_eq!(None, description.sub);
+}
+
+/// Checks if service responds with 401 Unauthorized when the client
+/// authentication fails.
+#[actix_web::test]
+async fn client_rejected() {
+    let app = common::setup_server().await;
+    let wrong_secret = format!("Basic {}", STANDARD.encode("dev:wrong"));
+    let user_token = "Bearer eyJ0e.xb26ww";
+
+    for (authorization, form) in [
+        (None, vec![("token", "token")]),
+        (Some(wrong_secret.as_str()), vec![("token", "token")]),
+        (Some(user_token), vec![("token", "token")]),
+        (
+            None,
+            vec![
+                ("token", "token"),
+                ("client_id", "dev"),
+                ("client_secret", "x"),
+            ],
+        ),
+        // Only one authentication method may be used
+        (
+            Some(client_basic().as_str()),
+            vec![
+                ("token", "token"),
+                ("client_id", "dev"),
+                ("client_secret", "dev"),
+            ],
+        ),
+    ] {
+        let resp = introspect(&app, authorization, &form).await;
+        assert_eq!(401, resp.status().as_u16());
+        assert!(resp.headers().get(http::header::WWW_AUTHENTICATE).is_some());
+        let problem: ProblemDetails = test::read_body_json(resp).await;
+        assert_eq!(ErrorCode::InvalidClient, problem.code);
+    }
+}
@@ -0,0 +1,172 @@
+mod common;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use actix_web::{http, test};
+use na::models;
+use serde_json::Value;
+
+/// Loads the OpenAPI specification served by the application.
+async fn load_spec(
+    app: &impl actix_web::dev::Service<
+        actix_http::Request,
+        Response = actix_web::dev::ServiceResponse,
+        Error = actix_web::Error,
+    >,
+) -> Value {
+    let req = test::TestRequest::get().uri("/openapi.json").to_request();
+    let resp = test::call_service(app, req).await;
+    assert_eq!(200, resp.status().as_u16());
+    let body = test::read_body(resp).await;
+    serde_json::from_slice(&body).unwrap()
+}
+
+/// Returns the operations of the specification: methods per path.
+fn spec_operations(spec: &Value) -> BTreeMap<String, BTreeSet<String>> {
+    spec["paths"]
+        .as_object()
+        .unwrap()
+        .iter()
+        .map(|(path, item)| {
+            let methods = item
+                .as_object()
+                .unwrap()
+                .keys()
+                .filter(|key| key.as_str() != "parameters")
+                .map(|method| method.to_uppercase())
+                .collect();
+            (path.clone(), methods)
+        })
+        .collect()
+}
+
+/// Checks if the specification is served, and describes the security scheme
+/// and the error responses.
+#[actix_web::test]
+async fn spec_served() {
+    let app = common::setup_server().await;
+    let spec = load_spec(&app).await;
+
+    assert!(spec["openapi"].as_str().unwrap().starts_with("3.1"));
+    assert_eq!(
+        "bearer",
+        spec["components"]["securitySchemes"]["bearer_auth"]["scheme"]
+    );
+    for schema in [
+        "InputUser",
+        "OutputUser",
+        "TokenCreateRequest",
+        "ProblemDetails",
+    ] {
+        assert!(
+            spec["components"]["schemas"][schema].is_object(),
+            "{schema}"
+        );
+    }
+    let params: Vec<&str> = spec["paths"]["/v1/users"]["get"]["parameters"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|param| param["name"].as_str().unwrap())
+        .collect();
+    assert!(params.contains(&"limit"), "{params:?}");
+    assert!(params.contains(&"sort"), "{params:?}");
+    assert!(
+        spec["paths"]["/v1/user/me"]["get"]["responses"]["401"]["content"]
+            ["application/problem+json"]
+            .is_object()
+    );
+
+    let req = test::TestRequest::get().uri("/docs/").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(200, resp.status().as_u16());
+}
+
+/// Returns the paths of the resources registered in the routing table source.
+fn routed_paths(source: &str) -> impl Iterator<Item = &str> {
+    source
+        .split("web::resource(\"")
+        .skip(1)
+        .filter_map(|rest| rest.split_once('"'))
+        .map(|(path, _)| path)
+}
+
+/// Checks if the specification matches the routes: every documented path is
+/// routed, with exactly the documented methods allowed, every routed path is
+/// documented, and every endpoint listed in the binary target docs is
+/// documented.
+#[actix_web::test]
+async fn spec_matches_routes() {
+    let app = common::setup_server().await;
+    let spec = load_spec(&app).await;
+    let operations = spec_operations(&spec);
+
+    // Admin token, so no route is hidden behind the authorization
+    let db = actix_web::web::Data::new(common::db_pool(na::config::ServerConfig::new_leaked()));
+    let email = common::random_email();
+    let password = common::random_string(16);
+    let admin = common::register_user(&app, &email, &password).await;
+    models::User::grant_role(admin.id, models::ROLE_ADMIN, db).unwrap();
+    let token = common::create_token(&app, &email, &password).await;
+
+    for (path, methods) in &operations {
+        // Methods which are not routed are rejected with the `Allow` header
+        // listing the routed ones
+        let req = test::TestRequest::default()
+            .method(http::Method::TRACE)
+            .uri(&path.replace("{id}", &admin.id.to_string()))
+            .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(405, resp.status().as_u16(), "{path}");
+        let allowed: BTreeSet<String> = resp
+            .headers()
+            .get(http::header::ALLOW)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .split(',')
+            .map(|method| method.trim().to_string())
+            .collect();
+        assert_eq!(methods, &allowed, "{path}");
+    }
+
+    // The version routes are documented under the version prefix only (the
+    // unversioned paths are their aliases). The metrics are served by their
+    // own listener, apart from the API
+    let (root, v1) = include_str!("../src/routes.rs")
+        .split_once("\nfn api_v1(")
+        .unwrap();
+    let (_, root) = root.split_once("\npub fn configure(").unwrap();
+    let routed: BTreeSet<String> = routed_paths(root)
+        .map(str::to_string)
+        .chain(routed_paths(v1).map(|path| format!("/v1{path}")))
+        .collect();
+    assert!(routed.contains("/v1/user") && routed.contains("/openapi.json"));
+    let undocumented: Vec<&String> = routed
+        .iter()
+        .filter(|path| !operations.contains_key(*path))
+        .collect();
+    assert!(undocumented.is_empty(), "{undocumented:?}");
+
+    let documented: BTreeSet<(String, String)> = operations
+        .iter()
+        .flat_map(|(path, methods)| {
+            methods
+                .iter()
+                .map(move |method| (method.clone(), path.clone()))
+        })
+        .collect();
+    let listed: BTreeSet<(String, String)> = include_str!("../src/main.rs")
+        .lines()
+        .filter_map(|line| line.strip_prefix("//! - "))
+        .filter_map(|line| {
+            let (endpoint, _) = line.split_once(':')?;
+            let (method, path) = endpoint.split_once(' ')?;
+            Some((method.to_string(), path.to_string()))
+        })
+        // The documentation UI itself is not a part of the API
+        .filter(|(_, path)| path != "/docs")
+        .collect();
+    assert_eq!(listed, documented);
+}
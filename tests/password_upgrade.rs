@@ -0,0 +1,177 @@
+mod common;
+
+use actix_web::{dev::ServiceResponse, http, test, web};
+use argon2::password_hash::{rand_core::OsRng, PasswordHasher, SaltString};
+use na::config::ServerConfig;
+use na::models;
+use serial_test::serial;
+
+/// Imports the users with the given pre-hashed passwords.
+async fn import_users(
+    app: &impl actix_web::dev::Service<
+        actix_http::Request,
+        Response = ServiceResponse,
+        Error = actix_web::Error,
+    >,
+    db: web::Data<na::DbPool>,
+    users: &[(&str, &str)],
+) {
+    let email = common::random_email();
+    let password = common::random_string(16);
+    let admin = common::register_user(app, &email, &password).await;
+    models::User::grant_role(admin.id, models::ROLE_ADMIN, db).unwrap();
+    let token = common::create_token(app, &email, &password).await;
+
+    let body: Vec<String> = users
+        .iter()
+        .map(|(email, hash)| {
+            serde_json::json!({"email": email, "name": "Legacy", "password_hash": hash}).to_string()
+        })
+        .collect();
+    let req = test::TestRequest::post()
+        .uri("/users/import")
+        .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+        .set_payload(body.join("\n"))
+        .to_request();
+    let resp = test::call_service(app, req).await;
+    assert_eq!(200, resp.status().as_u16());
+    let body = test::read_body(resp).await;
+    for line in std::str::from_utf8(&body).unwrap().lines() {
+        assert!(line.contains("\"created\""), "{line}");
+    }
+}
+
+/// Returns the number of legacy password hashes of the given scheme, as
+/// reported by the metrics endpoint.
+async fn legacy_hashes(
+    app: &impl actix_web::dev::Service<
+        actix_http::Request,
+        Response = ServiceResponse,
+        Error = actix_web::Error,
+    >,
+    scheme: &str,
+) -> i64 {
+    let req = test::TestRequest::get().uri("/metrics").to_request();
+    let resp = test::call_service(app, req).await;
+    assert_eq!(200, resp.status().as_u16());
+    let body = test::read_body(resp).await;
+    let prefix = format!("na_legacy_password_hashes{{scheme=\"{scheme}\"}} ");
+    std::str::from_utf8(&body)
+        .unwrap()
+        .lines()
+        .find_map(|line| line.strip_prefix(&prefix))
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+/// Checks if users with bcrypt, scrypt, PBKDF2 and outdated Argon2 hashes can
+/// log in, and their hashes are upgraded to the configured Argon2.
+#[actix_web::test]
+#[serial]
+async fn login_upgrades_legacy_hashes() {
+    let app = common::setup_server().await;
+    let metrics = common::setup_metrics_server().await;
+    let db = web::Data::new(common::db_pool(ServerConfig::new_leaked()));
+    let password = common::random_string(16);
+    let salt = SaltString::generate(&mut OsRng);
+
+    let bcrypt_hash = bcrypt::hash(&password, 4).unwrap();
+    let scrypt_hash = scrypt::Scrypt
+        .hash_password_customized(
+            password.as_bytes(),
+            None,
+            None,
+            scrypt::Params::new(10, 8, 1, 32).unwrap(),
+            &salt,
+        )
+        .unwrap()
+        .to_string();
+    let pbkdf2_hash = pbkdf2::Pbkdf2
+        .hash_password_customized(
+            password.as_bytes(),
+            None,
+            None,
+            pbkdf2::Params {
+                rounds: 1000,
+                output_length: 32,
+            },
+            &salt,
+        )
+        .unwrap()
+        .to_string();
+    let argon2i_hash = argon2::Argon2::new(
+        argon2::Algorithm::Argon2i,
+        argon2::Version::V0x13,
+        argon2::Params::new(8192, 1, 1, None).unwrap(),
+    )
+    .hash_password(password.as_bytes(), &salt)
+    .unwrap()
+    .to_string();
+
+    let users = [
+        (common::random_email(), bcrypt_hash, Some("bcrypt")),
+        (common::random_email(), scrypt_hash, Some("scrypt")),
+        (common::random_email(), pbkdf2_hash, Some("pbkdf2")),
+        (common::random_email(), argon2i_hash, None),
+    ];
+    let records: Vec<(&str, &str)> = users
+        .iter()
+        .map(|(email, hash, _)| (email.as_str(), hash.as_str()))
+        .collect();
+    import_users(&app, db.clone(), &records).await;
+
+    for (email, hash, scheme) in &users {
+        let before = match scheme {
+            Some(scheme) => legacy_hashes(&metrics, scheme).await,
+            None => 0,
+        };
+        let _ = common::create_token(&app, email, &password).await;
+
+        let user = models::User::find_by_email(email.clone(), db.clone()).unwrap();
+        assert_ne!(hash, &user.hashed_password);
+        assert!(
+            user.hashed_password
+                .starts_with("$argon2id$v=19$m=19456,t=2,p=1$"),
+            "{}",
+            user.hashed_password
+        );
+        if let Some(scheme) = scheme {
+            assert_eq!(
+                before - 1,
+                legacy_hashes(&metrics, scheme).await,
+                "{scheme}"
+            );
+        }
+
+        // The upgraded hash is verified as well, and kept as is
+        let _ = common::create_token(&app, email, &password).await;
+        let upgraded = models::User::find_by_email(email.clone(), db.clone()).unwrap();
+        assert_eq!(user.hashed_password, upgraded.hashed_password);
+    }
+}
+
+/// Checks if users with legacy hashes cannot log in with a wrong password, and
+/// their hashes are kept.
+#[actix_web::test]
+#[serial]
+async fn login_rejects_wrong_legacy_password() {
+    let app = common::setup_server().await;
+    let db = web::Data::new(common::db_pool(ServerConfig::new_leaked()));
+    let email = common::random_email();
+    let hash = bcrypt::hash(common::random_string(16), 4).unwrap();
+    import_users(&app, db.clone(), &[(&email, &hash)]).await;
+
+    let req = test::TestRequest::post()
+        .uri("/auth/token")
+        .set_json(na::handlers::auth::TokenCreateRequest {
+            email: email.clone(),
+            password: common::random_string(16),
+        })
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(400, resp.status().as_u16());
+
+    let user = models::User::find_by_email(email, db).unwrap();
+    assert_eq!(hash, user.hashed_password);
+}
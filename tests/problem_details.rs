@@ -0,0 +1,171 @@
+mod common;
+
+use actix_web::{body::MessageBody, http, test};
+use na::errors::{ErrorCode, ProblemDetails};
+use na::handlers::auth::TokenCreateRequest;
+
+/// Parses the problem details response body, checking the content type.
+fn parse_problem(resp: actix_web::HttpResponse) -> ProblemDetails {
+    assert_eq!(
+        "application/problem+json",
+        resp.headers().get(http::header::CONTENT_TYPE).unwrap()
+    );
+    let body = resp.into_body().try_into_bytes().unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+/// Checks if handler errors are rendered as problem details.
+#[actix_web::test]
+async fn handler_errors() {
+    let app = common::setup_server().await;
+
+    let req = test::TestRequest::post()
+        .uri("/auth/token")
+        .set_json(TokenCreateRequest {
+            email: common::random_email(),
+            password: common::random_string(16),
+        })
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    let problem = parse_problem(resp.into_parts().1);
+    assert_eq!(ErrorCode::InvalidCredentials, problem.code);
+    assert_eq!(400, problem.status);
+    assert_eq!("urn:na:error:invalid_credentials", problem.problem_type);
+    assert_eq!("Invalid credentials", problem.title);
+    assert_eq!(Some("/auth/token".to_string()), problem.instance);
+    assert!(problem.errors.is_empty());
+}
+
+/// Checks if malformed JSON bodies are rejected with problem details.
+#[actix_web::test]
+async fn malformed_json() {
+    let app = common::setup_server().await;
+
+    let req = test::TestRequest::post()
+        .uri("/user")
+        .insert_header((http::header::CONTENT_TYPE, "application/json"))
+        .set_payload("{\"email\": ")
+        .to_request();
+    let resp = test::try_call_service(&app, req).await.unwrap();
+    assert_eq!(400, resp.status().as_u16());
+    let problem = parse_problem(resp.into_parts().1);
+    assert_eq!(ErrorCode::MalformedRequest, problem.code);
+    assert!(problem.detail.is_some());
+}
+
+/// Checks if the authorization middleware rejects requests with problem
+/// details.
+#[actix_web::test]
+async fn unauthorized() {
+    let app = common::setup_server().await;
+
+    for token in [None, Some("garbage")] {
+        let mut req = test::TestRequest::get().uri("/user/me");
+        if let Some(token) = token {
+            req = req.append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)));
+        }
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(401, resp.status().as_u16());
+        assert_eq!(
+            "Bearer",
+            resp.headers().get(http::header::WWW_AUTHENTICATE).unwrap()
+        );
+        let problem = parse_problem(resp.into_parts().1);
+        assert_eq!(ErrorCode::Unauthorized, problem.code);
+        assert_eq!(401, problem.status);
+        assert_eq!(Some("/user/me".to_string()), problem.instance);
+    }
+}
+
+/// Checks if query, path, routing and media type rejections are rendered as
+/// problem details.
+#[actix_web::test]
+async fn rejections() {
+    let app = common::setup_server().await;
+    let email = common::random_email();
+    let password = common::random_string(16);
+    let _ = common::register_user(&app, &email, &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+
+    for (method, uri, expected_code) in [
+        (
+            http::Method::GET,
+            "/users?limit=abc",
+            ErrorCode::MalformedRequest,
+        ),
+        (http::Method::GET, "/user/abc", ErrorCode::NotFound),
+        (http::Method::GET, "/no/such/route", ErrorCode::NotFound),
+        (http::Method::PUT, "/user/me", ErrorCode::MethodNotAllowed),
+        (
+            http::Method::DELETE,
+            "/auth/token",
+            ErrorCode::MethodNotAllowed,
+        ),
+    ] {
+        let req = test::TestRequest::default()
+            .method(method.clone())
+            .uri(uri)
+            .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let status = resp.status();
+        let (_, resp) = resp.into_parts();
+        let allow = resp.headers().get(http::header::ALLOW).cloned();
+        let problem = parse_problem(resp);
+        assert_eq!(expected_code, problem.code, "{method} {uri}");
+        assert_eq!(status.as_u16(), problem.status, "{method} {uri}");
+        assert_eq!(
+            Some(uri.split('?').next().unwrap().to_string()),
+            problem.instance
+        );
+        if expected_code == ErrorCode::MethodNotAllowed {
+            assert!(allow.is_some(), "{method} {uri}");
+        }
+    }
+
+    let req = test::TestRequest::post()
+        .uri("/user")
+        .insert_header((http::header::CONTENT_TYPE, "text/plain"))
+        .set_payload("{}")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(415, resp.status().as_u16());
+    let problem = parse_problem(resp.into_parts().1);
+    assert_eq!(ErrorCode::UnsupportedMediaType, problem.code);
+}
+
+/// Checks if the request is rejected with 503 Service Unavailable and the
+/// `Retry-After` header when the database pool is exhausted.
+#[actix_web::test]
+async fn service_unavailable() {
+    let cfg = na::config::ServerConfig::new_leaked();
+    let manager = diesel::r2d2::ConnectionManager::<diesel::PgConnection>::new(&cfg.database.url);
+    let db_pool: na::DbPool = r2d2::Pool::builder()
+        .max_size(1)
+        .connection_timeout(std::time::Duration::from_millis(100))
+        .build(manager)
+        .unwrap();
+    let app = test::init_service(
+        actix_web::App::new()
+            .app_data(actix_web::web::Data::new(db_pool.clone()))
+            .app_data(actix_web::web::Data::new(cfg))
+            .configure(na::routes::configure(cfg)),
+    )
+    .await;
+
+    // Hold the only connection, so the handler cannot get one
+    let _conn = db_pool.get().unwrap();
+    let req = test::TestRequest::post()
+        .uri("/user")
+        .set_json(na::handlers::user::InputUser {
+            name: common::random_string(16),
+            email: common::random_email(),
+            password: common::random_string(16),
+        })
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(503, resp.status().as_u16());
+    assert_eq!("5", resp.headers().get(http::header::RETRY_AFTER).unwrap());
+    let problem = parse_problem(resp.into_parts().1);
+    assert_eq!(ErrorCode::ServiceUnavailable, problem.code);
+}
@@ -0,0 +1,131 @@
+mod common;
+
+use actix_web::{dev::ServiceResponse, http, test, web, App, HttpResponse};
+use na::errors::ProblemDetails;
+use na::middleware::request_id::{self, RequestId, RequestIdMiddleware};
+
+/// Returns the `X-Request-Id` response header value.
+fn response_request_id(resp: &ServiceResponse) -> String {
+    resp.headers()
+        .get("x-request-id")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+/// Checks if the request id is generated (in the W3C trace id format) when
+/// the client does not supply one.
+#[actix_web::test]
+async fn request_id_generated() {
+    let app = common::setup_server().await;
+
+    let mut request_ids = Vec::new();
+    for _ in 0..2 {
+        let req = test::TestRequest::get().uri("/openapi.json").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(200, resp.status().as_u16());
+        let request_id = response_request_id(&resp);
+        assert_eq!(32, request_id.len());
+        assert!(request_id
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c)));
+        request_ids.push(request_id);
+    }
+    assert_ne!(request_ids[0], request_ids[1]);
+}
+
+/// Checks if the request id supplied by the client (directly or as the trace
+/// id of the `traceparent` header) is echoed, and invalid ones are replaced.
+#[actix_web::test]
+async fn request_id_propagated() {
+    let app = common::setup_server().await;
+    let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+    let traceparent = format!("00-{trace_id}-00f067aa0ba902b7-01");
+
+    for (headers, expected) in [
+        (vec![("x-request-id", "support-42")], Some("support-42")),
+        (vec![("traceparent", traceparent.as_str())], Some(trace_id)),
+        (
+            vec![
+                ("x-request-id", "support-42"),
+                ("traceparent", traceparent.as_str()),
+            ],
+            Some("support-42"),
+        ),
+        (vec![("x-request-id", "with spaces")], None),
+        (vec![("x-request-id", &"a".repeat(129))], None),
+        (
+            vec![(
+                "traceparent",
+                "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            )],
+            None,
+        ),
+        (vec![("traceparent", "garbage")], None),
+    ] {
+        let mut req = test::TestRequest::get().uri("/openapi.json");
+        for header in &headers {
+            req = req.append_header(*header);
+        }
+        let resp = test::call_service(&app, req.to_request()).await;
+        let request_id = response_request_id(&resp);
+        match expected {
+            Some(expected) => assert_eq!(expected, request_id, "{headers:?}"),
+            None => assert_eq!(32, request_id.len(), "{headers:?}"),
+        }
+    }
+}
+
+/// Checks if error responses (built by handlers, middlewares and actix
+/// itself) carry the request id in the body.
+#[actix_web::test]
+async fn request_id_in_errors() {
+    let app = common::setup_server().await;
+
+    for (method, uri) in [
+        (http::Method::POST, "/auth/token"),
+        (http::Method::GET, "/user/me"),
+        (http::Method::GET, "/no/such/route"),
+        (http::Method::PUT, "/openapi.json"),
+    ] {
+        let req = test::TestRequest::default()
+            .method(method.clone())
+            .uri(uri)
+            .insert_header(("x-request-id", "support-42"))
+            .set_json(serde_json::json!({"email": "nobody@example.org", "password": "secret"}))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_client_error(), "{method} {uri}");
+        assert_eq!("support-42", response_request_id(&resp), "{method} {uri}");
+        let body = test::read_body(resp).await;
+        let problem: ProblemDetails = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            Some("support-42".to_string()),
+            problem.request_id,
+            "{method} {uri}"
+        );
+    }
+}
+
+/// Checks if the request id is available to the blocking work of the request
+/// (so its log lines carry the id as well).
+#[actix_web::test]
+async fn request_id_in_blocking_work() {
+    let app = test::init_service(App::new().wrap(RequestIdMiddleware).route(
+        "/",
+        web::get().to(|| async {
+            let request_id = request_id::block(RequestId::current).await.unwrap();
+            HttpResponse::Ok().body(request_id.map(|id| id.0).unwrap_or_default())
+        }),
+    ))
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/")
+        .insert_header(("x-request-id", "support-42"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(200, resp.status().as_u16());
+    assert_eq!(&b"support-42"[..], &test::read_body(resp).await[..]);
+}
@@ -7,7 +7,7 @@ use na::handlers::{auth::TokenCreateRequest, user::InputUser};
 #[actix_web::test]
 async fn create_token() {
     let app = common::setup_server().await;
-    let email = common::random_string(16);
+    let email = common::random_email();
     let password = common::random_string(16);
 
     let req = test::TestRequest::post()
@@ -37,7 +37,7 @@ async fn create_token() {
 #[actix_web::test]
 async fn create_token_invalid_password() {
     let app = common::setup_server().await;
-    let email = common::random_string(16);
+    let email = common::random_email();
 
     let req = test::TestRequest::post()
         .uri("/user")
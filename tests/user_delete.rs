@@ -0,0 +1,167 @@
+mod common;
+
+use actix_web::{http, test, web};
+use na::{config::ServerConfig, handlers::auth::TokenCreateRequest, jobs, models};
+use serial_test::serial;
+
+/// Checks if the authorized user can delete itself, and that both the issued
+/// token and the credentials stop working right away.
+#[actix_web::test]
+#[serial]
+async fn delete_me() {
+    let app = common::setup_server().await;
+    let email = common::random_email();
+    let password = common::random_string(16);
+
+    let _ = common::register_user(&app, &email, &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+
+    let req = test::TestRequest::delete()
+        .uri("/user/me")
+        .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(204, resp.status().as_u16());
+
+    let req = test::TestRequest::get()
+        .uri("/user/me")
+        .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(401, resp.status().as_u16());
+
+    let req = test::TestRequest::post()
+        .uri("/auth/token")
+        .set_json(TokenCreateRequest {
+            email: email.clone(),
+            password: password.clone(),
+        })
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(400, resp.status().as_u16());
+}
+
+/// Checks if service responds with 403 Forbidden when a non-admin user tries
+/// to delete another user.
+#[actix_web::test]
+#[serial]
+async fn delete_by_id_forbidden() {
+    let app = common::setup_server().await;
+    let email = common::random_email();
+    let password = common::random_string(16);
+
+    let _ = common::register_user(&app, &email, &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+    let other_user =
+        common::register_user(&app, &common::random_email(), &common::random_string(16)).await;
+
+    let req = test::TestRequest::delete()
+        .uri(format!("/user/{}", other_user.id).as_str())
+        .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(403, resp.status().as_u16());
+}
+
+/// Checks if an admin can delete and restore another user.
+#[actix_web::test]
+#[serial]
+async fn delete_and_restore_by_admin() {
+    let app = common::setup_server().await;
+    let db = web::Data::new(common::db_pool(ServerConfig::new_leaked()));
+    let email = common::random_email();
+    let password = common::random_string(16);
+
+    let admin = common::register_user(&app, &email, &password).await;
+    models::User::grant_role(admin.id, models::ROLE_ADMIN, db.clone()).unwrap();
+    let token = common::create_token(&app, &email, &password).await;
+    let other_user =
+        common::register_user(&app, &common::random_email(), &common::random_string(16)).await;
+
+    for (method, uri, expected_status) in [
+        (
+            http::Method::DELETE,
+            format!("/user/{}", other_user.id),
+            204,
+        ),
+        (http::Method::GET, format!("/user/{}", other_user.id), 404),
+        (
+            http::Method::DELETE,
+            format!("/user/{}", other_user.id),
+            404,
+        ),
+        (
+            http::Method::POST,
+            format!("/user/{}/restore", other_user.id),
+            200,
+        ),
+        (http::Method::GET, format!("/user/{}", other_user.id), 200),
+    ] {
+        let req = test::TestRequest::default()
+            .method(method)
+            .uri(uri.as_str())
+            .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(expected_status, resp.status().as_u16());
+    }
+}
+
+/// Checks if deleted users are purged after the grace period and can no
+/// longer be restored.
+#[actix_web::test]
+#[serial]
+async fn purge_deleted() {
+    let app = common::setup_server().await;
+    let db = web::Data::new(common::db_pool(ServerConfig::new_leaked()));
+    let email = common::random_email();
+    let password = common::random_string(16);
+
+    let admin = common::register_user(&app, &email, &password).await;
+    models::User::grant_role(admin.id, models::ROLE_ADMIN, db.clone()).unwrap();
+    let token = common::create_token(&app, &email, &password).await;
+    let other_user =
+        common::register_user(&app, &common::random_email(), &common::random_string(16)).await;
+    let cfg = ServerConfig::new_leaked();
+    let principal = models::IdempotencyKey::user_principal(other_user.id);
+    let key = common::random_string(32);
+    let claim = || {
+        models::IdempotencyKey::claim(
+            &principal,
+            &key,
+            "fingerprint",
+            cfg.idempotency.ttl(),
+            cfg.idempotency.lock_timeout(),
+            db.clone(),
+        )
+        .unwrap()
+    };
+    assert!(matches!(claim(), models::IdempotencyClaim::Claimed(_)));
+
+    let req = test::TestRequest::delete()
+        .uri(format!("/user/{}", other_user.id).as_str())
+        .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(204, resp.status().as_u16());
+
+    // nothing is purged within the grace period
+    let purged = jobs::purge::purge(db.clone(), chrono::Duration::hours(1))
+        .await
+        .unwrap();
+    assert_eq!(0, purged);
+
+    let purged = jobs::purge::purge(db.clone(), chrono::Duration::zero())
+        .await
+        .unwrap();
+    assert!(purged >= 1);
+    // The idempotency keys of the purged user are gone as well
+    assert!(matches!(claim(), models::IdempotencyClaim::Claimed(_)));
+
+    let req = test::TestRequest::post()
+        .uri(format!("/user/{}/restore", other_user.id).as_str())
+        .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(404, resp.status().as_u16());
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use actix_web::{http, test, web};
+use na::{config::ServerConfig, export, models};
+
+/// Checks if the authorized user can export its personal data, and that the
+/// archive contains a section per registered exporter.
+#[actix_web::test]
+async fn export_me() {
+    let app = common::setup_server().await;
+    let db = web::Data::new(common::db_pool(ServerConfig::new_leaked()));
+    let email = common::random_email();
+    let password = common::random_string(16);
+
+    let self_user = common::register_user(&app, &email, &password).await;
+    models::User::grant_role(self_user.id, models::ROLE_ADMIN, db.clone()).unwrap();
+    let token = common::create_token(&app, &email, &password).await;
+    let principal = models::IdempotencyKey::user_principal(self_user.id);
+    let key = common::random_string(32);
+    let cfg = ServerConfig::new_leaked();
+    let models::IdempotencyClaim::Claimed(lease) = models::IdempotencyKey::claim(
+        &principal,
+        &key,
+        "fingerprint",
+        cfg.idempotency.ttl(),
+        cfg.idempotency.lock_timeout(),
+        db.clone(),
+    )
+    .unwrap() else {
+        panic!("key not claimed");
+    };
+    assert!(models::IdempotencyKey::complete(
+        &principal,
+        &key,
+        lease,
+        201,
+        "[]",
+        b"{}",
+        db.clone()
+    )
+    .unwrap());
+
+    let req = test::TestRequest::get()
+        .uri("/user/me/export")
+        .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(200, resp.status().as_u16());
+    assert!(resp
+        .headers()
+        .get(http::header::CONTENT_DISPOSITION)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let body = test::read_body(resp).await;
+    let archive: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(self_user.id, archive["user_id"]);
+    for exporter in export::EXPORTERS {
+        assert!(archive["data"].get(exporter.section()).is_some());
+    }
+    assert_eq!(email, archive["data"]["profile"]["email"]);
+    assert!(archive["data"]["profile"].get("hashed_password").is_none());
+    assert_eq!(
+        models::ROLE_ADMIN,
+        archive["data"]["roles"][0]["role"].as_str().unwrap()
+    );
+    let keys = &archive["data"]["idempotency_keys"];
+    assert_eq!(key, keys[0]["idempotency_key"].as_str().unwrap());
+    assert_eq!(201, keys[0]["status"]);
+    assert!(keys[0].get("fingerprint").is_none());
+}
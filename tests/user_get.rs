@@ -0,0 +1,87 @@
+mod common;
+
+use actix_web::{http, test};
+use na::handlers::OutputUser;
+
+/// Checks if the authorized user can retrieve its own profile.
+#[actix_web::test]
+async fn get_me() {
+    let app = common::setup_server().await;
+    let email = common::random_email();
+    let password = common::random_string(16);
+
+    let self_user = common::register_user(&app, &email, &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+
+    let req = test::TestRequest::get()
+        .uri("/user/me")
+        .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(200, resp.status().as_u16());
+    let body = test::read_body(resp).await;
+    let user: OutputUser = serde_json::from_slice(&body).unwrap();
+    assert_eq!(self_user.id, user.id);
+    assert_eq!(email, user.email);
+}
+
+/// Checks if service responds with 401 Unauthorized when no token is provided.
+#[actix_web::test]
+async fn get_me_unauthorized() {
+    let app = common::setup_server().await;
+
+    let req = test::TestRequest::get().uri("/user/me").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(401, resp.status().as_u16());
+}
+
+/// Checks if a single user can be retrieved by id.
+#[actix_web::test]
+async fn get_by_id() {
+    let app = common::setup_server().await;
+    let email = common::random_email();
+    let password = common::random_string(16);
+
+    let _ = common::register_user(&app, &email, &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+    let other_user =
+        common::register_user(&app, &common::random_email(), &common::random_string(16)).await;
+
+    let req = test::TestRequest::get()
+        .uri(format!("/user/{}", other_user.id).as_str())
+        .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(200, resp.status().as_u16());
+    let body = test::read_body(resp).await;
+    let user: OutputUser = serde_json::from_slice(&body).unwrap();
+    assert_eq!(other_user.id, user.id);
+    // other users' emails are masked
+    assert_eq!(
+        format!("{}***@example.org", &other_user.email[..1]),
+        user.email
+    );
+    assert_eq!(None, user.listed);
+}
+
+/// Checks if service responds with 404 Not Found for a non-existent user id.
+#[actix_web::test]
+async fn get_by_id_not_found() {
+    let app = common::setup_server().await;
+    let email = common::random_email();
+    let password = common::random_string(16);
+
+    let _ = common::register_user(&app, &email, &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+
+    let req = test::TestRequest::get()
+        .uri(format!("/user/{}", i32::MAX).as_str())
+        .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(404, resp.status().as_u16());
+    let body = test::read_body(resp).await;
+    let payload: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!("not_found", payload["code"]);
+    assert_eq!("Resource not found", payload["title"]);
+}
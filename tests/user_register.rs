@@ -1,7 +1,10 @@
 mod common;
 
 use actix_web::test;
-use na::handlers::user::InputUser;
+use na::{
+    errors::{ErrorCode, ProblemDetails},
+    handlers::user::InputUser,
+};
 
 /// Checks if user can be registered
 #[actix_web::test]
@@ -12,7 +15,7 @@ async fn register_user() {
         .uri("/user")
         .set_json(InputUser {
             name: common::random_string(16),
-            email: common::random_string(16),
+            email: common::random_email(),
             password: common::random_string(16),
         })
         .to_request();
@@ -22,11 +25,11 @@ async fn register_user() {
 }
 
 /// Checks if service responds with 409 Conflict when registering the user with
-/// the duplicate email.
+/// the duplicate email, naming the conflicting field.
 #[actix_web::test]
 async fn register_user_duplicate() {
     let app = common::setup_server().await;
-    let email = common::random_string(16);
+    let email = common::random_email();
 
     let req = test::TestRequest::post()
         .uri("/user")
@@ -51,4 +54,49 @@ async fn register_user_duplicate() {
     let resp = test::call_service(&app, req).await;
 
     assert_eq!(409, resp.status().as_u16());
+    let body = test::read_body(resp).await;
+    let payload: ProblemDetails = serde_json::from_slice(&body).unwrap();
+    assert_eq!(ErrorCode::Conflict, payload.code);
+    let errors: Vec<(&str, &str)> = payload
+        .errors
+        .iter()
+        .map(|e| (e.field.as_str(), e.code.as_str()))
+        .collect();
+    assert_eq!(vec![("email", "unique")], errors);
+}
+
+/// Checks if service responds with 422 Unprocessable Entity listing every
+/// failing field when registering the user with invalid data.
+#[actix_web::test]
+async fn register_user_invalid() {
+    let app = common::setup_server().await;
+
+    let req = test::TestRequest::post()
+        .uri("/user")
+        .set_json(InputUser {
+            name: "John\u{202E}".to_string(),
+            email: "john.example.org".to_string(),
+            password: "secr3t".to_string(),
+        })
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(422, resp.status().as_u16());
+
+    let body = test::read_body(resp).await;
+    let payload: ProblemDetails = serde_json::from_slice(&body).unwrap();
+    assert_eq!(ErrorCode::ValidationFailed, payload.code);
+    let errors: Vec<(&str, &str)> = payload
+        .errors
+        .iter()
+        .map(|e| (e.field.as_str(), e.code.as_str()))
+        .collect();
+    assert_eq!(
+        vec![
+            ("email", "email"),
+            ("name", "invalid_characters"),
+            ("password", "length")
+        ],
+        errors
+    );
+    assert!(payload.errors[2].params.get("value").is_none());
 }
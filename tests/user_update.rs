@@ -0,0 +1,138 @@
+mod common;
+
+use actix_web::{
+    dev::ServiceResponse,
+    http::{self, header},
+    test,
+};
+use na::handlers::OutputUser;
+
+/// Retrieves the authorized user ETag value via GET /user/me.
+async fn get_me_etag(
+    app: &impl actix_web::dev::Service<
+        actix_http::Request,
+        Response = ServiceResponse,
+        Error = actix_web::Error,
+    >,
+    token: &str,
+) -> String {
+    let req = test::TestRequest::get()
+        .uri("/user/me")
+        .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(app, req).await;
+    assert_eq!(200, resp.status().as_u16());
+    resp.headers()
+        .get(header::ETAG)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+/// Checks if the user profile can be updated.
+#[actix_web::test]
+async fn update_me() {
+    let app = common::setup_server().await;
+    let email = common::random_email();
+    let password = common::random_string(16);
+
+    let _ = common::register_user(&app, &email, &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+    let etag = get_me_etag(&app, &token).await;
+
+    let new_name = common::random_string(16);
+    let req = test::TestRequest::patch()
+        .uri("/user/me")
+        .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+        .append_header((header::IF_MATCH, etag.clone()))
+        .append_header((header::CONTENT_TYPE, "application/merge-patch+json"))
+        .set_payload(serde_json::json!({ "name": new_name }).to_string())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(200, resp.status().as_u16());
+    let new_etag = resp
+        .headers()
+        .get(header::ETAG)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert_ne!(etag, new_etag);
+    let body = test::read_body(resp).await;
+    let user: OutputUser = serde_json::from_slice(&body).unwrap();
+    assert_eq!(new_name, user.name);
+    assert_eq!(new_etag, get_me_etag(&app, &token).await);
+}
+
+/// Checks if service responds with 412 Precondition Failed when the profile
+/// was modified after the ETag was retrieved.
+#[actix_web::test]
+async fn update_me_stale_etag() {
+    let app = common::setup_server().await;
+    let email = common::random_email();
+    let password = common::random_string(16);
+
+    let _ = common::register_user(&app, &email, &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+    let etag = get_me_etag(&app, &token).await;
+
+    for expected_status in [200, 412] {
+        let req = test::TestRequest::patch()
+            .uri("/user/me")
+            .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+            .append_header((header::IF_MATCH, etag.clone()))
+            .set_json(serde_json::json!({ "name": common::random_string(16) }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(expected_status, resp.status().as_u16());
+    }
+}
+
+/// Checks if service responds with 428 Precondition Required when no
+/// `If-Match` header is provided.
+#[actix_web::test]
+async fn update_me_no_if_match() {
+    let app = common::setup_server().await;
+    let email = common::random_email();
+    let password = common::random_string(16);
+
+    let _ = common::register_user(&app, &email, &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+
+    let req = test::TestRequest::patch()
+        .uri("/user/me")
+        .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+        .set_json(serde_json::json!({ "name": common::random_string(16) }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(428, resp.status().as_u16());
+}
+
+/// Checks if invalid patches are rejected.
+#[actix_web::test]
+async fn update_me_invalid() {
+    let app = common::setup_server().await;
+    let email = common::random_email();
+    let password = common::random_string(16);
+
+    let _ = common::register_user(&app, &email, &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+    let etag = get_me_etag(&app, &token).await;
+
+    for (patch, expected_status) in [
+        (serde_json::json!({ "name": "   " }), 422),
+        (serde_json::json!({ "name": null }), 422),
+        (serde_json::json!({ "name": "x".repeat(129) }), 422),
+        (serde_json::json!({ "email": "john@example.org" }), 400),
+    ] {
+        let req = test::TestRequest::patch()
+            .uri("/user/me")
+            .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+            .append_header((header::IF_MATCH, etag.clone()))
+            .set_json(patch)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(expected_status, resp.status().as_u16());
+    }
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use actix_web::{http, test, web};
+use na::config::ServerConfig;
+use na::models;
+
+/// Checks if admins can export the filtered users as NDJSON and CSV.
+#[actix_web::test]
+async fn export_users() {
+    let app = common::setup_server().await;
+    let db = web::Data::new(common::db_pool(ServerConfig::new_leaked()));
+    let email = common::random_email();
+    let password = common::random_string(16);
+    let admin = common::register_user(&app, &email, &password).await;
+    models::User::grant_role(admin.id, models::ROLE_ADMIN, db.clone()).unwrap();
+    let token = common::create_token(&app, &email, &password).await;
+
+    let role = common::random_string(16);
+    let mut users = vec![];
+    for _ in 1..=3 {
+        let user =
+            common::register_user(&app, &common::random_email(), &common::random_string(16)).await;
+        models::User::grant_role(user.id, &role, db.clone()).unwrap();
+        users.push(user);
+    }
+
+    // NDJSON is the default format
+    for accept in [None, Some("application/x-ndjson"), Some("*/*")] {
+        let mut req = test::TestRequest::get()
+            .uri(&format!("/users/export?role={}", role))
+            .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)));
+        if let Some(accept) = accept {
+            req = req.append_header((http::header::ACCEPT, accept));
+        }
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(200, resp.status().as_u16());
+        assert_eq!(
+            "application/x-ndjson",
+            resp.headers().get(http::header::CONTENT_TYPE).unwrap()
+        );
+        let body = test::read_body(resp).await;
+        let lines: Vec<serde_json::Value> = std::str::from_utf8(&body)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(3, lines.len());
+        for (line, user) in lines.iter().zip(&users) {
+            assert_eq!(user.id, line["id"]);
+            assert_eq!(user.email, line["email"]);
+            assert!(line.get("hashed_password").is_none());
+        }
+    }
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/users/export?role={}", role))
+        .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+        .append_header((http::header::ACCEPT, "application/json;q=0.9, text/csv"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(200, resp.status().as_u16());
+    let body = test::read_body(resp).await;
+    let csv = std::str::from_utf8(&body).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!("id,email,name,created_at,listed,deleted_at", lines[0]);
+    assert_eq!(4, lines.len());
+    assert!(lines[1].starts_with(&format!(
+        "{},{},{},",
+        users[0].id, users[0].email, users[0].name
+    )));
+}
+
+/// Checks if non-admins cannot export users, and unsupported formats are
+/// rejected.
+#[actix_web::test]
+async fn export_users_rejected() {
+    let app = common::setup_server().await;
+    let db = web::Data::new(common::db_pool(ServerConfig::new_leaked()));
+    let email = common::random_email();
+    let password = common::random_string(16);
+    let user = common::register_user(&app, &email, &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+
+    let req = test::TestRequest::get()
+        .uri("/users/export")
+        .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(403, resp.status().as_u16());
+
+    models::User::grant_role(user.id, models::ROLE_ADMIN, db).unwrap();
+    for (query, accept, expected_status) in [
+        ("", "application/xml", 406),
+        ("", "application/json", 406),
+        ("status=unknown", "text/csv", 400),
+        ("name_prefix=", "text/csv", 422),
+    ] {
+        let req = test::TestRequest::get()
+            .uri(&format!("/users/export?{}", query))
+            .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+            .append_header((http::header::ACCEPT, accept))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(expected_status, resp.status().as_u16(), "{query} {accept}");
+    }
+}
@@ -0,0 +1,250 @@
+mod common;
+
+use actix_web::{dev::ServiceResponse, http, test, web};
+use argon2::password_hash::{rand_core::OsRng, PasswordHasher, SaltString};
+use na::config::ServerConfig;
+use na::handlers::users_import::{ImportResult, ImportStatus};
+use na::models;
+
+/// Imports users from the NDJSON body, returning the response status and the
+/// parsed results (on success).
+async fn import(
+    app: &impl actix_web::dev::Service<
+        actix_http::Request,
+        Response = ServiceResponse,
+        Error = actix_web::Error,
+    >,
+    token: &str,
+    query: &str,
+    body: String,
+) -> (u16, Vec<ImportResult>) {
+    let req = test::TestRequest::post()
+        .uri(&format!("/users/import?{query}"))
+        .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+        .append_header((http::header::CONTENT_TYPE, "application/x-ndjson"))
+        .set_payload(body)
+        .to_request();
+    let resp = test::call_service(app, req).await;
+    let status = resp.status().as_u16();
+    let body = test::read_body(resp).await;
+    let results = match status {
+        200 => std::str::from_utf8(&body)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect(),
+        _ => vec![],
+    };
+    (status, results)
+}
+
+/// Registers a new admin, returning the token.
+async fn admin_token(
+    app: &impl actix_web::dev::Service<
+        actix_http::Request,
+        Response = ServiceResponse,
+        Error = actix_web::Error,
+    >,
+) -> String {
+    let db = web::Data::new(common::db_pool(ServerConfig::new_leaked()));
+    let email = common::random_email();
+    let password = common::random_string(16);
+    let admin = common::register_user(app, &email, &password).await;
+    models::User::grant_role(admin.id, models::ROLE_ADMIN, db).unwrap();
+    common::create_token(app, &email, &password).await
+}
+
+/// Checks if records are created, conflicting and invalid ones are reported,
+/// and imported users can log in with both plaintext and pre-hashed
+/// passwords.
+#[actix_web::test]
+async fn import_users() {
+    let app = common::setup_server().await;
+    let token = admin_token(&app).await;
+
+    let existing_email = common::random_email();
+    let _ = common::register_user(&app, &existing_email, &common::random_string(16)).await;
+    let plain_email = common::random_email();
+    let plain_password = common::random_string(16);
+    let hashed_email = common::random_email();
+    let hashed_password = common::random_string(16);
+    let hash = argon2::Argon2::default()
+        .hash_password(
+            hashed_password.as_bytes(),
+            &SaltString::generate(&mut OsRng),
+        )
+        .unwrap()
+        .to_string();
+
+    let lines = [
+        serde_json::json!({"email": plain_email, "name": "Plain", "password": plain_password})
+            .to_string(),
+        serde_json::json!({"email": hashed_email, "name": "Hashed", "password_hash": hash})
+            .to_string(),
+        String::new(),
+        serde_json::json!({"email": existing_email, "name": "Existing", "password": plain_password})
+            .to_string(),
+        serde_json::json!({"email": plain_email, "name": "Duplicate", "password": plain_password})
+            .to_string(),
+        serde_json::json!({"email": "not an email", "name": "", "password": plain_password})
+            .to_string(),
+        serde_json::json!({"email": common::random_email(), "name": "Both", "password": plain_password, "password_hash": hash})
+            .to_string(),
+        serde_json::json!({"email": common::random_email(), "name": "Weak", "password_hash": "$1$saltsalt$hash"})
+            .to_string(),
+        "{\"email\": \"truncated".to_string(),
+        format!("{{\"name\": \"{}\"}}", "x".repeat(10_000)),
+        serde_json::json!({"email": common::random_email(), "name": "Last", "password": plain_password})
+            .to_string(),
+    ];
+    let (status, results) = import(&app, &token, "", lines.join("\n")).await;
+    assert_eq!(200, status);
+
+    let statuses: Vec<(usize, ImportStatus)> = results
+        .iter()
+        .map(|result| (result.line, result.status))
+        .collect();
+    assert_eq!(
+        vec![
+            (1, ImportStatus::Created),
+            (2, ImportStatus::Created),
+            (4, ImportStatus::Conflict),
+            (5, ImportStatus::Conflict),
+            (6, ImportStatus::Invalid),
+            (7, ImportStatus::Invalid),
+            (8, ImportStatus::Invalid),
+            (9, ImportStatus::Invalid),
+            (10, ImportStatus::Invalid),
+            (11, ImportStatus::Created),
+        ],
+        statuses
+    );
+    assert!(results[0].id.is_some());
+    assert!(results[2].id.is_none());
+    let codes = |line: usize| -> Vec<(String, String)> {
+        results
+            .iter()
+            .find(|result| result.line == line)
+            .unwrap()
+            .errors
+            .iter()
+            .map(|error| (error.field.clone(), error.code.clone()))
+            .collect()
+    };
+    assert_eq!(
+        vec![
+            ("email".to_string(), "email".to_string()),
+            ("name".to_string(), "length".to_string()),
+            ("name".to_string(), "blank".to_string()),
+        ],
+        codes(6)
+    );
+    assert_eq!(
+        vec![("__all__".to_string(), "conflicting_passwords".to_string())],
+        codes(7)
+    );
+    assert_eq!(
+        vec![("password_hash".to_string(), "unsupported_hash".to_string())],
+        codes(8)
+    );
+    assert_eq!(
+        vec![("line".to_string(), "malformed".to_string())],
+        codes(9)
+    );
+    assert_eq!(
+        vec![("line".to_string(), "too_long".to_string())],
+        codes(10)
+    );
+
+    let _ = common::create_token(&app, &plain_email, &plain_password).await;
+    let _ = common::create_token(&app, &hashed_email, &hashed_password).await;
+}
+
+/// Checks if the dry run reports the results without creating users, and
+/// non-admins cannot import users.
+#[actix_web::test]
+async fn import_users_dry_run() {
+    let app = common::setup_server().await;
+    let token = admin_token(&app).await;
+
+    let email = common::random_email();
+    let password = common::random_string(16);
+    let body = serde_json::json!({"email": email, "name": "Dry", "password": password}).to_string();
+    let (status, results) = import(&app, &token, "dry_run=true", body.clone()).await;
+    assert_eq!(200, status);
+    assert_eq!(1, results.len());
+    assert_eq!(ImportStatus::Created, results[0].status);
+    assert!(results[0].id.is_none());
+
+    // Nothing was created, so the import succeeds for real
+    let (status, results) = import(&app, &token, "", body.clone()).await;
+    assert_eq!(200, status);
+    assert_eq!(ImportStatus::Created, results[0].status);
+    let (status, results) = import(&app, &token, "dry_run=true", body.clone()).await;
+    assert_eq!(200, status);
+    assert_eq!(ImportStatus::Conflict, results[0].status);
+
+    let user_token = common::create_token(&app, &email, &password).await;
+    let (status, _) = import(&app, &user_token, "", body).await;
+    assert_eq!(403, status);
+}
+
+/// Checks if the dry run reports the emails repeated across the batches as
+/// conflicting, as the real import does.
+#[actix_web::test]
+async fn import_users_dry_run_across_batches() {
+    let app = common::setup_server().await;
+    let token = admin_token(&app).await;
+
+    let password = common::random_string(16);
+    let record = |email: &str| {
+        serde_json::json!({"email": email, "name": "Dry", "password": password}).to_string()
+    };
+    let email = common::random_email();
+    // More records than fit in a single batch
+    let mut lines = vec![record(&email)];
+    lines.extend((0..600).map(|_| record(&common::random_email())));
+    lines.push(record(&email));
+
+    let (status, results) = import(&app, &token, "dry_run=true", lines.join("\n")).await;
+    assert_eq!(200, status);
+    assert_eq!(lines.len(), results.len());
+    assert_eq!(ImportStatus::Created, results[0].status);
+    assert_eq!(ImportStatus::Conflict, results[lines.len() - 1].status);
+    assert!(results[1..lines.len() - 1]
+        .iter()
+        .all(|result| result.status == ImportStatus::Created));
+}
+
+/// Checks if the password hashes with the cost parameters exceeding the
+/// configured maximums are rejected.
+#[actix_web::test]
+async fn import_costly_hashes_rejected() {
+    let app = common::setup_server().await;
+    let token = admin_token(&app).await;
+
+    let salt = "c2FsdHNhbHRzYWx0c2FsdA";
+    let hash = "aGFzaGhhc2hoYXNoaGFzaGhhc2hoYXNoaGFzaGhhc2g";
+    let lines: Vec<String> = [
+        format!("$argon2id$v=19$m=4194304,t=2,p=1${salt}${hash}"),
+        format!("$argon2id$v=19$m=19456,t=1000,p=1${salt}${hash}"),
+        format!("$scrypt$ln=24,r=8,p=1${salt}${hash}"),
+        format!("$pbkdf2-sha256$i=100000000,l=32${salt}${hash}"),
+        format!("$2b$20${}", "a".repeat(53)),
+    ]
+    .iter()
+    .map(|password_hash| {
+        serde_json::json!({"email": common::random_email(), "name": "Costly", "password_hash": password_hash})
+            .to_string()
+    })
+    .collect();
+
+    let (status, results) = import(&app, &token, "dry_run=true", lines.join("\n")).await;
+    assert_eq!(200, status);
+    assert_eq!(lines.len(), results.len());
+    for result in results {
+        assert_eq!(ImportStatus::Invalid, result.status, "{}", result.line);
+        assert_eq!("password_hash", result.errors[0].field);
+        assert_eq!("hash_too_costly", result.errors[0].code, "{}", result.line);
+    }
+}
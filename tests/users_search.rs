@@ -0,0 +1,240 @@
+mod common;
+
+use actix_web::{dev::ServiceResponse, http, test, web};
+use na::config::ServerConfig;
+use na::handlers::{user::InputUser, users::ListResponse};
+use na::models;
+
+/// Registers a new user with the given name and email.
+async fn create_user(
+    app: &impl actix_web::dev::Service<
+        actix_http::Request,
+        Response = ServiceResponse,
+        Error = actix_web::Error,
+    >,
+    name: &str,
+    email: &str,
+) -> i32 {
+    let req = test::TestRequest::post()
+        .uri("/user")
+        .set_json(InputUser {
+            name: name.to_string(),
+            email: email.to_string(),
+            password: common::random_string(16),
+        })
+        .to_request();
+    let resp = test::call_service(app, req).await;
+    assert_eq!(201, resp.status().as_u16());
+    let body = test::read_body(resp).await;
+    let user: na::handlers::OutputUser = serde_json::from_slice(&body).unwrap();
+    user.id
+}
+
+/// Searches users with the given query string, returning the response status
+/// and the parsed body (on success).
+async fn search(
+    app: &impl actix_web::dev::Service<
+        actix_http::Request,
+        Response = ServiceResponse,
+        Error = actix_web::Error,
+    >,
+    token: &str,
+    query: &str,
+) -> (u16, Option<ListResponse>) {
+    let req = test::TestRequest::get()
+        .uri(&format!("/users/search?{query}"))
+        .append_header((http::header::AUTHORIZATION, format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(app, req).await;
+    let status = resp.status().as_u16();
+    let body = test::read_body(resp).await;
+    (status, serde_json::from_slice(&body).ok())
+}
+
+/// Checks if users can be found by a name, an email part and a misspelled
+/// email (by admins), and that deleted users are not found.
+#[actix_web::test]
+async fn search_users() {
+    let app = common::setup_server().await;
+    let db = web::Data::new(common::db_pool(ServerConfig::new_leaked()));
+    let email = common::random_email();
+    let password = common::random_string(16);
+    let admin = common::register_user(&app, &email, &password).await;
+    models::User::grant_role(admin.id, models::ROLE_ADMIN, db).unwrap();
+    let token = common::create_token(&app, &email, &password).await;
+
+    let tag = common::random_string(12).to_lowercase();
+    let user_email = format!("{tag}@example.org");
+    let user_id = create_user(&app, &format!("Bartholomew {tag}"), &user_email).await;
+    let misspelled = format!("{}x{}", &user_email[..4], &user_email[5..]);
+
+    for q in [
+        tag.to_uppercase(),
+        format!("barthol {}", &tag[..6]),
+        user_email.clone(),
+        misspelled,
+    ] {
+        let (status, page) = search(&app, &token, &format!("q={}", urlencode(&q))).await;
+        assert_eq!(200, status, "q={q}");
+        let page = page.unwrap();
+        assert_eq!(
+            Some(user_id),
+            page.users.first().map(|user| user.id),
+            "q={q}"
+        );
+    }
+
+    // Deleted users are not found
+    let deleted_email = format!("{tag}.deleted@example.org");
+    let deleted = common::register_user(&app, &deleted_email, &password).await;
+    let deleted_token = common::create_token(&app, &deleted_email, &password).await;
+    let req = test::TestRequest::delete()
+        .uri("/user/me")
+        .append_header((
+            http::header::AUTHORIZATION,
+            format!("Bearer {}", deleted_token),
+        ))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(204, resp.status().as_u16());
+
+    let (status, page) = search(&app, &token, &format!("q={tag}")).await;
+    assert_eq!(200, status);
+    let ids: Vec<i32> = page.unwrap().users.iter().map(|user| user.id).collect();
+    assert_eq!(vec![user_id], ids);
+    assert!(!ids.contains(&deleted.id));
+}
+
+/// Checks if non-admin callers cannot match users by masked emails, and do
+/// not find unlisted users.
+#[actix_web::test]
+async fn search_users_private() {
+    let app = common::setup_server().await;
+    let db = web::Data::new(common::db_pool(ServerConfig::new_leaked()));
+    let email = common::random_email();
+    let password = common::random_string(16);
+    let _ = common::register_user(&app, &email, &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+
+    let tag = common::random_string(12).to_lowercase();
+    let user_email = format!("{tag}@example.org");
+    let user_id = create_user(&app, "Private Person", &user_email).await;
+
+    let (status, page) = search(&app, &token, &format!("q={tag}")).await;
+    assert_eq!(200, status);
+    assert!(page.unwrap().users.is_empty());
+
+    let name = format!("Unlisted {tag}");
+    let unlisted_id = create_user(&app, &name, &common::random_email()).await;
+    let _ = diesel::RunQueryDsl::execute(
+        diesel::sql_query("UPDATE users SET listed = false WHERE id = $1")
+            .bind::<diesel::sql_types::Integer, _>(unlisted_id),
+        &mut db.get().unwrap(),
+    )
+    .unwrap();
+    let (status, page) = search(&app, &token, &format!("q={tag}")).await;
+    assert_eq!(200, status);
+    assert!(page.unwrap().users.is_empty());
+
+    let admin_email = common::random_email();
+    let admin = common::register_user(&app, &admin_email, &password).await;
+    models::User::grant_role(admin.id, models::ROLE_ADMIN, db).unwrap();
+    let admin_token = common::create_token(&app, &admin_email, &password).await;
+    let (status, page) = search(&app, &admin_token, &format!("q={tag}")).await;
+    assert_eq!(200, status);
+    let mut ids: Vec<i32> = page.unwrap().users.iter().map(|user| user.id).collect();
+    ids.sort();
+    assert_eq!(vec![user_id, unlisted_id], ids);
+}
+
+/// Checks if search results can be paged through in both directions.
+#[actix_web::test]
+async fn search_users_pages() {
+    let app = common::setup_server().await;
+    let email = common::random_email();
+    let password = common::random_string(16);
+    let _ = common::register_user(&app, &email, &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+
+    let tag = common::random_string(12).to_lowercase();
+    let mut ids = vec![];
+    for i in 0..5 {
+        let email = format!("{tag}{i}@example.org");
+        ids.push(create_user(&app, &format!("Member {tag}"), &email).await);
+    }
+
+    let mut found = vec![];
+    let mut query = format!("q={tag}&limit=2");
+    let mut pages = vec![];
+    loop {
+        let (status, page) = search(&app, &token, &query).await;
+        assert_eq!(200, status);
+        let page = page.unwrap();
+        found.extend(page.users.iter().map(|user| user.id));
+        pages.push(page.users.iter().map(|user| user.id).collect::<Vec<_>>());
+        match page.next_cursor {
+            Some(cursor) => query = format!("q={tag}&limit=2&after={cursor}"),
+            None => break,
+        }
+        if pages.len() == 2 {
+            // Page backwards from the second page
+            let prev = page.prev_cursor.unwrap();
+            let (status, prev_page) =
+                search(&app, &token, &format!("q={tag}&limit=2&before={prev}")).await;
+            assert_eq!(200, status);
+            let prev_ids: Vec<i32> = prev_page
+                .unwrap()
+                .users
+                .iter()
+                .map(|user| user.id)
+                .collect();
+            assert_eq!(pages[0], prev_ids);
+
+            // Cursors are bound to the query
+            let cursor = query.rsplit_once('=').unwrap().1.to_string();
+            let (status, _) = search(&app, &token, &format!("q=x{tag}&after={cursor}")).await;
+            assert_eq!(422, status);
+        }
+    }
+    found.sort();
+    assert_eq!(ids, found);
+    assert_eq!(3, pages.len());
+
+    let (status, page) = search(&app, &token, &format!("q={tag}&include_total=true")).await;
+    assert_eq!(200, status);
+    assert_eq!(5, page.unwrap().total.unwrap().count);
+}
+
+/// Checks if invalid search requests are rejected.
+#[actix_web::test]
+async fn search_users_invalid() {
+    let app = common::setup_server().await;
+    let email = common::random_email();
+    let password = common::random_string(16);
+    let _ = common::register_user(&app, &email, &password).await;
+    let token = common::create_token(&app, &email, &password).await;
+
+    for (query, expected_status) in [
+        ("", 400),
+        ("q=", 422),
+        ("q=%20%20", 422),
+        ("q=john&limit=0", 422),
+        ("q=john&limit=101", 422),
+        ("q=john&after=garbage", 422),
+        ("q=john&after=a&before=b", 422),
+    ] {
+        let (status, _) = search(&app, &token, query).await;
+        assert_eq!(expected_status, status, "{query}");
+    }
+
+    let req = test::TestRequest::get()
+        .uri("/users/search?q=john")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(401, resp.status().as_u16());
+}
+
+/// Percent-encodes the query string value.
+fn urlencode(value: &str) -> String {
+    serde_urlencoded::to_string([("", value)]).unwrap()[1..].to_string()
+}